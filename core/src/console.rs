@@ -0,0 +1,500 @@
+//! Line-based serial console to inspect and control the power feed from a PC.
+//!
+//! Every command is a single line (terminated by either `\r` or `\n`). Every response ends with a
+//! line starting with either `ok` (followed by the requested value, if any) or `err` (followed by
+//! the reason). Commands:
+//!
+//! * `help` -- list commands;
+//! * `settings` -- list all settings, one `name=value` per line;
+//! * `get NAME`, `set NAME VALUE` -- read or write a setting;
+//! * `pos` -- current stepper position, in steps;
+//! * `state` -- current stepper state;
+//! * `rpm` -- current spindle speed;
+//! * `error` -- last thread cutting error, in degrees;
+//! * `move POS` -- move stepper to the given position, in steps;
+//! * `stop` -- stop the stepper.
+//!
+//! Lines starting with a G-code word (like `G1 X1.5 F10`) are queued for execution by the
+//! "G-code" operation, see [`crate::gcode`]. Response `err busy` means the queue is full and the
+//! line should be sent again later.
+use crate::driver::StepperDriver;
+use crate::gcode::{self, Block, Program};
+use crate::hal::SpindleSensor;
+use crate::settings::{self, RawSetting, SettingsStorage};
+use crate::stepper::{Direction, State, Stepper, StepperError};
+use core::fmt::{self, Write};
+use rtic_core::Mutex;
+
+/// Longest command line accepted.
+const LINE_LENGTH: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    Help,
+    Settings,
+    Get(RawSetting),
+    Set(RawSetting, i32),
+    Position,
+    State,
+    Rpm,
+    Error,
+    Move(i32),
+    Stop,
+    Gcode(Block),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// Command line is longer than `LINE_LENGTH`
+    TooLong,
+    UnknownCommand,
+    UnknownSetting,
+    /// Argument is missing, not a number or there are too many arguments
+    InvalidArgument,
+    Stepper(StepperError),
+    Gcode(gcode::Error),
+    Storage,
+    /// Failed to write the response
+    Output,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooLong => f.write_str("line too long"),
+            Error::UnknownCommand => f.write_str("unknown command"),
+            Error::UnknownSetting => f.write_str("unknown setting"),
+            Error::InvalidArgument => f.write_str("invalid argument"),
+            Error::Stepper(StepperError::NotStopped) => f.write_str("not stopped"),
+            Error::Stepper(StepperError::StepgenError(err)) => write!(f, "stepgen {:?}", err),
+            Error::Stepper(StepperError::HardLimit) => f.write_str("hard limit"),
+            Error::Gcode(err) => write!(f, "{}", err),
+            Error::Storage => f.write_str("storage failure"),
+            Error::Output => f.write_str("output failure"),
+        }
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_err: fmt::Error) -> Self {
+        Error::Output
+    }
+}
+
+impl From<StepperError> for Error {
+    fn from(err: StepperError) -> Self {
+        Error::Stepper(err)
+    }
+}
+
+/// Parse the command line.
+pub fn parse(line: &str) -> Result<Command, Error> {
+    if is_gcode(line) {
+        return gcode::parse(line).map(Command::Gcode).map_err(Error::Gcode);
+    }
+    let mut words = line.split_ascii_whitespace();
+    let command = words.next().unwrap_or("");
+    let command = match command {
+        "help" => Command::Help,
+        "settings" => Command::Settings,
+        "get" => Command::Get(setting(words.next())?),
+        "set" => {
+            let setting = setting(words.next())?;
+            let value = number(words.next())?;
+            let (min, max) = setting.range();
+            if !(min..=max).contains(&value) {
+                return Err(Error::InvalidArgument);
+            }
+            Command::Set(setting, value)
+        }
+        "pos" => Command::Position,
+        "state" => Command::State,
+        "rpm" => Command::Rpm,
+        "error" => Command::Error,
+        "move" => Command::Move(number(words.next())?),
+        "stop" => Command::Stop,
+        _ => return Err(Error::UnknownCommand),
+    };
+    match words.next() {
+        Some(_) => Err(Error::InvalidArgument),
+        None => Ok(command),
+    }
+}
+
+/// G-code lines start with a letter followed by a number, or with a comment.
+fn is_gcode(line: &str) -> bool {
+    let mut chars = line.trim_ascii_start().chars();
+    match chars.next() {
+        Some('(' | ';') => true,
+        Some(c) if c.is_ascii_alphabetic() => chars
+            .find(|c| *c != ' ')
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, '.' | '+' | '-')),
+        _ => false,
+    }
+}
+
+fn setting(name: Option<&str>) -> Result<RawSetting, Error> {
+    let name = name.ok_or(Error::InvalidArgument)?;
+    settings::ALL
+        .iter()
+        .find(|setting| setting.name() == name)
+        .copied()
+        .ok_or(Error::UnknownSetting)
+}
+
+fn number<T: core::str::FromStr>(word: Option<&str>) -> Result<T, Error> {
+    word.and_then(|w| w.parse().ok())
+        .ok_or(Error::InvalidArgument)
+}
+
+/// Collects received bytes into command lines.
+pub struct Console {
+    line: [u8; LINE_LENGTH],
+    len: usize,
+    /// Line is longer than the buffer, ignore everything till the end of the line
+    overflow: bool,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            line: [0; LINE_LENGTH],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Handle received byte. Returns the parsed command once the end of the line is received.
+    /// Empty lines are ignored.
+    pub fn receive(&mut self, byte: u8) -> Option<Result<Command, Error>> {
+        match byte {
+            b'\r' | b'\n' => {
+                let len = core::mem::take(&mut self.len);
+                if core::mem::take(&mut self.overflow) {
+                    Some(Err(Error::TooLong))
+                } else if len == 0 {
+                    None
+                } else {
+                    let line = core::str::from_utf8(&self.line[..len]).unwrap_or("");
+                    Some(parse(line))
+                }
+            }
+            _ if self.len == LINE_LENGTH => {
+                self.overflow = true;
+                None
+            }
+            _ => {
+                self.line[self.len] = byte;
+                self.len += 1;
+                None
+            }
+        }
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the response (or the error) for the received command line.
+pub fn respond<D, R, S>(
+    command: Result<Command, Error>,
+    stepper: &mut impl Mutex<T = Stepper<D>>,
+    spindle: &mut impl Mutex<T = R>,
+    flash: &mut impl Mutex<T = S>,
+    program: &mut impl Mutex<T = Program>,
+    out: &mut impl Write,
+) -> fmt::Result
+where
+    D: StepperDriver,
+    R: SpindleSensor,
+    S: SettingsStorage,
+{
+    match command.and_then(|command| execute(command, stepper, spindle, flash, program, out)) {
+        Ok(()) => Ok(()),
+        Err(Error::Output) => Err(fmt::Error),
+        Err(err) => write!(out, "err {}\r\n", err),
+    }
+}
+
+/// Execute the command. Each shared resource is only locked for the duration of a single access,
+/// so the stepper interrupt is never delayed by the slow output or the settings storage.
+fn execute<D, R, S>(
+    command: Command,
+    stepper: &mut impl Mutex<T = Stepper<D>>,
+    spindle: &mut impl Mutex<T = R>,
+    flash: &mut impl Mutex<T = S>,
+    program: &mut impl Mutex<T = Program>,
+    out: &mut impl Write,
+) -> Result<(), Error>
+where
+    D: StepperDriver,
+    R: SpindleSensor,
+    S: SettingsStorage,
+{
+    match command {
+        Command::Help => {
+            out.write_str("ok help settings get set pos state rpm error move stop G-code\r\n")?;
+        }
+        Command::Settings => {
+            for setting in settings::ALL.iter() {
+                let value = flash.lock(|f| setting.read(f));
+                write!(out, "{}={}\r\n", setting.name(), value)?;
+            }
+            out.write_str("ok\r\n")?;
+        }
+        Command::Get(setting) => {
+            let value = flash.lock(|f| setting.read(f));
+            write!(out, "ok {}\r\n", value)?;
+        }
+        Command::Set(setting, value) => {
+            flash
+                .lock(|f| setting.write(f, value))
+                .map_err(|_| Error::Storage)?;
+            out.write_str("ok\r\n")?;
+        }
+        Command::Position => {
+            let position = stepper.lock(|s| s.position());
+            write!(out, "ok {}\r\n", position)?;
+        }
+        Command::State => {
+            let state = stepper.lock(|s| s.state());
+            write!(out, "ok {}\r\n", StateName(state))?;
+        }
+        Command::Rpm => {
+            let rpm = spindle.lock(|s| s.rpm());
+            write!(out, "ok {}\r\n", (rpm + 128) >> 8)?;
+        }
+        Command::Error => {
+            let error = stepper.lock(|s| s.last_error_degrees());
+            write!(out, "ok {}\r\n", error)?;
+        }
+        Command::Move(target) => {
+            stepper.lock(|s| s.move_to(target))?;
+            out.write_str("ok\r\n")?;
+        }
+        Command::Stop => {
+            stepper.lock(|s| s.stop());
+            out.write_str("ok\r\n")?;
+        }
+        Command::Gcode(block) => {
+            program.lock(|p| p.push(block)).map_err(Error::Gcode)?;
+            out.write_str("ok\r\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// Stepper state, as reported by the console.
+struct StateName(State);
+
+impl fmt::Display for StateName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |dir: Direction| match dir {
+            Direction::Left => "left",
+            Direction::Right => "right",
+        };
+        match self.0 {
+            State::Stopped => f.write_str("stopped"),
+            State::StopRequested(dir) | State::Stopping(dir) => write!(f, "stopping {}", name(dir)),
+            State::Running {
+                dir,
+                is_cutting_thread: false,
+            } => write!(f, "running {}", name(dir)),
+            State::Running {
+                dir,
+                is_cutting_thread: true,
+            } => write!(f, "threading {}", name(dir)),
+            State::ThreadStart | State::ThreadDelay => f.write_str("thread start"),
+            State::GearHold(dir) => write!(f, "geared {}", name(dir)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimDriver;
+    use rtic_core::Exclusive;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemoryStorage(HashMap<u16, u16>);
+
+    impl SettingsStorage for MemoryStorage {
+        type Error = ();
+
+        fn read(&mut self, tag: u16) -> Option<u16> {
+            self.0.get(&tag).copied()
+        }
+
+        fn write(&mut self, tag: u16, value: u16) -> Result<(), ()> {
+            self.0.insert(tag, value);
+            Ok(())
+        }
+
+        fn erase(&mut self) -> Result<(), ()> {
+            self.0.clear();
+            Ok(())
+        }
+    }
+
+    struct Spindle(u32);
+
+    impl SpindleSensor for Spindle {
+        fn rpm(&self) -> u32 {
+            self.0
+        }
+
+        fn interrupt(&mut self) -> bool {
+            false
+        }
+    }
+
+    struct Machine {
+        stepper: Stepper<SimDriver>,
+        spindle: Spindle,
+        flash: MemoryStorage,
+        program: Program,
+    }
+
+    impl Machine {
+        fn new() -> Machine {
+            let mut stepper = Stepper::new(1_000_000, SimDriver::new(), true);
+            stepper.set_acceleration(1200 << 8).unwrap();
+            stepper.set_speed(800 << 8).unwrap();
+            Machine {
+                stepper,
+                spindle: Spindle(600 << 8),
+                flash: MemoryStorage::default(),
+                program: Program::new(),
+            }
+        }
+
+        /// Send the command line, return the response
+        fn send(&mut self, line: &str) -> String {
+            let mut console = Console::new();
+            let mut out = String::new();
+            for &byte in line.as_bytes() {
+                if let Some(command) = console.receive(byte) {
+                    respond(
+                        command,
+                        &mut Exclusive(&mut self.stepper),
+                        &mut Exclusive(&mut self.spindle),
+                        &mut Exclusive(&mut self.flash),
+                        &mut Exclusive(&mut self.program),
+                        &mut out,
+                    )
+                    .unwrap();
+                }
+            }
+            out
+        }
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Ok(Command::Help), parse("help"));
+        assert_eq!(Ok(Command::Position), parse("  pos "));
+        assert_eq!(Ok(Command::Get(settings::PITCH.raw())), parse("get pitch"));
+        assert_eq!(
+            Ok(Command::Set(settings::MICROSTEPS.raw(), 8)),
+            parse("set microsteps 8")
+        );
+        assert_eq!(Ok(Command::Move(-1200)), parse("move -1200"));
+        assert_eq!(Ok(Command::Stop), parse("stop"));
+
+        // Every setting is found by its own name
+        for setting in settings::ALL {
+            let line = format!("get {}", setting.name());
+            assert_eq!(Ok(Command::Get(setting)), parse(&line));
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Err(Error::UnknownCommand), parse("jump"));
+        assert_eq!(Err(Error::UnknownSetting), parse("get speed"));
+        assert_eq!(Err(Error::InvalidArgument), parse("get"));
+        assert_eq!(Err(Error::InvalidArgument), parse("set pitch"));
+        assert_eq!(Err(Error::InvalidArgument), parse("set pitch -1"));
+        assert_eq!(Err(Error::InvalidArgument), parse("set pitch 100"));
+        assert_eq!(Err(Error::InvalidArgument), parse("move left"));
+        assert_eq!(Err(Error::InvalidArgument), parse("stop now"));
+    }
+
+    #[test]
+    fn lines() {
+        let mut console = Console::new();
+        let mut received = Vec::new();
+        for &byte in b"pos\r\n\r\nstop\n" {
+            received.extend(console.receive(byte));
+        }
+        assert_eq!(vec![Ok(Command::Position), Ok(Command::Stop)], received);
+
+        // Long lines are rejected as a whole
+        let mut received = Vec::new();
+        for &byte in "get ".repeat(20).as_bytes().iter().chain(b"\npos\n") {
+            received.extend(console.receive(byte));
+        }
+        assert_eq!(vec![Err(Error::TooLong), Ok(Command::Position)], received);
+    }
+
+    #[test]
+    fn settings() {
+        let mut machine = Machine::new();
+        assert_eq!("ok 16\r\n", machine.send("get pitch\n"));
+        assert_eq!("ok\r\n", machine.send("set pitch 20\n"));
+        assert_eq!("ok 20\r\n", machine.send("get pitch\n"));
+        assert_eq!(20, settings::PITCH.read(&mut machine.flash));
+
+        let listing = machine.send("settings\n");
+        assert!(listing.starts_with("lathe=0\r\n"));
+        assert!(listing.contains("pitch=20\r\n"));
+        assert!(listing.contains("spindle_cpr=1024\r\n"));
+        assert!(listing.ends_with("cross_acceleration=1200\r\nok\r\n"));
+    }
+
+    #[test]
+    fn moves() {
+        let mut machine = Machine::new();
+        assert_eq!("ok stopped\r\n", machine.send("state\n"));
+        assert_eq!("ok\r\n", machine.send("move -100\n"));
+        assert_eq!("ok running left\r\n", machine.send("state\n"));
+        assert_eq!("err not stopped\r\n", machine.send("move 100\n"));
+
+        while machine.stepper.driver_mut().advance_to_update() {
+            machine.stepper.interrupt();
+        }
+        assert_eq!("ok -100\r\n", machine.send("pos\n"));
+        assert_eq!("ok stopped\r\n", machine.send("state\n"));
+    }
+
+    #[test]
+    fn gcode() {
+        assert_eq!(
+            Ok(Command::Gcode(gcode::parse("G1 X1").unwrap())),
+            parse("g1 x1")
+        );
+        assert_eq!(Err(Error::Gcode(gcode::Error::Unsupported)), parse("G2 X1"));
+        assert_eq!(Ok(Command::Gcode(Block::default())), parse("(comment)"));
+
+        let mut machine = Machine::new();
+        assert_eq!("err not running\r\n", machine.send("G0 X1\n"));
+        machine.program.start();
+        for _ in 0..4 {
+            assert_eq!("ok\r\n", machine.send("G0 X1\n"));
+        }
+        assert_eq!("err busy\r\n", machine.send("G0 X1\n"));
+    }
+
+    #[test]
+    fn status() {
+        let mut machine = Machine::new();
+        assert_eq!("ok 600\r\n", machine.send("rpm\n"));
+        assert_eq!("ok 0\r\n", machine.send("error\n"));
+        assert_eq!("err unknown command\r\n", machine.send("jump\n"));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::gearing::Ratio;
+use crate::units::{Scale, Units, NM_PER_INCH, NM_PER_MM};
+use core::fmt;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FeedRate {
+    /// Inches per minute
+    InchesPerMinute(u16),
+    /// Thousands of inches per revolution
+    InchesPerRevolution(u16),
+    /// Millimeters per minute
+    MillimetersPerMinute(u16),
+    /// Hundredths of millimeter per revolution
+    MillimetersPerRevolution(u16),
+}
+
+impl FeedRate {
+    pub fn with_rate(&self, rate: u16) -> Self {
+        match *self {
+            FeedRate::InchesPerMinute(_ipm) => FeedRate::InchesPerMinute(rate),
+            FeedRate::InchesPerRevolution(_ipr) => FeedRate::InchesPerRevolution(rate),
+            FeedRate::MillimetersPerMinute(_mmpm) => FeedRate::MillimetersPerMinute(rate),
+            FeedRate::MillimetersPerRevolution(_mmpr) => FeedRate::MillimetersPerRevolution(rate),
+        }
+    }
+
+    pub fn rate(&self) -> u16 {
+        match *self {
+            FeedRate::InchesPerMinute(rate)
+            | FeedRate::InchesPerRevolution(rate)
+            | FeedRate::MillimetersPerMinute(rate)
+            | FeedRate::MillimetersPerRevolution(rate) => rate,
+        }
+    }
+
+    /// Smallest change of the feed rate operator can make with the encoder.
+    pub fn increment(&self) -> u16 {
+        match *self {
+            FeedRate::MillimetersPerMinute(_) => 5,
+            _ => 1,
+        }
+    }
+
+    /// Convert feed rate into the given units, rounding to the nearest increment (but never
+    /// rounding down to zero).
+    pub fn to_units(self, units: Units) -> Self {
+        let to = match (self, units) {
+            (FeedRate::InchesPerMinute(_), Units::Metric) => FeedRate::MillimetersPerMinute(0),
+            (FeedRate::InchesPerRevolution(_), Units::Metric) => {
+                FeedRate::MillimetersPerRevolution(0)
+            }
+            (FeedRate::MillimetersPerMinute(_), Units::Inch) => FeedRate::InchesPerMinute(0),
+            (FeedRate::MillimetersPerRevolution(_), Units::Inch) => {
+                FeedRate::InchesPerRevolution(0)
+            }
+            _ => return self,
+        };
+        let increment = to.with_rate(to.increment()).nanometers();
+        let count = ((self.nanometers() + increment / 2) / increment).max(1);
+        to.with_rate(count as u16 * to.increment())
+    }
+
+    /// Feed per minute or per revolution, in nanometers.
+    fn nanometers(self) -> u64 {
+        let unit = match self {
+            FeedRate::InchesPerMinute(_) => NM_PER_INCH,
+            FeedRate::InchesPerRevolution(_) => Units::Inch.resolution(),
+            FeedRate::MillimetersPerMinute(_) => NM_PER_MM,
+            FeedRate::MillimetersPerRevolution(_) => Units::Metric.resolution(),
+        };
+        u64::from(self.rate()) * unit as u64
+    }
+
+    /// Convert feed rate into the stepper speed, in (micro-)steps per second, 24.8 format.
+    /// `rpm` is the current spindle speed, in 24.8 format.
+    pub fn to_speed(self, scale: Scale, rpm: u32) -> u32 {
+        match self.to_ratio(scale) {
+            // RPM is already in 24.8 format, so no need to shift
+            Some(ratio) => ratio.to_speed(rpm),
+            None => scale.to_speed(self.nanometers()),
+        }
+    }
+
+    /// Amount of (micro-)steps per spindle revolution for the feed following the spindle. `None`
+    /// if feed does not depend on the spindle.
+    pub fn to_ratio(self, scale: Scale) -> Option<Ratio> {
+        match self {
+            FeedRate::InchesPerMinute(_) | FeedRate::MillimetersPerMinute(_) => None,
+            FeedRate::InchesPerRevolution(_) | FeedRate::MillimetersPerRevolution(_) => {
+                Some(scale.to_ratio(self.nanometers()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for FeedRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FeedRate::InchesPerMinute(ipm) => write!(f, "{: >3} IPM", ipm),
+            FeedRate::InchesPerRevolution(ipr) => write!(f, "0.{:0>3} IPR", ipr),
+            FeedRate::MillimetersPerMinute(mmpm) => write!(f, "{: >3} mm/m", mmpm),
+            FeedRate::MillimetersPerRevolution(mmpr) => {
+                write!(f, "{}.{:0>2} mm/r", mmpr / 100, mmpr % 100)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 TPI leadscrew, 16 microsteps, 200 steps per rotation
+    const STEPS_PER_INCH: u32 = 16 * 16 * 200;
+
+    fn scale() -> Scale {
+        Scale::inch(16 * 200, 16)
+    }
+
+    #[test]
+    fn inches_per_minute() {
+        // 30 IPM is 0.5 inch per second
+        assert_eq!(
+            (STEPS_PER_INCH / 2) << 8,
+            FeedRate::InchesPerMinute(30).to_speed(scale(), 0)
+        );
+        // Spindle speed does not matter
+        assert_eq!(
+            FeedRate::InchesPerMinute(7).to_speed(scale(), 0),
+            FeedRate::InchesPerMinute(7).to_speed(scale(), 1000 << 8)
+        );
+    }
+
+    #[test]
+    fn inches_per_revolution() {
+        // 0.006 IPR at 600 RPM is 0.06 inch per second
+        assert_eq!(
+            (STEPS_PER_INCH * 6 / 100) << 8,
+            FeedRate::InchesPerRevolution(6).to_speed(scale(), 600 << 8)
+        );
+        // Stopped spindle means no feed
+        assert_eq!(0, FeedRate::InchesPerRevolution(6).to_speed(scale(), 0));
+    }
+
+    #[test]
+    fn ratio() {
+        assert_eq!(None, FeedRate::InchesPerMinute(30).to_ratio(scale()));
+        // 0.004 IPR is 204.8 steps per revolution
+        let ratio = FeedRate::InchesPerRevolution(4).to_ratio(scale()).unwrap();
+        assert_eq!(2048, ratio.steps(10));
+    }
+
+    #[test]
+    fn rate() {
+        let feed = FeedRate::InchesPerRevolution(4).with_rate(12);
+        assert_eq!(FeedRate::InchesPerRevolution(12), feed);
+        assert_eq!(12, feed.rate());
+        assert_eq!(
+            FeedRate::InchesPerMinute(3),
+            FeedRate::InchesPerMinute(30).with_rate(3)
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(" 10 IPM", FeedRate::InchesPerMinute(10).to_string());
+        assert_eq!("0.004 IPR", FeedRate::InchesPerRevolution(4).to_string());
+        assert_eq!("0.120 IPR", FeedRate::InchesPerRevolution(120).to_string());
+        assert_eq!("250 mm/m", FeedRate::MillimetersPerMinute(250).to_string());
+        assert_eq!(
+            "0.10 mm/r",
+            FeedRate::MillimetersPerRevolution(10).to_string()
+        );
+    }
+
+    #[test]
+    fn millimeters() {
+        // 60 mm per minute is 1 mm per second, which is 2015.7 steps
+        assert_eq!(
+            516_031,
+            FeedRate::MillimetersPerMinute(60).to_speed(scale(), 0)
+        );
+        // 0.1 mm per revolution is 201.57 steps per revolution
+        let ratio = FeedRate::MillimetersPerRevolution(10)
+            .to_ratio(scale())
+            .unwrap();
+        assert_eq!(20_157, ratio.steps(100));
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(
+            FeedRate::MillimetersPerMinute(255),
+            FeedRate::InchesPerMinute(10).to_units(Units::Metric)
+        );
+        assert_eq!(
+            FeedRate::MillimetersPerRevolution(10),
+            FeedRate::InchesPerRevolution(4).to_units(Units::Metric)
+        );
+        assert_eq!(
+            FeedRate::InchesPerMinute(10),
+            FeedRate::MillimetersPerMinute(255).to_units(Units::Inch)
+        );
+        assert_eq!(
+            FeedRate::InchesPerRevolution(1),
+            FeedRate::MillimetersPerRevolution(1).to_units(Units::Inch)
+        );
+        assert_eq!(
+            FeedRate::InchesPerMinute(7),
+            FeedRate::InchesPerMinute(7).to_units(Units::Inch)
+        );
+    }
+}
@@ -0,0 +1,660 @@
+//! Minimal G-code interpreter for the single axis moves.
+//!
+//! Supported words are:
+//!
+//! * `G0`, `G1` -- rapid (at the traversal speed) and feed (at `F` speed) moves to `X` (or `Z`);
+//! * `G4 P` -- dwell for `P` seconds;
+//! * `G20`, `G21` -- inch and millimeter units;
+//! * `G33 K` -- thread cutting synchronized to the spindle, `K` is the thread pitch;
+//! * `G90`, `G91` -- absolute and relative (incremental) distances;
+//! * `M0` -- pause until operator continues, `M2` (or `M30`) -- end of the program;
+//! * `F` -- feed rate, in units per minute; `N` -- line number (ignored).
+//!
+//! Comments (in parentheses or after `;`) are ignored. Absolute positions are relative to the
+//! position stepper had when it was powered on. Programs start in the units selected in the
+//! settings.
+
+use crate::gearing::Ratio;
+use crate::units::{Scale, Units, NM_PER_INCH, NM_PER_MM};
+use core::fmt;
+
+/// Amount of blocks received ahead of the execution.
+const QUEUE_LENGTH: usize = 4;
+
+/// Numbers are kept as fixed point values with 4 decimal places.
+const SCALE: i64 = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Unexpected character or unterminated comment
+    Syntax,
+    /// Number is missing, malformed or out of range
+    InvalidNumber,
+    /// Unsupported word, G or M code
+    Unsupported,
+    /// Same word is given twice or words cannot be used together
+    Conflict,
+    /// Required word is missing (like `F` for the very first feed move)
+    MissingWord,
+    /// Program is not being executed, blocks are not accepted
+    NotRunning,
+    /// Too many blocks are waiting for execution
+    Full,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Error::Syntax => "syntax error",
+            Error::InvalidNumber => "invalid number",
+            Error::Unsupported => "unsupported",
+            Error::Conflict => "conflicting words",
+            Error::MissingWord => "missing word",
+            Error::NotRunning => "not running",
+            Error::Full => "busy",
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Motion {
+    Rapid,
+    Linear,
+    Thread,
+    Dwell,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Distance {
+    Absolute,
+    Relative,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stop {
+    Pause,
+    End,
+}
+
+/// Single line of the program. All numbers are fixed point with 4 decimal places.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Block {
+    motion: Option<Motion>,
+    units: Option<Units>,
+    distance: Option<Distance>,
+    stop: Option<Stop>,
+    axis: Option<i32>,
+    feed: Option<i32>,
+    pitch: Option<i32>,
+    dwell: Option<i32>,
+}
+
+/// Parse a single line of the program.
+pub fn parse(line: &str) -> Result<Block, Error> {
+    let mut block = Block::default();
+    let bytes = line.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let letter = bytes[pos].to_ascii_uppercase();
+        pos += 1;
+        match letter {
+            b' ' | b'\t' => {}
+            b';' => break,
+            b'(' => {
+                let end = bytes[pos..]
+                    .iter()
+                    .position(|&c| c == b')')
+                    .ok_or(Error::Syntax)?;
+                pos += end + 1;
+            }
+            b'A'..=b'Z' => {
+                while pos < bytes.len() && bytes[pos] == b' ' {
+                    pos += 1;
+                }
+                let start = pos;
+                while pos < bytes.len() && matches!(bytes[pos], b'0'..=b'9' | b'.' | b'+' | b'-') {
+                    pos += 1;
+                }
+                block.word(letter, number(&bytes[start..pos])?)?;
+            }
+            _ => return Err(Error::Syntax),
+        }
+    }
+    Ok(block)
+}
+
+/// Parse decimal number into fixed point with 4 decimal places. Extra digits are ignored.
+fn number(text: &[u8]) -> Result<i32, Error> {
+    let (negative, text) = match text.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        Some((b'+', rest)) => (false, rest),
+        _ => (false, text),
+    };
+    let mut parts = text.splitn(2, |&c| c == b'.');
+    let whole = parts.next().unwrap_or_default();
+    let fraction = parts.next().unwrap_or_default();
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(Error::InvalidNumber);
+    }
+
+    let mut value: i64 = 0;
+    for &c in whole {
+        if !c.is_ascii_digit() {
+            return Err(Error::InvalidNumber);
+        }
+        value = value * 10 + i64::from(c - b'0');
+        if value > i64::from(i32::MAX) {
+            return Err(Error::InvalidNumber);
+        }
+    }
+    let mut scale = SCALE;
+    for &c in fraction {
+        if !c.is_ascii_digit() {
+            return Err(Error::InvalidNumber);
+        }
+        scale /= 10;
+        value = value * 10 + i64::from(c - b'0');
+        if scale == 1 {
+            break;
+        }
+    }
+    let value = value * scale;
+    let value = if negative { -value } else { value };
+    i32::try_from(value).map_err(|_| Error::InvalidNumber)
+}
+
+/// Set the word which could only be given once per block.
+fn set<T>(word: &mut Option<T>, value: T) -> Result<(), Error> {
+    if word.is_some() {
+        return Err(Error::Conflict);
+    }
+    *word = Some(value);
+    Ok(())
+}
+
+impl Block {
+    fn word(&mut self, letter: u8, value: i32) -> Result<(), Error> {
+        let non_negative = || {
+            if value < 0 {
+                Err(Error::InvalidNumber)
+            } else {
+                Ok(value)
+            }
+        };
+        match letter {
+            b'G' => match code(value)? {
+                0 => set(&mut self.motion, Motion::Rapid),
+                1 => set(&mut self.motion, Motion::Linear),
+                4 => set(&mut self.motion, Motion::Dwell),
+                20 => set(&mut self.units, Units::Inch),
+                21 => set(&mut self.units, Units::Metric),
+                33 => set(&mut self.motion, Motion::Thread),
+                90 => set(&mut self.distance, Distance::Absolute),
+                91 => set(&mut self.distance, Distance::Relative),
+                _ => Err(Error::Unsupported),
+            },
+            b'M' => match code(value)? {
+                0 => set(&mut self.stop, Stop::Pause),
+                2 | 30 => set(&mut self.stop, Stop::End),
+                _ => Err(Error::Unsupported),
+            },
+            b'X' | b'Z' => set(&mut self.axis, value),
+            b'F' => set(&mut self.feed, non_negative()?),
+            b'K' => set(&mut self.pitch, non_negative()?),
+            b'P' => set(&mut self.dwell, non_negative()?),
+            b'N' => Ok(()),
+            _ => Err(Error::Unsupported),
+        }
+    }
+}
+
+/// G and M codes are whole numbers.
+fn code(value: i32) -> Result<i32, Error> {
+    if value < 0 || i64::from(value) % SCALE != 0 {
+        return Err(Error::Unsupported);
+    }
+    Ok(value / SCALE as i32)
+}
+
+/// What stepper should do to execute a block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Move to the given position, speed is in (micro-)steps per second, 24.8 format
+    Move { target: i32, speed: u32 },
+    /// Cut the thread up to the given position, `ratio` is (micro-)steps per spindle revolution
+    Thread { target: i32, ratio: Ratio },
+    /// Wait for the given amount of milliseconds
+    Dwell(u32),
+    /// Wait for the operator to continue
+    Pause,
+    /// End of the program
+    End,
+}
+
+/// Keeps modal state of the program (units, distance mode, motion and feed rate) and converts
+/// blocks into the stepper actions.
+pub struct Interpreter {
+    scale: Scale,
+    /// Units program starts with
+    default_units: Units,
+    /// Speed of rapid moves, in (micro-)steps per second, 24.8 format
+    rapid_speed: u32,
+    /// Feed moves are never faster than this speed
+    max_speed: u32,
+    units: Units,
+    distance: Distance,
+    motion: Motion,
+    /// Feed rate, in (micro-)steps per second, 24.8 format
+    feed: Option<u32>,
+}
+
+impl Interpreter {
+    pub fn new(scale: Scale, units: Units, rapid_speed: u32, max_speed: u32) -> Interpreter {
+        Interpreter {
+            scale,
+            default_units: units,
+            rapid_speed,
+            max_speed,
+            units,
+            distance: Distance::Absolute,
+            motion: Motion::Linear,
+            feed: None,
+        }
+    }
+
+    /// Reset modal state to the defaults, as at the end of the program.
+    pub fn reset(&mut self) {
+        *self = Interpreter::new(
+            self.scale,
+            self.default_units,
+            self.rapid_speed,
+            self.max_speed,
+        );
+    }
+
+    /// Update modal state from the block and return the action to execute, if any. `position` is
+    /// the current stepper position.
+    pub fn plan(&mut self, block: &Block, position: i32) -> Result<Option<Action>, Error> {
+        if let Some(units) = block.units {
+            self.units = units;
+        }
+        if let Some(distance) = block.distance {
+            self.distance = distance;
+        }
+        if let Some(feed) = block.feed {
+            self.feed = Some(self.to_speed(feed));
+        }
+
+        if let Some(stop) = block.stop {
+            if block.motion.is_some() || block.axis.is_some() {
+                return Err(Error::Conflict);
+            }
+            return Ok(Some(match stop {
+                Stop::Pause => Action::Pause,
+                Stop::End => {
+                    self.reset();
+                    Action::End
+                }
+            }));
+        }
+
+        match block.motion {
+            Some(Motion::Dwell) => {
+                if block.axis.is_some() {
+                    return Err(Error::Conflict);
+                }
+                let seconds = block.dwell.ok_or(Error::MissingWord)?;
+                // Seconds with 4 decimal places to milliseconds
+                return Ok(Some(Action::Dwell(seconds as u32 / 10)));
+            }
+            Some(motion) => self.motion = motion,
+            None => {}
+        }
+
+        let Some(axis) = block.axis else {
+            return Ok(None);
+        };
+        let distance = self.to_steps(axis)?;
+        let target = match self.distance {
+            Distance::Absolute => distance,
+            Distance::Relative => position.checked_add(distance).ok_or(Error::InvalidNumber)?,
+        };
+        let action = match self.motion {
+            Motion::Rapid => Action::Move {
+                target,
+                speed: self.rapid_speed,
+            },
+            Motion::Linear => Action::Move {
+                target,
+                speed: self.feed.ok_or(Error::MissingWord)?.min(self.max_speed),
+            },
+            Motion::Thread => {
+                let pitch = block.pitch.ok_or(Error::MissingWord)?;
+                let ratio = u64::try_from(self.to_nanometers(pitch))
+                    .ok()
+                    .and_then(|pitch| self.scale.checked_ratio(pitch, 1))
+                    .filter(|ratio| ratio.numerator != 0)
+                    .ok_or(Error::InvalidNumber)?;
+                Action::Thread { target, ratio }
+            }
+            Motion::Dwell => unreachable!(),
+        };
+        Ok(Some(action))
+    }
+
+    /// Convert number in current units into nanometers.
+    fn to_nanometers(&self, value: i32) -> i64 {
+        let unit = match self.units {
+            Units::Inch => NM_PER_INCH,
+            Units::Metric => NM_PER_MM,
+        };
+        i64::from(value) * (unit / SCALE)
+    }
+
+    /// Convert distance in current units into (micro-)steps, rounding to the nearest step.
+    fn to_steps(&self, value: i32) -> Result<i32, Error> {
+        let steps = self.scale.to_steps(self.to_nanometers(value));
+        i32::try_from(steps).map_err(|_| Error::InvalidNumber)
+    }
+
+    /// Convert feed rate in current units per minute into the stepper speed.
+    fn to_speed(&self, feed: i32) -> u32 {
+        // Feed rate is never negative
+        self.scale.to_speed(self.to_nanometers(feed) as u64)
+    }
+}
+
+/// Blocks received, but not executed yet. Blocks are only accepted while program is running.
+pub struct Program {
+    blocks: [Block; QUEUE_LENGTH],
+    /// Position of the first block in the queue
+    head: usize,
+    len: usize,
+    running: bool,
+}
+
+impl Program {
+    pub fn new() -> Program {
+        Program {
+            blocks: [Block::default(); QUEUE_LENGTH],
+            head: 0,
+            len: 0,
+            running: false,
+        }
+    }
+
+    /// Start accepting blocks.
+    pub fn start(&mut self) {
+        self.running = true;
+    }
+
+    /// Stop accepting blocks and drop all the pending ones.
+    pub fn stop(&mut self) {
+        self.running = false;
+        self.len = 0;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Queue block for the execution.
+    pub fn push(&mut self, block: Block) -> Result<(), Error> {
+        if !self.running {
+            return Err(Error::NotRunning);
+        }
+        if self.len == QUEUE_LENGTH {
+            return Err(Error::Full);
+        }
+        self.blocks[(self.head + self.len) % QUEUE_LENGTH] = block;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Take next block to execute.
+    pub fn pop(&mut self) -> Option<Block> {
+        if self.len == 0 {
+            return None;
+        }
+        let block = self.blocks[self.head];
+        self.head = (self.head + 1) % QUEUE_LENGTH;
+        self.len -= 1;
+        Some(block)
+    }
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 TPI leadscrew, 16 microsteps, 200 steps per rotation
+    const STEPS_PER_INCH: u32 = 16 * 16 * 200;
+
+    fn interpreter(units: Units) -> Interpreter {
+        Interpreter::new(Scale::inch(16 * 200, 16), units, RAPID, MAX)
+    }
+    // 10 IPM and 30 IPM
+    const RAPID: u32 = ((10 * STEPS_PER_INCH) << 8) / 60;
+    const MAX: u32 = ((30 * STEPS_PER_INCH) << 8) / 60;
+
+    fn plan(interpreter: &mut Interpreter, line: &str, position: i32) -> Option<Action> {
+        interpreter.plan(&parse(line).unwrap(), position).unwrap()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(Ok(12_500), number(b"1.25"));
+        assert_eq!(Ok(-5_000), number(b"-.5"));
+        assert_eq!(Ok(30_000), number(b"+3."));
+        assert_eq!(Ok(1_234), number(b"0.123456"));
+        assert_eq!(Err(Error::InvalidNumber), number(b""));
+        assert_eq!(Err(Error::InvalidNumber), number(b"-"));
+        assert_eq!(Err(Error::InvalidNumber), number(b"1-2"));
+        assert_eq!(Err(Error::InvalidNumber), number(b"1000000"));
+    }
+
+    #[test]
+    fn blocks() {
+        let block = parse("n10 g1 x-1.5 f20 (feed) ; comment").unwrap();
+        assert_eq!(Some(Motion::Linear), block.motion);
+        assert_eq!(Some(-15_000), block.axis);
+        assert_eq!(Some(200_000), block.feed);
+
+        let block = parse("G21G91Z 2").unwrap();
+        assert_eq!(Some(Units::Metric), block.units);
+        assert_eq!(Some(Distance::Relative), block.distance);
+        assert_eq!(Some(20_000), block.axis);
+
+        assert_eq!(Ok(Block::default()), parse("(just a comment)"));
+    }
+
+    #[test]
+    fn block_errors() {
+        assert_eq!(Err(Error::Syntax), parse("G1 X1 (comment"));
+        assert_eq!(Err(Error::Syntax), parse("G1 X1 #"));
+        assert_eq!(Err(Error::InvalidNumber), parse("G1 X"));
+        assert_eq!(Err(Error::InvalidNumber), parse("G1 F-10"));
+        assert_eq!(Err(Error::Unsupported), parse("G2 X1"));
+        assert_eq!(Err(Error::Unsupported), parse("G33.1 Z1"));
+        assert_eq!(Err(Error::Unsupported), parse("Y1"));
+        assert_eq!(Err(Error::Conflict), parse("G0 G1 X1"));
+        assert_eq!(Err(Error::Conflict), parse("X1 Z1"));
+    }
+
+    #[test]
+    fn moves() {
+        let mut interpreter = interpreter(Units::Inch);
+        assert_eq!(
+            Some(Action::Move {
+                target: 25_600,
+                speed: RAPID
+            }),
+            plan(&mut interpreter, "G0 X0.5", 0)
+        );
+        // 6 IPM
+        assert_eq!(
+            Some(Action::Move {
+                target: -5_120,
+                speed: ((6 * STEPS_PER_INCH) << 8) / 60
+            }),
+            plan(&mut interpreter, "G1 X-0.1 F6", 25_600)
+        );
+        // Motion and feed are modal
+        assert_eq!(
+            Some(Action::Move {
+                target: 0,
+                speed: ((6 * STEPS_PER_INCH) << 8) / 60
+            }),
+            plan(&mut interpreter, "X0", -5_120)
+        );
+        // Feed is limited by the maximum speed
+        assert_eq!(
+            Some(Action::Move {
+                target: 5_120,
+                speed: MAX
+            }),
+            plan(&mut interpreter, "X0.1 F100", 0)
+        );
+    }
+
+    #[test]
+    fn units_and_distance() {
+        let mut interpreter = interpreter(Units::Inch);
+        assert_eq!(None, plan(&mut interpreter, "G21 G91", 0));
+        // 25.4 mm is one inch, 254 mm per minute is 10 IPM
+        assert_eq!(
+            Some(Action::Move {
+                target: 1_000 + 51_200,
+                speed: RAPID
+            }),
+            plan(&mut interpreter, "G1 X25.4 F254", 1_000)
+        );
+        // 1 mm is 2015.7 steps
+        assert_eq!(
+            Some(Action::Move {
+                target: -2_016,
+                speed: RAPID
+            }),
+            plan(&mut interpreter, "X-1", 0)
+        );
+        // Feed rate was converted with the units it was given in
+        assert_eq!(
+            Some(Action::Move {
+                target: 51_200,
+                speed: RAPID
+            }),
+            plan(&mut interpreter, "G20 G90 X1", 0)
+        );
+
+        // Program starts in the configured units, which are restored at the end of the program
+        let mut interpreter =
+            Interpreter::new(Scale::inch(16 * 200, 16), Units::Metric, RAPID, MAX);
+        assert_eq!(
+            Some(Action::Move {
+                target: 25_600,
+                speed: RAPID
+            }),
+            plan(&mut interpreter, "G20 G0 X0.5", 0)
+        );
+        assert_eq!(Some(Action::End), plan(&mut interpreter, "M2", 0));
+        assert_eq!(
+            Some(Action::Move {
+                target: 2_016,
+                speed: RAPID
+            }),
+            plan(&mut interpreter, "G0 X1", 0)
+        );
+    }
+
+    #[test]
+    fn threads_and_dwell() {
+        let mut interpreter = interpreter(Units::Inch);
+        // 16 TPI
+        assert_eq!(
+            Some(Action::Thread {
+                target: -51_200,
+                ratio: Ratio::new(3_200, 1)
+            }),
+            plan(&mut interpreter, "G33 Z-1 K0.0625", 0)
+        );
+        assert_eq!(
+            Some(Action::Dwell(1_500)),
+            plan(&mut interpreter, "G4 P1.5", 0)
+        );
+        // Dwell is not modal
+        assert_eq!(
+            Some(Action::Thread {
+                target: 0,
+                ratio: Ratio::new(3_200, 1)
+            }),
+            plan(&mut interpreter, "Z0 K0.0625", 0)
+        );
+        // 0.7 mm pitch is 1411.02 steps per revolution, kept exact
+        assert_eq!(
+            Some(Action::Thread {
+                target: -20_157,
+                ratio: Ratio::new(179_200, 127)
+            }),
+            plan(&mut interpreter, "G21 Z-10 K0.7", 0)
+        );
+        let block = parse("Z0 K0").unwrap();
+        assert_eq!(
+            Err(Error::InvalidNumber),
+            interpreter.plan(&block, 0).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn stops() {
+        let mut interpreter = interpreter(Units::Inch);
+        assert_eq!(Some(Action::Pause), plan(&mut interpreter, "M0", 0));
+        assert_eq!(None, plan(&mut interpreter, "G91 F10", 0));
+        assert_eq!(Some(Action::End), plan(&mut interpreter, "M2", 0));
+        // Program end resets the modal state
+        let block = parse("X1").unwrap();
+        assert_eq!(Err(Error::MissingWord), interpreter.plan(&block, 0));
+    }
+
+    #[test]
+    fn plan_errors() {
+        let mut interpreter = interpreter(Units::Inch);
+        let mut plan = |line| interpreter.plan(&parse(line).unwrap(), 0);
+        assert_eq!(Err(Error::MissingWord), plan("G1 X1"));
+        assert_eq!(Err(Error::MissingWord), plan("G33 X1"));
+        assert_eq!(Err(Error::MissingWord), plan("G4"));
+        assert_eq!(Err(Error::InvalidNumber), plan("G33 X1 K0"));
+        assert_eq!(Err(Error::Conflict), plan("G0 X1 M0"));
+        assert_eq!(Err(Error::Conflict), plan("G4 P1 X1"));
+    }
+
+    #[test]
+    fn queue() {
+        let mut program = Program::new();
+        let block = parse("G0 X1").unwrap();
+        assert_eq!(Err(Error::NotRunning), program.push(block));
+
+        program.start();
+        for _ in 0..QUEUE_LENGTH {
+            program.push(block).unwrap();
+        }
+        assert_eq!(Err(Error::Full), program.push(block));
+        assert_eq!(Some(block), program.pop());
+        program.push(Block::default()).unwrap();
+        for _ in 1..QUEUE_LENGTH {
+            assert_eq!(Some(block), program.pop());
+        }
+        assert_eq!(Some(Block::default()), program.pop());
+        assert_eq!(None, program.pop());
+
+        program.push(block).unwrap();
+        program.stop();
+        assert!(!program.is_running());
+        assert_eq!(None, program.pop());
+    }
+}
@@ -0,0 +1,288 @@
+//! Electronic leadscrew: stepper motor position slaved to the spindle position.
+//!
+//! Spindle position is only known at spindle events (once per revolution), so at every event we
+//! calculate where stepper should be at the next one and adjust the speed accordingly. Stepper is
+//! never allowed to run further than a few revolutions ahead of the spindle, so it stops (at the
+//! exact position) once the spindle stops.
+
+/// Amount of (micro-)steps stepper makes per one spindle revolution, `numerator / denominator`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ratio {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Ratio {
+    pub const fn new(numerator: u32, denominator: u32) -> Ratio {
+        Ratio {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Amount of steps for the given amount of spindle revolutions, rounded down.
+    pub fn steps(self, revolutions: u32) -> u64 {
+        u64::from(revolutions) * u64::from(self.numerator) / u64::from(self.denominator)
+    }
+
+    /// Speed of the stepper motor, in steps per second, 24.8 format. `rpm` is the spindle speed,
+    /// in 24.8 format.
+    pub fn to_speed(self, rpm: u32) -> u32 {
+        (u64::from(rpm) * u64::from(self.numerator) / (60 * u64::from(self.denominator))) as u32
+    }
+}
+
+/// Speed is never adjusted by more than `1 / MAX_CORRECTION` of the spindle speed.
+const MAX_CORRECTION: u32 = 2;
+
+/// Spindle revolutions stepper could run ahead, in addition to its deceleration distance.
+const LOOKAHEAD_REVOLUTIONS: u32 = 2;
+
+/// Command for the stepper calculated at the spindle event.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GearCommand {
+    /// Step stepper should not go past until the next spindle event
+    pub target_step: u32,
+    /// New speed for the stepper, `None` if spindle speed is unknown
+    pub speed: Option<u32>,
+}
+
+pub struct Gearing {
+    ratio: Ratio,
+    /// Acceleration, steps per second per second, 24.8 format
+    acceleration: u32,
+    /// Stepper follows the spindle
+    engaged: bool,
+    /// Stepper step at the reference spindle event (where count of revolutions starts)
+    origin_step: u32,
+    /// Spindle revolutions since the reference event
+    revolutions: u32,
+    /// Use next spindle event as a new reference
+    rebase: bool,
+    /// Step to stop at once the move is finished
+    final_step: u32,
+}
+
+impl Gearing {
+    pub fn new() -> Gearing {
+        Gearing {
+            ratio: Ratio::new(0, 1),
+            acceleration: 0,
+            engaged: false,
+            origin_step: 0,
+            revolutions: 0,
+            rebase: false,
+            final_step: 0,
+        }
+    }
+
+    pub fn set_acceleration(&mut self, acceleration: u32) {
+        self.acceleration = acceleration;
+    }
+
+    /// Set new ratio. Takes effect starting from the next spindle event.
+    pub fn set_ratio(&mut self, ratio: Ratio) {
+        self.ratio = ratio;
+        self.rebase = true;
+    }
+
+    /// Start following the spindle, finishing at `final_step`. Next spindle event is used as a
+    /// reference.
+    pub fn engage(&mut self, final_step: u32) {
+        self.engaged = true;
+        self.rebase = true;
+        self.final_step = final_step;
+    }
+
+    pub fn disengage(&mut self) {
+        self.engaged = false;
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.engaged
+    }
+
+    pub fn final_step(&self) -> u32 {
+        self.final_step
+    }
+
+    /// Steps needed to decelerate from the given speed (24.8 format) to the full stop.
+    fn deceleration_steps(&self, speed: u32) -> u64 {
+        if self.acceleration == 0 {
+            return 0;
+        }
+        // v^2 / 2a, both in 24.8 format
+        (u64::from(speed) * u64::from(speed)) / (2 * 256 * u64::from(self.acceleration))
+    }
+
+    /// Calculate the stepper command at the spindle event. `step` is the current stepper step,
+    /// `rpm` is the spindle speed in 24.8 format (`0` if unknown). Disengages once final step is
+    /// reached.
+    pub fn spindle_sync(&mut self, step: u32, rpm: u32) -> GearCommand {
+        if core::mem::take(&mut self.rebase) {
+            self.origin_step = step;
+            self.revolutions = 0;
+        } else {
+            self.revolutions += 1;
+        }
+        self.command(step, rpm)
+    }
+
+    /// Calculate the command at the spindle event for the stepper following the other axis rather
+    /// than the spindle. `expected` is the step stepper should be at now and `advance` is the
+    /// amount of steps the other axis moved (scaled to this stepper) since the previous event, so
+    /// stepper is expected to make as many by the next one.
+    pub fn follow_sync(&mut self, step: u32, expected: u32, advance: u32, rpm: u32) -> GearCommand {
+        self.origin_step = expected;
+        self.revolutions = 0;
+        self.ratio = Ratio::new(advance, 1);
+        self.command(step, rpm)
+    }
+
+    fn command(&mut self, step: u32, rpm: u32) -> GearCommand {
+        let base_speed = self.ratio.to_speed(rpm);
+        let expected = |revolutions: u32| -> u64 {
+            u64::from(self.origin_step) + self.ratio.steps(self.revolutions + revolutions)
+        };
+
+        // Allow running far enough to never decelerate while spindle keeps rotating
+        let max_speed = base_speed + base_speed / MAX_CORRECTION;
+        let lookahead = match self.ratio.numerator {
+            0 => 0,
+            numerator => {
+                self.deceleration_steps(max_speed) * u64::from(self.ratio.denominator)
+                    / u64::from(numerator)
+            }
+        };
+        let lookahead = u32::try_from(lookahead).unwrap_or(u32::MAX);
+        let target_step = expected(lookahead.saturating_add(LOOKAHEAD_REVOLUTIONS))
+            .min(u64::from(self.final_step)) as u32;
+        let speed = if base_speed == 0 {
+            None
+        } else {
+            // Make stepper be exactly where it should be at the next spindle event
+            let needed = expected(1) as i64 - i64::from(step);
+            let speed = needed * i64::from(rpm) / 60;
+            let bound = i64::from(base_speed / MAX_CORRECTION);
+            let speed = speed.max(i64::from(base_speed) - bound);
+            Some(speed.min(i64::from(base_speed) + bound) as u32)
+        };
+        // Only finish once spindle speed is known (so stepper can run to the final step) or if
+        // already there
+        if target_step == self.final_step && (speed.is_some() || step == self.final_step) {
+            self.engaged = false;
+        }
+        GearCommand { target_step, speed }
+    }
+}
+
+impl Default for Gearing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0.004 IPR with 16 TPI leadscrew, 16 microsteps: 204.8 steps per revolution
+    const RATIO: Ratio = Ratio::new(4 * 16 * 16 * 200, 1000);
+    const ACCELERATION: u32 = (1200 * 16) << 8;
+
+    fn gearing() -> Gearing {
+        let mut gearing = Gearing::new();
+        gearing.set_acceleration(ACCELERATION);
+        gearing.set_ratio(RATIO);
+        gearing.engage(1_000_000);
+        gearing
+    }
+
+    #[test]
+    fn ratio() {
+        assert_eq!(204, RATIO.steps(1));
+        assert_eq!(2048, RATIO.steps(10));
+        assert_eq!(204_800, RATIO.steps(1000));
+        // 600 RPM is 10 revolutions per second
+        assert_eq!(2048 << 8, RATIO.to_speed(600 << 8));
+    }
+
+    #[test]
+    fn first_event_is_reference() {
+        let mut gearing = gearing();
+        let cmd = gearing.spindle_sync(100, 600 << 8);
+        // 204 steps to make till the next event
+        assert_eq!(Some((204 * (600 << 8)) / 60), cmd.speed);
+        assert!(cmd.target_step > 100 + 204 * LOOKAHEAD_REVOLUTIONS);
+    }
+
+    #[test]
+    fn speed_follows_position() {
+        let mut gearing = gearing();
+        gearing.spindle_sync(0, 600 << 8);
+        let base = RATIO.to_speed(600 << 8);
+
+        // Exactly on track: next event is expected at 409.6 steps
+        let cmd = gearing.spindle_sync(204, 600 << 8);
+        assert_eq!(Some((205 * (600 << 8)) / 60), cmd.speed);
+
+        // Lagging behind: speed up
+        let cmd = gearing.spindle_sync(400, 600 << 8);
+        assert!(cmd.speed.unwrap() > base);
+
+        // Running ahead: slow down
+        let cmd = gearing.spindle_sync(820, 600 << 8);
+        assert!(cmd.speed.unwrap() < base);
+        assert!(cmd.target_step >= 820);
+    }
+
+    #[test]
+    fn unknown_speed() {
+        let mut gearing = gearing();
+        let cmd = gearing.spindle_sync(0, 0);
+        assert_eq!(None, cmd.speed);
+        // Still allowed to move ahead (if already moving)
+        assert_eq!(RATIO.steps(LOOKAHEAD_REVOLUTIONS) as u32, cmd.target_step);
+    }
+
+    #[test]
+    fn final_step() {
+        let mut gearing = gearing();
+        gearing.engage(300);
+        let cmd = gearing.spindle_sync(0, 600 << 8);
+        assert_eq!(300, cmd.target_step);
+        assert!(!gearing.is_engaged());
+    }
+
+    #[test]
+    fn rebase_on_ratio_change() {
+        let mut gearing = gearing();
+        gearing.spindle_sync(0, 600 << 8);
+        gearing.spindle_sync(204, 600 << 8);
+        gearing.set_ratio(Ratio::new(100, 1));
+        // New reference: next event is expected 100 steps away from here
+        let cmd = gearing.spindle_sync(500, 600 << 8);
+        assert_eq!(Some((100 * (600 << 8)) / 60), cmd.speed);
+    }
+
+    #[test]
+    fn follow_other_axis() {
+        let mut gearing = gearing();
+        gearing.engage(10_000);
+        // Other axis is not moving yet
+        let cmd = gearing.follow_sync(0, 0, 0, 600 << 8);
+        assert_eq!(None, cmd.speed);
+        assert_eq!(0, cmd.target_step);
+
+        // Lagging 20 steps behind the other axis which moved 100 steps over the last revolution
+        let cmd = gearing.follow_sync(80, 100, 100, 600 << 8);
+        assert_eq!(Some((120 * (600 << 8)) / 60), cmd.speed);
+        assert!(cmd.target_step >= 100 + 100 * LOOKAHEAD_REVOLUTIONS);
+        assert!(gearing.is_engaged());
+
+        // Other axis stopped at the end of the move
+        let cmd = gearing.follow_sync(10_000, 10_000, 0, 600 << 8);
+        assert_eq!(10_000, cmd.target_step);
+        assert!(!gearing.is_engaged());
+    }
+}
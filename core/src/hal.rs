@@ -0,0 +1,199 @@
+//! Hardware abstraction for the user interface: controls, rotary encoder, emergency stop,
+//! spindle sensor and the board tying them together with the display, settings storage and
+//! the stepper motor.
+use crate::driver::StepperDriver;
+use crate::gcode::Program;
+use crate::settings::SettingsStorage;
+use crate::stepper::Stepper;
+use rtic_core::Mutex;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ControlsState {
+    pub left: bool,
+    pub right: bool,
+    pub fast: bool,
+    pub button: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    Left,
+    Right,
+    Fast,
+    Encoder,
+}
+
+pub const BUTTONS: [Button; 4] = [Button::Left, Button::Right, Button::Fast, Button::Encoder];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    Pressed(Button),
+    Unpressed(Button),
+    None,
+}
+
+pub trait Controls {
+    /// Current state of all buttons.
+    fn state(&self) -> ControlsState;
+
+    /// Check buttons state, compare with the previous one and return Pressed/Unpressed event if
+    /// state was changed.
+    fn read_event(&mut self) -> Event;
+}
+
+pub trait EStop {
+    /// Check if we are in the emergency stop condition.
+    fn is_emergency_stop(&self) -> bool;
+}
+
+/// Spindle sensor: either a hall sensor triggered once per revolution or a quadrature encoder.
+pub trait SpindleSensor {
+    /// Get latest captured RPM, in 24.8 format
+    fn rpm(&self) -> u32;
+
+    /// Spindle angle since the start of the revolution, in 0.16 format (fraction of the
+    /// revolution), measured in the forward direction. Sensors triggered once per revolution
+    /// always return `0`.
+    fn angle(&self) -> u16 {
+        0
+    }
+
+    /// Check for pending interrupt and handle it (reset pending flag). Returns `true` at the start
+    /// of the spindle revolution (hall sensor pulse or encoder index).
+    fn interrupt(&mut self) -> bool;
+}
+
+/// Measures durations, in microseconds. Used for detecting long presses of the buttons.
+pub trait Stopwatch {
+    /// Start measuring the duration.
+    fn start() -> Self;
+
+    /// Duration passed since the stopwatch was started. Might need to be called often enough to
+    /// account for the underlying timer overflows.
+    fn elapsed_us(&mut self) -> u32;
+}
+
+/// Rotary encoder, counting from `0` to `limit - 1` and wrapping around.
+pub trait QuadEncoder {
+    /// Get rotary encoder limit.
+    fn get_limit(&self) -> u16;
+
+    /// Set rotary encoder limit. Note that this function is "unsafe" because it changes the
+    /// configuration of the encoder without resetting it back.
+    fn set_limit_unsafe(&mut self, limit: u16);
+
+    /// Get current value of the rotary encoder.
+    fn current(&self) -> u16;
+
+    /// Set current value of the rotary encoder.
+    fn set_current(&mut self, pos: u16);
+
+    /// Set `limit` and `current` value temporarily. Once return value is dropped, encoder is
+    /// reset back to its original settings.
+    fn set_current_limit(&mut self, current: u16, limit: u16) -> QuadEncoderWithSettings<'_, Self>
+    where
+        Self: Sized,
+    {
+        let (old_limit, old_current) = (self.get_limit(), self.current());
+        self.set_limit_unsafe(limit);
+        self.set_current(current);
+        QuadEncoderWithSettings {
+            limit: old_limit,
+            current: old_current,
+            encoder: self,
+        }
+    }
+
+    fn delta_encoder(&mut self) -> EncoderDelta<'_, Self>
+    where
+        Self: Sized,
+    {
+        EncoderDelta::new(self)
+    }
+}
+
+pub struct QuadEncoderWithSettings<'a, E: QuadEncoder> {
+    limit: u16,
+    current: u16,
+    encoder: &'a mut E,
+}
+
+impl<'a, E: QuadEncoder> core::ops::Deref for QuadEncoderWithSettings<'a, E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        self.encoder
+    }
+}
+
+impl<'a, E: QuadEncoder> core::ops::DerefMut for QuadEncoderWithSettings<'a, E> {
+    fn deref_mut(&mut self) -> &mut E {
+        self.encoder
+    }
+}
+
+impl<'a, E: QuadEncoder> Drop for QuadEncoderWithSettings<'a, E> {
+    fn drop(&mut self) {
+        self.encoder.set_limit_unsafe(self.limit);
+        self.encoder.set_current(self.current);
+    }
+}
+
+// Any reasonably big number to make sure you cannot crank half of it on the encoder between 'ticks'
+const LIMIT: u16 = 20_000;
+
+/// Helper structure to use encoder as encoder producing "deltas".
+pub struct EncoderDelta<'a, E: QuadEncoder> {
+    last: u16,
+    encoder: QuadEncoderWithSettings<'a, E>,
+}
+
+impl<'a, E: QuadEncoder> EncoderDelta<'a, E> {
+    fn new(encoder: &'a mut E) -> Self {
+        Self {
+            last: LIMIT / 2,
+            encoder: encoder.set_current_limit(LIMIT / 2, LIMIT),
+        }
+    }
+
+    pub fn delta(&mut self) -> i16 {
+        let current = self.encoder.current();
+        // Substract unsigned wrapping around LIMIT
+        let delta = if current < self.last {
+            current + LIMIT - self.last
+        } else {
+            current - self.last
+        };
+        self.last = current;
+        // Convert delta to signed -LIMIT/2 to LIMIT/2
+        if delta < LIMIT / 2 {
+            delta as i16
+        } else {
+            (delta as i16) - LIMIT as i16
+        }
+    }
+}
+
+/// Hardware the menus run on. Shared resources (stepper motors, spindle sensor, settings storage
+/// and G-code program queue) are accessed through the mutexes, as they are also used by the
+/// interrupt handlers.
+pub trait Board {
+    type Screen: lcd::Hardware + lcd::Delay;
+    type Encoder: QuadEncoder;
+    type Controls: Controls;
+    type EStop: EStop;
+    type Storage: SettingsStorage;
+    type Stopwatch: Stopwatch;
+    type Driver: StepperDriver;
+    type Spindle: SpindleSensor;
+    type Stepper: Mutex<T = Stepper<Self::Driver>>;
+    /// Cross-slide axis
+    type Cross: Mutex<T = Stepper<Self::Driver>>;
+    type Hall: Mutex<T = Self::Spindle>;
+    type Flash: Mutex<T = Self::Storage>;
+    type Program: Mutex<T = Program>;
+
+    /// Wait for the next interrupt. Called while stepper motor is locked, when waiting for it to
+    /// stop.
+    fn wait_for_interrupt();
+}
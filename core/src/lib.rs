@@ -0,0 +1,26 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+//! Hardware-independent logic of the stepper-motor based power feed: stepper motor control,
+//! thread cutting synchronization, feed rate conversions, settings and the user interface
+//! (menus), running on top of the hardware abstraction from the `hal` module.
+//!
+//! Kept separate from the firmware so it can be built and tested on the host.
+
+pub mod console;
+pub mod driver;
+pub mod feed;
+pub mod font;
+pub mod gcode;
+pub mod gearing;
+pub mod hal;
+pub mod menu;
+pub mod session;
+pub mod settings;
+#[cfg(any(test, feature = "std"))]
+pub mod sim;
+pub mod spindle;
+pub mod stepper;
+pub mod threads;
+pub mod units;
+
+pub use self::driver::StepperDriver;
@@ -0,0 +1,58 @@
+use crate::hal::{Board, Controls, QuadEncoder};
+use crate::menu::util::{printable_position, NavStatus, Navigation};
+use crate::menu::{steputil, MenuItem, MenuResources};
+use crate::settings;
+use crate::units::div_round;
+use core::fmt::Write;
+use rtic_core::Mutex;
+
+/// Cross-slide jog: every encoder click moves the cross-slide by one thou (or a hundredth of a
+/// millimeter), ten with "Fast" held. Short press on the encoder zeroes the position.
+pub struct CrossOperation {}
+
+impl CrossOperation {
+    pub fn new() -> CrossOperation {
+        CrossOperation {}
+    }
+}
+
+impl MenuItem for CrossOperation {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>) {
+        r.reload_cross_settings();
+        let (scale, units) = r
+            .shared
+            .flash
+            .lock(|f| (settings::cross_scale(f), settings::units(f)));
+
+        let mut deltaenc = r.encoder.delta_encoder();
+        let mut nav = Navigation::<B::Stopwatch>::new();
+        r.display.clear();
+        loop {
+            let pos = r.shared.cross.lock(|s| s.position());
+            let delta = i64::from(deltaenc.delta());
+            let event = r.controls.read_event();
+
+            r.display.position(0, 0);
+            let _ = write!(r.display, "Cross Slide");
+            r.display.position(0, 1);
+            let _ = write!(r.display, "{}    ", printable_position(pos, scale, units));
+
+            // Target is snapped to the whole units, so rounding errors do not accumulate
+            if delta != 0 {
+                let step = if r.controls.state().fast { 10 } else { 1 };
+                let resolution = units.resolution();
+                let current = div_round(scale.to_nanometers(i64::from(pos)), resolution);
+                let target = scale.to_steps((current + delta * step) * resolution) as i32;
+                if r.shared.cross.lock(|s| s.move_to(target)).is_ok() {
+                    steputil::wait_axis_stopped::<B>(&mut r.shared.cross);
+                }
+            }
+
+            match nav.check(r.estop, event) {
+                Some(NavStatus::Exit) => return,
+                Some(NavStatus::Select) => r.shared.cross.lock(|s| s.set_position(0)).unwrap(),
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,548 @@
+use crate::feed::FeedRate;
+use crate::font;
+use crate::gearing::Ratio;
+use crate::hal::{Board, Button, Controls, Event, QuadEncoder, SpindleSensor, Stopwatch};
+use crate::menu::util::{run_selection_idx, wait_loop, NavStatus, Navigation};
+use crate::menu::{limits, steputil, MenuItem, MenuResources, SharedResources};
+use crate::settings;
+use crate::stepper::State as StepperState;
+use crate::stepper::{Direction, StepperError};
+use crate::units::Scale;
+use core::fmt::Write;
+use lcd::Display;
+use rtic_core::Mutex;
+use stepgen::Error as StepgenError;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FeedSpeed {
+    Fast,
+    Slow,
+}
+
+/// Automatic reciprocating feed between the limits.
+#[derive(Clone, Copy)]
+struct AutoReverse {
+    /// Amount of cutting passes, `0` disables automatic reverse
+    passes: u16,
+    /// Dwell at each end, in tenths of a second
+    dwell: u16,
+    /// Return at the traversal speed, cutting in one direction only
+    rapid_return: bool,
+}
+
+/// Progress of the automatic reciprocating feed.
+struct Reciprocating<W: Stopwatch> {
+    /// Direction of the first stroke. With rapid return, only strokes in this direction cut.
+    cut_dir: Direction,
+    /// Direction of the current stroke
+    dir: Direction,
+    /// Cutting passes completed
+    passes: u16,
+    /// Dwell at the end of the stroke, if started
+    dwell: Option<W>,
+    /// Speed of the rapid return strokes, in (micro-)steps per second, 24.8 format
+    rapid_speed: u32,
+}
+
+pub struct FeedOperation {
+    speed: u32,
+    /// Feed follows the spindle with the given ratio
+    ratio: Option<Ratio>,
+    error: Option<StepperError>,
+    slow_speed: FeedRate,
+    fast_speed: FeedRate,
+    feed: FeedSpeed,
+    rpm: u32,
+    limits: (Option<i32>, Option<i32>),
+    auto: AutoReverse,
+}
+
+impl FeedOperation {
+    pub fn new(is_lathe: bool) -> FeedOperation {
+        FeedOperation {
+            speed: 0,
+            ratio: None,
+            error: None,
+            slow_speed: if is_lathe {
+                FeedRate::InchesPerRevolution(4)
+            } else {
+                FeedRate::InchesPerMinute(10)
+            },
+            fast_speed: FeedRate::InchesPerMinute(30),
+            feed: FeedSpeed::Slow,
+            rpm: 0,
+            limits: (None, None),
+            auto: AutoReverse {
+                passes: 0,
+                dwell: 5,
+                rapid_return: false,
+            },
+        }
+    }
+
+    fn update_screen<B: Board>(
+        &self,
+        shared: &mut SharedResources<B>,
+        display: &mut Display<B::Screen>,
+        controls: &B::Controls,
+        feed: FeedRate,
+        pass: Option<u16>,
+    ) {
+        let (run_state, limit) = shared.stepper.lock(|s| (s.state(), s.hard_limit()));
+        let feed_speed = if controls.state().fast {
+            FeedSpeed::Fast
+        } else {
+            FeedSpeed::Slow
+        };
+
+        display.position(0, 0);
+        let rrpm = (self.rpm + 128) >> 8;
+
+        let _ = match pass {
+            Some(pass) => write!(
+                display,
+                "{: >4} RPM {: >2}/{: <2}",
+                rrpm, pass, self.auto.passes
+            ),
+            None => {
+                let llim = if self.limits.0.is_some() { " L" } else { "  " };
+                let rlim = if self.limits.1.is_some() { " R" } else { "  " };
+                write!(display, "{: >4} RPM{}{}  ", rrpm, llim, rlim)
+            }
+        };
+
+        display.position(0, 1);
+        let run_state = match run_state {
+            // Feed is engaged, just waiting for the spindle
+            StepperState::GearHold(dir) => StepperState::Running {
+                dir,
+                is_cutting_thread: false,
+            },
+            state => state,
+        };
+        let c = match (run_state, feed_speed) {
+            (
+                StepperState::Running {
+                    dir: Direction::Left,
+                    ..
+                },
+                FeedSpeed::Slow,
+            ) => font::LEFT,
+            (
+                StepperState::Running {
+                    dir: Direction::Right,
+                    ..
+                },
+                FeedSpeed::Slow,
+            ) => font::RIGHT,
+            (
+                StepperState::Running {
+                    dir: Direction::Left,
+                    ..
+                },
+                FeedSpeed::Fast,
+            ) => font::FAST_LEFT,
+            (
+                StepperState::Running {
+                    dir: Direction::Right,
+                    ..
+                },
+                FeedSpeed::Fast,
+            ) => font::FAST_RIGHT,
+            _ => ' ',
+        };
+        let _ = display.write_char(c);
+        let _ = write!(display, "{}", feed);
+        let _ = match self.error {
+            _ if limit.is_some() => write!(display, " Limit"),
+            Some(StepperError::StepgenError(StepgenError::TooSlow)) => {
+                write!(display, " Slow!")
+            }
+            Some(StepperError::StepgenError(StepgenError::TooFast)) => {
+                write!(display, " Fast!")
+            }
+            _ => write!(display, "      "),
+        };
+    }
+
+    fn handle_feed_rate(&mut self, event: Event, encoder: &mut impl QuadEncoder) -> FeedRate {
+        let proto = match self.feed {
+            FeedSpeed::Fast => self.fast_speed,
+            FeedSpeed::Slow => self.slow_speed,
+        };
+        // Encoder is off by one (as it starts from 0)
+        let mut feed = proto.with_rate((encoder.current() + 1) * proto.increment());
+        match event {
+            Event::Pressed(Button::Fast) => {
+                // Switch to fast IPM
+                self.feed = FeedSpeed::Fast;
+                self.slow_speed = feed;
+                feed = self.fast_speed;
+                encoder.set_current(feed.rate() / feed.increment() - 1);
+            }
+            Event::Unpressed(Button::Fast) => {
+                // Switch to slow IPM
+                self.feed = FeedSpeed::Slow;
+                self.fast_speed = feed;
+                feed = self.slow_speed;
+                encoder.set_current(feed.rate() / feed.increment() - 1);
+            }
+            _ => {}
+        }
+
+        feed
+    }
+
+    fn update_speed<B: Board>(
+        &mut self,
+        shared: &mut SharedResources<B>,
+        feed: FeedRate,
+        scale: Scale,
+        rpm: u32,
+    ) {
+        match feed.to_ratio(scale) {
+            Some(ratio) => {
+                // Follow the spindle position rather than its speed
+                if self.ratio != Some(ratio) {
+                    self.ratio = Some(ratio);
+                    self.error = shared
+                        .stepper
+                        .lock(|s| {
+                            s.set_gear_ratio(ratio);
+                            s.set_geared(true)
+                        })
+                        .err();
+                }
+            }
+            None => {
+                let speed = feed.to_speed(scale, rpm);
+                if self.ratio.take().is_some() || self.speed != speed {
+                    self.speed = speed;
+                    self.error = shared
+                        .stepper
+                        .lock(|s| s.set_geared(false).and_then(|_| s.set_speed(speed)))
+                        .err();
+                }
+            }
+        }
+    }
+
+    /// Start moving towards the target. Returns `false` if stepper refused to move, like when
+    /// moving towards the tripped hard limit switch; the error is shown on the screen.
+    fn start_movement<B: Board>(&mut self, shared: &mut SharedResources<B>, target: i32) -> bool {
+        let geared = self.ratio.is_some();
+        let result = shared.stepper.lock(|s| {
+            if geared {
+                s.gear_to(target)
+            } else {
+                s.move_to(target)
+            }
+        });
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+        result.is_ok()
+    }
+
+    fn update_movement<B: Board>(&mut self, event: Event, shared: &mut SharedResources<B>) {
+        let run_state = shared.stepper.lock(|s| s.state());
+        match (run_state, event) {
+            (StepperState::Stopped, Event::Pressed(Button::Left)) => {
+                // Use very low number for moving left
+                // FIXME: explicit support for -+INF?
+                let target = self.limits.0.unwrap_or(-1_000_000_000);
+                self.start_movement(shared, target);
+            }
+
+            (StepperState::Stopped, Event::Pressed(Button::Right)) => {
+                // Use very high number for moving right
+                let target = self.limits.1.unwrap_or(1_000_000_000);
+                self.start_movement(shared, target);
+            }
+
+            (
+                StepperState::Running {
+                    dir: Direction::Left,
+                    ..
+                },
+                Event::Unpressed(Button::Left),
+            )
+            | (
+                StepperState::Running {
+                    dir: Direction::Right,
+                    ..
+                },
+                Event::Unpressed(Button::Right),
+            )
+            | (StepperState::GearHold(Direction::Left), Event::Unpressed(Button::Left))
+            | (StepperState::GearHold(Direction::Right), Event::Unpressed(Button::Right)) => {
+                shared.stepper.lock(|s| s.stop())
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Start reciprocating feed if it is enabled, both limits are set and operator pressed "Left"
+    /// or "Right" button.
+    fn start_reciprocating<B: Board>(
+        &mut self,
+        event: Event,
+        shared: &mut SharedResources<B>,
+        rapid_speed: u32,
+    ) -> Option<Reciprocating<B::Stopwatch>> {
+        let (Some(left), Some(right)) = self.limits else {
+            return None;
+        };
+        if self.auto.passes == 0 || shared.stepper.lock(|s| s.state()) != StepperState::Stopped {
+            return None;
+        }
+        let (dir, target) = match event {
+            Event::Pressed(Button::Left) => (Direction::Left, left),
+            Event::Pressed(Button::Right) => (Direction::Right, right),
+            _ => return None,
+        };
+        if !self.start_movement(shared, target) {
+            return None;
+        }
+        Some(Reciprocating {
+            cut_dir: dir,
+            dir,
+            passes: 0,
+            dwell: None,
+            rapid_speed,
+        })
+    }
+
+    /// Run reciprocating feed: dwell at the end of each stroke and reverse. Returns `false` once
+    /// all passes are done or operator stopped the feed by pressing "Left" or "Right" button.
+    fn update_reciprocating<B: Board>(
+        &mut self,
+        rec: &mut Reciprocating<B::Stopwatch>,
+        event: Event,
+        shared: &mut SharedResources<B>,
+        feed: FeedRate,
+        scale: Scale,
+        rpm: u32,
+    ) -> bool {
+        if let Event::Pressed(Button::Left | Button::Right) = event {
+            shared.stepper.lock(|s| s.stop());
+            return false;
+        }
+
+        let is_return = self.auto.rapid_return && rec.dir != rec.cut_dir;
+        if !is_return {
+            self.update_speed(shared, feed, scale, rpm);
+        }
+        if shared.stepper.lock(|s| s.state()) != StepperState::Stopped {
+            return true;
+        }
+
+        // At the end of the stroke
+        let Some(ref mut dwell) = rec.dwell else {
+            if !is_return {
+                rec.passes += 1;
+            }
+            if rec.passes == self.auto.passes && (is_return || !self.auto.rapid_return) {
+                return false;
+            }
+            rec.dwell = Some(B::Stopwatch::start());
+            return true;
+        };
+        if dwell.elapsed_us() < u32::from(self.auto.dwell) * 100_000 {
+            return true;
+        }
+
+        // Reverse
+        rec.dwell = None;
+        rec.dir = match rec.dir {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        };
+        let target = match rec.dir {
+            Direction::Left => self.limits.0,
+            Direction::Right => self.limits.1,
+        };
+        let Some(target) = target else {
+            return false;
+        };
+        if self.auto.rapid_return && rec.dir != rec.cut_dir {
+            // Feed rate is re-applied for the next cutting stroke
+            let rapid_speed = rec.rapid_speed;
+            self.ratio = None;
+            self.speed = rapid_speed;
+            self.error = shared
+                .stepper
+                .lock(|s| {
+                    s.set_geared(false)?;
+                    s.set_speed(rapid_speed)?;
+                    s.move_to(target)
+                })
+                .err();
+            self.error.is_none()
+        } else {
+            self.update_speed(shared, feed, scale, rpm);
+            // Stopped at the tripped hard limit switch
+            self.start_movement(shared, target)
+        }
+    }
+
+    fn update_rpm(&mut self, rpm: u32) {
+        // Only capture if difference is big enough (more than .5%)
+        if self.rpm == 0 || rpm * 200 > self.rpm * 201 || rpm * 200 < self.rpm * 199 {
+            self.rpm = rpm;
+        }
+    }
+
+    fn run_feed<B: Board>(&mut self, r: &mut MenuResources<B>) -> NavStatus {
+        r.reload_stepper_settings();
+
+        let (scale, units, max_ipm, traversal) = r.shared.flash.lock(|f| {
+            (
+                settings::scale(f),
+                settings::units(f),
+                settings::MAX_IPM.read(f),
+                settings::TRAVERSAL.read(f),
+            )
+        });
+        let rapid_speed = FeedRate::InchesPerMinute(traversal).to_speed(scale, 0);
+        let mut reciprocating = None;
+
+        // Feed rates are kept in the units they were set in until units setting changes
+        self.slow_speed = self.slow_speed.to_units(units);
+        self.fast_speed = self.fast_speed.to_units(units);
+        let max_rate = FeedRate::InchesPerMinute(max_ipm).to_units(units);
+        let mut encoder = r.encoder.set_current_limit(
+            self.slow_speed.rate() / self.slow_speed.increment() - 1,
+            max_rate.rate() / max_rate.increment(),
+        );
+
+        r.display.clear();
+
+        let mut nav = Navigation::<B::Stopwatch>::new();
+        loop {
+            let event = r.controls.read_event();
+            let rpm = r.shared.hall.lock(|hall| hall.rpm());
+
+            let feed = self.handle_feed_rate(event, &mut *encoder);
+            match reciprocating {
+                Some(ref mut rec) => {
+                    let running =
+                        self.update_reciprocating(rec, event, &mut r.shared, feed, scale, rpm);
+                    if !running {
+                        reciprocating = None;
+                    }
+                }
+                None => {
+                    self.update_speed(&mut r.shared, feed, scale, rpm);
+                    reciprocating = self.start_reciprocating(event, &mut r.shared, rapid_speed);
+                    if reciprocating.is_none() {
+                        self.update_movement(event, &mut r.shared);
+                    }
+                }
+            }
+            self.update_rpm(rpm);
+            let limits = self.limits;
+            steputil::save_session(&mut r.shared, &mut r.session, |s| s.limits = limits);
+            let pass = reciprocating
+                .as_ref()
+                .map(|rec| (rec.passes + 1).min(self.auto.passes));
+            self.update_screen(&mut r.shared, r.display, r.controls, feed, pass);
+
+            if let Some(status) = nav.check(r.estop, event) {
+                self.stop_and_wait(&mut r.shared, r.display);
+                return status;
+            }
+        }
+    }
+
+    fn stop_and_wait<B: Board>(
+        &self,
+        shared: &mut SharedResources<B>,
+        display: &mut Display<B::Screen>,
+    ) {
+        shared.stepper.lock(|s| s.stop());
+        {
+            display.clear();
+            display.position(0, 0);
+            let _ = write!(display, "Stopping");
+            display.position(0, 1);
+            let _ = write!(display, "  ...");
+        }
+
+        steputil::wait_stopped(shared);
+    }
+
+    /// Configure automatic reciprocating feed: amount of passes (`0` disables it), dwell at each end
+    /// and the return speed. Returns `None` if operator exits.
+    fn capture_auto_reverse<B: Board>(&mut self, r: &mut MenuResources<B>) -> Option<()> {
+        const RETURNS: [&str; 2] = ["Feed", "Rapid"];
+
+        r.display.clear();
+        r.display.position(0, 0);
+        let _ = write!(r.display, "Auto Passes?");
+        let encoder = r.encoder.set_current_limit(self.auto.passes, 100);
+        self.auto.passes = wait_loop::<B, _>(r.controls, r.estop, || {
+            let passes = encoder.current();
+            r.display.position(0, 1);
+            let _ = match passes {
+                0 => write!(r.display, "Off             "),
+                passes => write!(r.display, "{: <16}", passes),
+            };
+            passes
+        })?;
+        drop(encoder);
+        if self.auto.passes == 0 {
+            return Some(());
+        }
+
+        r.display.clear();
+        r.display.position(0, 0);
+        let _ = write!(r.display, "Dwell?");
+        let encoder = r.encoder.set_current_limit(self.auto.dwell, 101);
+        self.auto.dwell = wait_loop::<B, _>(r.controls, r.estop, || {
+            let dwell = encoder.current();
+            r.display.position(0, 1);
+            let _ = write!(r.display, "{}.{} s          ", dwell / 10, dwell % 10);
+            dwell
+        })?;
+        drop(encoder);
+
+        let initial = usize::from(self.auto.rapid_return);
+        self.auto.rapid_return = run_selection_idx(r, "Return?", &RETURNS, initial)? == 1;
+        Some(())
+    }
+}
+
+impl MenuItem for FeedOperation {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>) {
+        if let Some(session) = r.session {
+            self.limits = session.limits;
+        }
+        loop {
+            // FIXME: make submenus?
+            if let NavStatus::Exit = self.run_feed(r) {
+                break;
+            }
+
+            let (left, status) = limits::capture_limit(r, "Left");
+            if let NavStatus::Exit = status {
+                break;
+            }
+
+            let (right, status) = limits::capture_limit(r, "Right");
+            if let NavStatus::Exit = status {
+                break;
+            }
+
+            self.limits = (left, right);
+            if left.is_some() && right.is_some() && self.capture_auto_reverse(r).is_none() {
+                break;
+            }
+        }
+    }
+
+    // fn is_active_by_default(&self, _r: &mut MenuResources<B>) -> bool {
+    //     true
+    // }
+}
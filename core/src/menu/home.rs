@@ -0,0 +1,142 @@
+use crate::feed::FeedRate;
+use crate::hal::{Board, Controls};
+use crate::menu::util::{printable_position, wait_loop, NavStatus, Navigation};
+use crate::menu::{steputil, MenuItem, MenuResources};
+use crate::settings;
+use crate::stepper::{State as StepperState, StepperError};
+use crate::units::{Scale, NM_PER_INCH};
+use core::fmt::Write;
+use rtic_core::Mutex;
+
+/// Distance to back off the home switch before re-approaching it slowly.
+const BACK_OFF: i64 = NM_PER_INCH / 10;
+
+/// Home switch is given up on if not found within this distance.
+const SEEK_DISTANCE: i64 = 40 * NM_PER_INCH;
+
+/// Speed of the slow re-approach.
+const SLOW_SPEED: FeedRate = FeedRate::InchesPerMinute(1);
+
+/// Homing cycle: seeks the home switch at the traversal speed, backs off, re-approaches it slowly
+/// and zeroes the position at the switch.
+pub struct HomingOperation {}
+
+enum Failure {
+    /// Operator stopped the homing
+    Aborted,
+    /// Home switch was not found within `SEEK_DISTANCE`
+    NotFound,
+    /// Home switch is still pressed after backing off
+    Stuck,
+    Stepper(StepperError),
+}
+
+impl From<StepperError> for Failure {
+    fn from(err: StepperError) -> Self {
+        Failure::Stepper(err)
+    }
+}
+
+impl HomingOperation {
+    pub fn new() -> HomingOperation {
+        HomingOperation {}
+    }
+}
+
+impl MenuItem for HomingOperation {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>) {
+        r.reload_stepper_settings();
+        let (scale, units, home_right) = r.shared.flash.lock(|f| {
+            (
+                settings::scale(f),
+                settings::units(f),
+                settings::HOME_RIGHT.read(f),
+            )
+        });
+
+        r.display.clear();
+        r.display.position(0, 0);
+        let _ = write!(r.display, "Homing...");
+        let result = home(r, scale, home_right);
+        // Restore the traversal speed
+        r.reload_stepper_settings();
+        steputil::save_session(&mut r.shared, &mut r.session, |_| {});
+
+        r.display.position(0, 0);
+        let message = match result {
+            Ok(()) => "Homed",
+            Err(Failure::Aborted) => return,
+            Err(Failure::NotFound) => "Switch not found",
+            Err(Failure::Stuck) => "Switch stuck!",
+            Err(Failure::Stepper(StepperError::HardLimit)) => "Hard limit!",
+            Err(Failure::Stepper(_)) => "Stepper error!",
+        };
+        let _ = write!(r.display, "{: <16}", message);
+        let position = r.shared.stepper.lock(|s| s.position());
+        r.display.position(0, 1);
+        let _ = write!(r.display, "{}", printable_position(position, scale, units));
+        wait_loop::<B, _>(r.controls, r.estop, || {});
+    }
+}
+
+fn home<B: Board>(r: &mut MenuResources<B>, scale: Scale, home_right: bool) -> Result<(), Failure> {
+    let sign = if home_right { 1 } else { -1 };
+    let back_off = sign * scale.to_steps(BACK_OFF) as i32;
+
+    // Already at the switch, need to get off it first
+    if r.shared.stepper.lock(|s| s.is_home_switch_pressed()) {
+        let position = r.shared.stepper.lock(|s| s.position());
+        move_to(r, position - back_off)?;
+    }
+
+    let home = seek(r, sign * scale.to_steps(SEEK_DISTANCE) as i32)?;
+    move_to(r, home - back_off)?;
+
+    let speed = SLOW_SPEED.to_speed(scale, 0);
+    r.shared.stepper.lock(|s| s.set_speed(speed))?;
+    let home = seek(r, 2 * back_off)?;
+
+    r.shared.stepper.lock(|s| {
+        let position = s.position();
+        s.set_position(position - home)
+    })?;
+    Ok(())
+}
+
+/// Move by the given distance until the home switch is pressed. Returns the position the switch
+/// was pressed at.
+fn seek<B: Board>(r: &mut MenuResources<B>, distance: i32) -> Result<i32, Failure> {
+    r.shared.stepper.lock(|s| {
+        let target = s.position() + distance;
+        s.seek_home(target)
+    })?;
+    wait_move(r)?;
+    r.shared
+        .stepper
+        .lock(|s| s.home_position())
+        .ok_or(Failure::NotFound)
+}
+
+/// Move to the given position, which should be off the home switch.
+fn move_to<B: Board>(r: &mut MenuResources<B>, target: i32) -> Result<(), Failure> {
+    r.shared.stepper.lock(|s| s.move_to(target))?;
+    wait_move(r)?;
+    if r.shared.stepper.lock(|s| s.is_home_switch_pressed()) {
+        return Err(Failure::Stuck);
+    }
+    Ok(())
+}
+
+/// Wait until stepper stops. Operator can stop it by a long press on the encoder.
+fn wait_move<B: Board>(r: &mut MenuResources<B>) -> Result<(), Failure> {
+    let mut nav = Navigation::<B::Stopwatch>::new();
+    while r.shared.stepper.lock(|s| s.state()) != StepperState::Stopped {
+        let event = r.controls.read_event();
+        if let Some(NavStatus::Exit) = nav.check(r.estop, event) {
+            r.shared.stepper.lock(|s| s.stop());
+            steputil::wait_stopped(&mut r.shared);
+            return Err(Failure::Aborted);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use crate::feed::FeedRate;
+use crate::hal::{Board, Button, Controls, Event, QuadEncoder};
+use crate::menu::util::{printable_position, NavStatus, Navigation};
+use crate::menu::{steputil, MenuResources};
+use crate::settings;
+use crate::units::div_round;
+use core::fmt::Write;
+use rtic_core::Mutex;
+
+pub fn capture_limit<B: Board>(
+    r: &mut MenuResources<B>,
+    label: &'static str,
+) -> (Option<i32>, NavStatus) {
+    let mut deltaenc = r.encoder.delta_encoder();
+    let mut limit: Option<i32> = None;
+    let mut nav = Navigation::<B::Stopwatch>::new();
+
+    let (scale, units) = r
+        .shared
+        .flash
+        .lock(|f| (settings::scale(f), settings::units(f)));
+
+    loop {
+        let pos = r.shared.stepper.lock(|s| s.position());
+        let delta = i32::from(deltaenc.delta());
+        let event = r.controls.read_event();
+
+        // Update screen
+        r.display.position(0, 0);
+        let _ = write!(r.display, "{}: ", label);
+        let _ = match limit {
+            None => write!(r.display, "Not Set  "),
+            Some(limit) => {
+                write!(r.display, "{}  ", printable_position(limit, scale, units))
+            }
+        };
+        r.display.position(0, 1);
+        let _ = write!(r.display, "{}    ", printable_position(pos, scale, units));
+
+        // Update stepper position; unit is one thou (or a hundredth of a millimeter). Target is
+        // snapped to the whole units, so rounding errors do not accumulate.
+        if delta != 0 {
+            // FIXME: hard-coded speed?...
+            let speed = FeedRate::InchesPerMinute(10).to_speed(scale, 0);
+            // FIXME: Traversal speed?
+            r.shared.stepper.lock(|s| s.set_speed(speed)).unwrap();
+            let resolution = units.resolution();
+            let current = div_round(scale.to_nanometers(i64::from(pos)), resolution);
+            let target = scale.to_steps((current + i64::from(delta)) * resolution) as i32;
+            // Refused if moving towards the tripped hard limit switch
+            if steputil::move_delta(target - pos, &mut r.shared).is_ok() {
+                // FIXME: print "MOVING..."
+                steputil::wait_stopped(&mut r.shared);
+            }
+        }
+
+        match event {
+            Event::Pressed(Button::Fast) if limit == Some(pos) => {
+                limit = None;
+            }
+            Event::Pressed(Button::Fast) => {
+                limit = Some(pos);
+            }
+            Event::Pressed(Button::Left) => {
+                // FIXME: feed...
+            }
+            _ => {}
+        }
+
+        // Position is saved once the jog is over (on a button press or leaving the screen) rather
+        // than on every encoder click, to spare the EEPROM
+        let status = nav.check(r.estop, event);
+        if status.is_some() || matches!(event, Event::Pressed(_)) {
+            steputil::save_session(&mut r.shared, &mut r.session, |_| {});
+        }
+        if let Some(status) = status {
+            return (limit, status);
+        }
+    }
+}
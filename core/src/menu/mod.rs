@@ -0,0 +1,316 @@
+use self::cross::CrossOperation;
+use self::feed::FeedOperation;
+use self::home::HomingOperation;
+use self::program::ProgramOperation;
+use self::thread::ThreadingOperation;
+use crate::feed::FeedRate;
+use crate::font;
+use crate::hal::Board;
+use crate::session::Session;
+use crate::settings;
+use crate::units::Units;
+use lcd::{Delay, Display, Hardware};
+use rtic_core::Mutex;
+
+/// Resources shared with the interrupt handlers.
+pub struct SharedResources<B: Board> {
+    pub stepper: B::Stepper,
+    pub cross: B::Cross,
+    pub hall: B::Hall,
+    pub flash: B::Flash,
+    pub program: B::Program,
+}
+
+pub struct MenuResources<'a, B: Board> {
+    pub encoder: &'a mut B::Encoder,
+    pub display: &'a mut Display<B::Screen>,
+    pub controls: &'a mut B::Controls,
+    pub estop: &'a mut B::EStop,
+    pub shared: SharedResources<B>,
+    /// Session last saved into the EEPROM, `None` until operator is asked to restore the previous
+    /// one (so it is not overwritten before that).
+    pub session: Option<Session>,
+}
+
+impl<B: Board> MenuResources<'_, B> {
+    /// Reload stepper settings from EEPROM. Sets acceleration, reverse flag, phase correction gain,
+    /// backlash and speed. Speed is set to the default traversal speed.
+    fn reload_stepper_settings(&mut self) {
+        let (reversed, acceleration, speed, phase_gain, backlash) =
+            self.shared.flash.lock(|flash| {
+                let reversed = settings::IS_REVERSED.read(flash);
+                let acceleration = (u32::from(settings::ACCELERATION.read(flash))
+                    * u32::from(settings::MICROSTEPS.read(flash)))
+                    << 8;
+                let scale = settings::scale(flash);
+                let speed =
+                    FeedRate::InchesPerMinute(settings::TRAVERSAL.read(flash)).to_speed(scale, 0);
+                let phase_gain = u32::from(settings::PHASE_GAIN.read(flash));
+                let backlash = scale
+                    .to_steps(i64::from(settings::BACKLASH.read(flash)) * Units::Inch.resolution())
+                    as u32;
+                (reversed, acceleration, speed, phase_gain, backlash)
+            });
+
+        self.shared.stepper.lock(|s| {
+            s.set_reversed(reversed);
+            s.set_phase_gain(phase_gain);
+            s.set_backlash(backlash);
+            s.set_speed(speed).unwrap();
+            s.set_acceleration(acceleration).unwrap();
+        });
+    }
+
+    /// Reload cross-slide settings from EEPROM. Sets reverse flag, acceleration and speed. Speed is
+    /// set to the default traversal speed.
+    fn reload_cross_settings(&mut self) {
+        let (reversed, acceleration, speed) = self.shared.flash.lock(|flash| {
+            let reversed = settings::CROSS_REVERSED.read(flash);
+            let acceleration = (u32::from(settings::CROSS_ACCELERATION.read(flash))
+                * u32::from(settings::CROSS_MICROSTEPS.read(flash)))
+                << 8;
+            let scale = settings::cross_scale(flash);
+            let speed =
+                FeedRate::InchesPerMinute(settings::TRAVERSAL.read(flash)).to_speed(scale, 0);
+            (reversed, acceleration, speed)
+        });
+
+        self.shared.cross.lock(|s| {
+            s.set_reversed(reversed);
+            s.set_speed(speed).unwrap();
+            s.set_acceleration(acceleration).unwrap();
+        });
+    }
+
+    /// Offer to restore the session saved before the power cycle. Does nothing if operator was
+    /// already asked.
+    fn restore_session(&mut self) {
+        if self.session.is_some() {
+            return;
+        }
+        let saved = self.shared.flash.lock(Session::load);
+        let session = match saved {
+            Some(saved)
+                if util::run_selection_idx(self, "Restore session?", &["> Yes", "> No"], 0)
+                    == Some(0) =>
+            {
+                self.shared
+                    .stepper
+                    .lock(|s| s.set_position(saved.position))
+                    .unwrap();
+                saved
+            }
+            Some(_) => {
+                self.shared.flash.lock(Session::clear).unwrap();
+                Session::default()
+            }
+            None => Session::default(),
+        };
+        self.session = Some(session);
+    }
+}
+
+/// Run once at startup, before the main menu is selected.
+pub fn startup<B: Board>(r: &mut MenuResources<B>) {
+    profiles::select_at_startup(r);
+}
+
+pub fn init_display<H: Hardware + Delay>(lcd: &mut Display<H>) {
+    lcd.init(lcd::FunctionLine::Line2, lcd::FunctionDots::Dots5x8);
+    lcd.display(
+        lcd::DisplayMode::DisplayOn,
+        lcd::DisplayCursor::CursorOff,
+        lcd::DisplayBlink::BlinkOff,
+    );
+    font::upload_characters(lcd);
+    lcd.entry_mode(
+        lcd::EntryModeDirection::EntryRight,
+        lcd::EntryModeShift::NoShift,
+    );
+}
+
+#[macro_use]
+mod util;
+mod cross;
+mod feed;
+mod home;
+mod limits;
+mod profiles;
+mod program;
+mod steputil;
+mod thread;
+
+/// Trait for a generic menu item
+pub trait MenuItem {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>);
+}
+
+#[derive(Clone, Copy)]
+enum SettingsItem {
+    /// Machine profiles
+    Profiles,
+    Setting(settings::RawSetting),
+    /// Revert all settings to their defaults
+    FactoryReset,
+}
+
+impl core::fmt::Display for SettingsItem {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            SettingsItem::Profiles => f.pad("Profiles"),
+            SettingsItem::Setting(setting) => setting.fmt(f),
+            SettingsItem::FactoryReset => f.pad("Factory Reset"),
+        }
+    }
+}
+
+pub struct SettingsMenuTemplate<const N: usize> {
+    items: [SettingsItem; N],
+}
+
+/// Profiles, all settings and the factory reset.
+pub type SettingsMenu = SettingsMenuTemplate<{ settings::ALL.len() + 2 }>;
+
+impl<const N: usize> MenuItem for SettingsMenuTemplate<N> {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>) {
+        let mut initial = 0;
+        while let Some(pos) =
+            crate::menu::util::run_selection_idx(r, "-- Settings --", &self.items, initial)
+        {
+            match self.items[pos] {
+                SettingsItem::Profiles => profiles::run_profiles(r),
+                SettingsItem::Setting(setting) => crate::menu::util::run_setting(r, &setting),
+                SettingsItem::FactoryReset => factory_reset(r),
+            }
+            initial = pos;
+        }
+    }
+}
+
+/// Revert all settings to their defaults (and forget the saved session), after confirmation.
+fn factory_reset<B: Board>(r: &mut MenuResources<B>) {
+    let confirmed =
+        crate::menu::util::run_selection_idx(r, "Reset all?", &["> No", "> Yes"], 0) == Some(1);
+    if confirmed {
+        r.shared.flash.lock(settings::factory_reset).unwrap();
+        if r.session.is_some() {
+            r.session = Some(Session::default());
+        }
+    }
+}
+
+impl SettingsMenu {
+    pub fn new() -> SettingsMenu {
+        let mut items = [SettingsItem::FactoryReset; settings::ALL.len() + 2];
+        items[0] = SettingsItem::Profiles;
+        for (item, setting) in items[1..].iter_mut().zip(settings::ALL) {
+            *item = SettingsItem::Setting(setting);
+        }
+        SettingsMenu { items }
+    }
+}
+
+impl Default for SettingsMenu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct LatheMenu {
+    feed: FeedOperation,
+    thread: ThreadingOperation,
+    cross: CrossOperation,
+    program: ProgramOperation,
+    home: HomingOperation,
+    settings: SettingsMenu,
+}
+
+impl LatheMenu {
+    pub fn new() -> LatheMenu {
+        LatheMenu {
+            feed: FeedOperation::new(true),
+            thread: ThreadingOperation::new(),
+            cross: CrossOperation::new(),
+            program: ProgramOperation::new(),
+            home: HomingOperation::new(),
+            settings: SettingsMenu::new(),
+        }
+    }
+}
+
+impl Default for LatheMenu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MenuItem for LatheMenu {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>) {
+        const LABELS: [&str; 6] = [
+            "> Power Feed",
+            "> Threading",
+            "> Cross Slide",
+            "> G-code",
+            "> Homing",
+            "> Settings",
+        ];
+
+        r.restore_session();
+        // Default menu item
+        self.thread.run(r);
+        while let Some(pos) = crate::menu::util::run_selection_idx(r, "-- Select --", &LABELS, 0) {
+            match pos {
+                0 => self.feed.run(r),
+                1 => self.thread.run(r),
+                2 => self.cross.run(r),
+                3 => self.program.run(r),
+                4 => self.home.run(r),
+                5 => self.settings.run(r),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+pub struct MillMenu {
+    feed: FeedOperation,
+    program: ProgramOperation,
+    home: HomingOperation,
+    settings: SettingsMenu,
+}
+
+impl MillMenu {
+    pub fn new() -> MillMenu {
+        MillMenu {
+            feed: FeedOperation::new(false),
+            program: ProgramOperation::new(),
+            home: HomingOperation::new(),
+            settings: SettingsMenu::new(),
+        }
+    }
+}
+
+impl Default for MillMenu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MenuItem for MillMenu {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>) {
+        const LABELS: [&str; 4] = ["> Power Feed", "> G-code", "> Homing", "> Settings"];
+
+        r.restore_session();
+        // Default menu item
+        self.feed.run(r);
+        while let Some(pos) = crate::menu::util::run_selection_idx(r, "-- Select --", &LABELS, 0) {
+            match pos {
+                0 => self.feed.run(r),
+                1 => self.program.run(r),
+                2 => self.home.run(r),
+                3 => self.settings.run(r),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
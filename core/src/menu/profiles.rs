@@ -0,0 +1,135 @@
+use crate::hal::{Board, QuadEncoder};
+use crate::menu::util::{run_selection_idx, wait_loop};
+use crate::menu::MenuResources;
+use crate::settings::{self, ProfileName, RawSetting, PROFILES, PROFILE_NAME_LEN};
+use core::fmt::Write;
+use rtic_core::Mutex;
+
+/// Characters profile names are made of
+const CHARSET: &[u8] = b" ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-";
+
+/// Settings which are only applied at startup.
+const STARTUP_SETTINGS: [RawSetting; 3] = [
+    settings::IS_LATHE.raw(),
+    settings::SPINDLE_ENCODER.raw(),
+    settings::HARD_LIMITS.raw(),
+];
+
+/// Select, copy and rename machine profiles.
+pub fn run_profiles<B: Board>(r: &mut MenuResources<B>) {
+    const LABELS: [&str; 4] = ["> Select", "> Copy From", "> Rename", "> Ask at Boot"];
+
+    let mut initial = 0;
+    while let Some(pos) = run_selection_idx(r, "-- Profiles --", &LABELS, initial) {
+        match pos {
+            0 => select_profile(r, &STARTUP_SETTINGS),
+            1 => copy_profile(r),
+            2 => rename_profile(r),
+            3 => {
+                let prompt = r.shared.flash.lock(settings::profile_prompt);
+                if let Some(pos) =
+                    run_selection_idx(r, "Ask at Boot?", &["> No", "> Yes"], usize::from(prompt))
+                {
+                    r.shared
+                        .flash
+                        .lock(|f| settings::set_profile_prompt(f, pos == 1))
+                        .unwrap();
+                }
+            }
+            _ => unreachable!(),
+        }
+        initial = pos;
+    }
+}
+
+/// Ask operator to select the profile at startup, if enabled. Machine type is read after this, so
+/// it does not need a restart.
+pub fn select_at_startup<B: Board>(r: &mut MenuResources<B>) {
+    if r.shared.flash.lock(settings::profile_prompt) {
+        select_profile(r, &STARTUP_SETTINGS[1..]);
+    }
+}
+
+/// Select the active profile. Operator is asked to restart if any of the given settings differ
+/// between the previous and the new profile.
+fn select_profile<B: Board>(r: &mut MenuResources<B>, startup: &[RawSetting]) {
+    let (active, names) = r
+        .shared
+        .flash
+        .lock(|f| (settings::profile(f), profile_names(f)));
+    let Some(selected) = run_selection_idx(r, "Profile?", &names, usize::from(active)) else {
+        return;
+    };
+    let selected = selected as u16;
+    let needs_restart = r.shared.flash.lock(|f| {
+        settings::set_profile(f, selected).unwrap();
+        startup
+            .iter()
+            .any(|s| s.read_in(f, active) != s.read_in(f, selected))
+    });
+    if needs_restart {
+        r.display.clear();
+        r.display.position(0, 0);
+        let _ = write!(r.display, "Restart to apply");
+        wait_loop::<B, _>(r.controls, r.estop, || {});
+    }
+}
+
+/// Copy all settings of another profile into the active one.
+fn copy_profile<B: Board>(r: &mut MenuResources<B>) {
+    let (active, names) = r
+        .shared
+        .flash
+        .lock(|f| (settings::profile(f), profile_names(f)));
+    let Some(from) = run_selection_idx(r, "Copy From?", &names, usize::from(active)) else {
+        return;
+    };
+    let from = from as u16;
+    if from != active && run_selection_idx(r, "Overwrite?", &["> No", "> Yes"], 0) == Some(1) {
+        r.shared
+            .flash
+            .lock(|f| settings::copy_profile(f, from, active))
+            .unwrap();
+    }
+}
+
+/// Edit name of the active profile, character by character. Long press on the encoder cancels.
+fn rename_profile<B: Board>(r: &mut MenuResources<B>) {
+    let (active, mut name) = r.shared.flash.lock(|f| {
+        let active = settings::profile(f);
+        (active, settings::profile_name(f, active))
+    });
+    r.display.clear();
+    for pos in 0..PROFILE_NAME_LEN {
+        let initial = CHARSET.iter().position(|c| *c == name.0[pos]).unwrap_or(0);
+        let encoder = r
+            .encoder
+            .set_current_limit(initial as u16, CHARSET.len() as u16);
+        let result = wait_loop::<B, _>(r.controls, r.estop, || {
+            name.0[pos] = CHARSET[usize::from(encoder.current())];
+            r.display.position(0, 0);
+            for c in name.0 {
+                let _ = r.display.write_char(char::from(c));
+            }
+            r.display.position(0, 1);
+            let _ = write!(r.display, "{: >width$}          ", "^", width = pos + 1);
+        });
+        if result.is_none() {
+            return;
+        }
+    }
+    r.shared
+        .flash
+        .lock(|f| settings::set_profile_name(f, active, &name))
+        .unwrap();
+}
+
+fn profile_names<S: settings::SettingsStorage>(
+    storage: &mut S,
+) -> [ProfileName; PROFILES as usize] {
+    let mut names = [ProfileName([b' '; PROFILE_NAME_LEN]); PROFILES as usize];
+    for (profile, name) in names.iter_mut().enumerate() {
+        *name = settings::profile_name(storage, profile as u16);
+    }
+    names
+}
@@ -0,0 +1,185 @@
+use crate::feed::FeedRate;
+use crate::gcode::{Action, Error as GcodeError, Interpreter};
+use crate::hal::{Board, Button, Controls, Event, SpindleSensor, Stopwatch};
+use crate::menu::util::{printable_position, NavStatus, Navigation};
+use crate::menu::{steputil, MenuItem, MenuResources};
+use crate::settings;
+use crate::stepper::{State as StepperState, StepperError};
+use crate::threads::Phase;
+use core::fmt;
+use core::fmt::Write;
+use rtic_core::Mutex;
+use stepgen::Error as StepgenError;
+
+/// Executes G-code program received over the serial console, block by block. Blocks are only
+/// accepted while this operation is running.
+pub struct ProgramOperation {}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    /// Waiting for the next block
+    Waiting,
+    /// Executing the block
+    Running,
+    /// Waiting for the operator to continue (`M0`)
+    Paused,
+    /// Program ended (`M2`)
+    Done,
+    /// Program was stopped, operator needs to acknowledge the error
+    Failed(Failure),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Failure {
+    Gcode(GcodeError),
+    Stepper(StepperError),
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Waiting => f.pad("G-code: waiting"),
+            Status::Running => f.pad("G-code: running"),
+            Status::Paused => f.pad("Paused. Resume?"),
+            Status::Done => f.pad("G-code: done"),
+            Status::Failed(Failure::Gcode(err)) => err.fmt(f),
+            Status::Failed(Failure::Stepper(StepperError::StepgenError(StepgenError::TooSlow))) => {
+                f.pad("Too slow!")
+            }
+            Status::Failed(Failure::Stepper(StepperError::StepgenError(StepgenError::TooFast))) => {
+                f.pad("Too fast!")
+            }
+            Status::Failed(Failure::Stepper(StepperError::HardLimit)) => f.pad("Hard limit!"),
+            Status::Failed(Failure::Stepper(_)) => f.pad("Stepper error!"),
+        }
+    }
+}
+
+impl ProgramOperation {
+    pub fn new() -> ProgramOperation {
+        ProgramOperation {}
+    }
+}
+
+impl MenuItem for ProgramOperation {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>) {
+        r.reload_stepper_settings();
+        let (scale, units, traversal, max_ipm) = r.shared.flash.lock(|f| {
+            (
+                settings::scale(f),
+                settings::units(f),
+                settings::TRAVERSAL.read(f),
+                settings::MAX_IPM.read(f),
+            )
+        });
+        let mut interpreter = Interpreter::new(
+            scale,
+            units,
+            FeedRate::InchesPerMinute(traversal).to_speed(scale, 0),
+            FeedRate::InchesPerMinute(max_ipm).to_speed(scale, 0),
+        );
+
+        let mut status = Status::Waiting;
+        // Time dwell started at and its duration, in milliseconds
+        let mut dwell: Option<(B::Stopwatch, u32)> = None;
+        let mut nav = Navigation::<B::Stopwatch>::new();
+        r.shared.program.lock(|p| p.start());
+        r.display.clear();
+        loop {
+            // We use `Fast` button for continuing the program, same as `Select`
+            let event = r.controls.read_event();
+            match nav.check(r.estop, event) {
+                Some(NavStatus::Exit) => break,
+                Some(NavStatus::Select) => status = resume(r, &mut interpreter, status),
+                None if matches!(event, Event::Pressed(Button::Fast)) => {
+                    status = resume(r, &mut interpreter, status)
+                }
+                _ => {}
+            }
+
+            if let Some((ref mut started, duration)) = dwell {
+                if started.elapsed_us() >= duration * 1000 {
+                    dwell = None;
+                }
+            }
+
+            steputil::save_session(&mut r.shared, &mut r.session, |_| {});
+            let (state, position) = r.shared.stepper.lock(|s| (s.state(), s.position()));
+            let is_ready = matches!(status, Status::Waiting | Status::Running | Status::Done)
+                && state == StepperState::Stopped
+                && dwell.is_none();
+            if is_ready {
+                match r.shared.program.lock(|p| p.pop()) {
+                    Some(block) => {
+                        status = match interpreter.plan(&block, position) {
+                            Ok(Some(Action::Dwell(duration))) => {
+                                dwell = Some((B::Stopwatch::start(), duration));
+                                Status::Running
+                            }
+                            Ok(Some(Action::Pause)) => Status::Paused,
+                            Ok(Some(Action::End)) => Status::Done,
+                            Ok(Some(action)) => match execute(r, action) {
+                                Ok(()) => Status::Running,
+                                Err(err) => Status::Failed(Failure::Stepper(err)),
+                            },
+                            Ok(None) => Status::Running,
+                            Err(err) => Status::Failed(Failure::Gcode(err)),
+                        };
+                        if let Status::Failed(_) = status {
+                            r.shared.program.lock(|p| p.stop());
+                        }
+                    }
+                    None if status == Status::Running => status = Status::Waiting,
+                    None => {}
+                }
+            }
+
+            r.display.position(0, 0);
+            let _ = write!(r.display, "{: <16.16}", status);
+            r.display.position(0, 1);
+            let _ = write!(
+                r.display,
+                "{: <16}",
+                printable_position(position, scale, units)
+            );
+        }
+
+        r.shared.program.lock(|p| p.stop());
+        r.shared.stepper.lock(|s| s.stop());
+        steputil::wait_stopped(&mut r.shared);
+    }
+}
+
+/// Operator continues the paused program or acknowledges the error.
+fn resume<B: Board>(
+    r: &mut MenuResources<B>,
+    interpreter: &mut Interpreter,
+    status: Status,
+) -> Status {
+    match status {
+        Status::Paused => Status::Running,
+        Status::Failed(_) => {
+            interpreter.reset();
+            r.shared.program.lock(|p| p.start());
+            Status::Waiting
+        }
+        status => status,
+    }
+}
+
+/// Start the stepper motor move.
+fn execute<B: Board>(r: &mut MenuResources<B>, action: Action) -> Result<(), StepperError> {
+    match action {
+        Action::Move { target, speed } => r.shared.stepper.lock(|s| {
+            s.set_speed(speed)?;
+            s.move_to(target)
+        }),
+        Action::Thread { target, ratio } => {
+            let rpm = r.shared.hall.lock(|hall| hall.rpm());
+            r.shared
+                .stepper
+                .lock(|s| s.thread_start(target, ratio, Phase::new(0), rpm))
+        }
+        Action::Dwell(_) | Action::Pause | Action::End => Ok(()),
+    }
+}
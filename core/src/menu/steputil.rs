@@ -0,0 +1,76 @@
+use crate::hal::Board;
+use crate::menu::SharedResources;
+use crate::session::Session;
+use crate::stepper;
+use crate::stepper::{Stepper, StepperError};
+use rtic_core::Mutex;
+
+pub fn move_delta<B: Board>(delta: i32, r: &mut SharedResources<B>) -> Result<(), StepperError> {
+    r.stepper.lock(|s| {
+        let target = s.position() + delta;
+        s.move_to(target)
+    })
+}
+
+/// Move to the given position and wait until stepper stops. Returns `false` if the move was
+/// refused, like when moving towards the tripped hard limit switch.
+pub fn jog_to<B: Board>(target: i32, r: &mut SharedResources<B>) -> bool {
+    let is_moving = r.stepper.lock(|s| s.move_to(target)).is_ok();
+    if is_moving {
+        wait_stopped(r);
+    }
+    is_moving
+}
+
+/// Update the session and save it into EEPROM, along with the current stepper position. Only saved
+/// while stepper is stopped, and only if anything changed.
+pub fn save_session<B: Board>(
+    r: &mut SharedResources<B>,
+    session: &mut Option<Session>,
+    update: impl FnOnce(&mut Session),
+) {
+    let Some(mut updated) = *session else {
+        return;
+    };
+    update(&mut updated);
+    store_session(r, session, updated);
+}
+
+// Not inlined into every `save_session` caller, to save flash
+#[inline(never)]
+fn store_session<B: Board>(
+    r: &mut SharedResources<B>,
+    session: &mut Option<Session>,
+    mut updated: Session,
+) {
+    let (state, position) = r.stepper.lock(|s| (s.state(), s.position()));
+    if state != stepper::State::Stopped {
+        return;
+    }
+    updated.position = position;
+    if *session != Some(updated) {
+        r.flash.lock(|f| updated.save(f)).unwrap();
+        *session = Some(updated);
+    }
+}
+
+pub fn wait_stopped<B: Board>(r: &mut SharedResources<B>) {
+    wait_axis_stopped::<B>(&mut r.stepper);
+}
+
+/// Wait until the stepper motor of the given axis stops.
+pub fn wait_axis_stopped<B: Board>(stepper: &mut impl Mutex<T = Stepper<B::Driver>>) {
+    let mut is_stopped = false;
+    while !is_stopped {
+        is_stopped = stepper.lock(|s| {
+            if let stepper::State::Stopped = s.state() {
+                return true;
+            }
+            // Enter WFI while we block stepper interrupt (via lock above), to avoid race conditions.
+            // We should still wake up if interrupt happens (but it won't be handled until we exit
+            // the claim block).
+            B::wait_for_interrupt();
+            false
+        });
+    }
+}
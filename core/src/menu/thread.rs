@@ -0,0 +1,601 @@
+use crate::gearing::Ratio;
+use crate::hal::{Board, QuadEncoder, SpindleSensor};
+use crate::menu::util::{check_move, printable_position, wait_loop};
+use crate::menu::{steputil, MenuItem, MenuResources, SharedResources};
+use crate::stepper::{Direction, StepperError};
+use crate::threads::{Hand, InfeedSchedule, Phase, ThreadForm, ThreadSize};
+use crate::units::{div_round, Scale, Units, NM_PER_INCH, NM_PER_MM};
+use crate::{settings, stepper};
+use core::fmt::Write;
+use rtic_core::Mutex;
+use stepgen::Error as StepgenError;
+
+pub struct ThreadingOperation {
+    thread: ThreadSize,
+    /// Phase for starting the thread cutting, from `0` to `360`. Defined as amount of
+    /// degrees we should offset our thread start. `180` would mean thread will have exactly half
+    /// pitch offset.
+    phase: u16,
+    /// Amount of starts of the thread; lead is `starts` times the thread pitch
+    starts: u16,
+    hand: Hand,
+    /// Direction carriage moves in while cutting
+    direction: Direction,
+    /// Start to cut with the next pass, from `0` to `starts - 1`
+    start: u16,
+    /// Next pass of the infeed schedule, counting from `1`
+    pass: u16,
+    /// Shift of the picked up thread, in `1 / 65536` of the revolution
+    shift: u16,
+    /// End of the thread position
+    shoulder_pos: i32,
+    /// Retraction position
+    retract_pos: i32,
+}
+
+impl ThreadingOperation {
+    pub fn new() -> ThreadingOperation {
+        ThreadingOperation {
+            thread: ThreadSize::Tpi(18),
+            phase: 0,
+            starts: 1,
+            hand: Hand::Right,
+            direction: Direction::Left,
+            start: 0,
+            pass: 1,
+            shift: 0,
+            shoulder_pos: 0,
+            retract_pos: 0,
+        }
+    }
+}
+
+impl MenuItem for ThreadingOperation {
+    fn run<B: Board>(&mut self, r: &mut MenuResources<B>) {
+        self.run_impl(r);
+        // Cross-slide should not keep following the carriage
+        r.shared.cross.lock(|s| s.stop());
+    }
+}
+
+impl ThreadingOperation {
+    fn run_impl<B: Board>(&mut self, r: &mut MenuResources<B>) -> Option<()> {
+        r.reload_stepper_settings();
+        let (scale, units) = r
+            .shared
+            .flash
+            .lock(|f| (settings::scale(f), settings::units(f)));
+
+        self.thread = select_thread_size(r)?;
+        self.starts = select_starts(r, self.starts)?;
+        self.start = 0;
+        let form = select_form(r, self.thread)?;
+        let schedule = InfeedSchedule::new(form.depth(self.thread.pitch()));
+        self.pass = 1;
+
+        // Infeed is shown along the compound slide
+        r.display.clear();
+        r.display.position(0, 0);
+        let angle = form.compound_angle();
+        let _ = write!(r.display, "Compound {}.{}deg", angle / 10, angle % 10);
+        wait_loop::<B, _>(r.controls, r.estop, || {})?;
+
+        self.hand = super::util::run_selection(r, "Thread Hand?", &[Hand::Right, Hand::Left], 0)
+            .copied()?;
+        self.direction = select_direction(r, self.hand)?;
+        let reversed = self.hand.is_spindle_reversed(self.direction);
+        if reversed {
+            r.display.clear();
+            r.display.position(0, 0);
+            let _ = write!(r.display, "Spindle reverse!");
+            wait_loop::<B, _>(r.controls, r.estop, || {})?;
+        }
+        // Cross-slide follows the carriage to cut the taper, moving out toward the wide end. Its
+        // current position is the one at the shoulder.
+        let taper = match self.thread.taper() {
+            Some(taper) => {
+                // Radius grows toward the wide end, both on the external and on the internal thread
+                let wide = select_end(r, "Wide End?", Direction::Left)?;
+                r.reload_cross_settings();
+                let cross_scale = r.shared.flash.lock(settings::cross_scale);
+                // Taper is given on the diameter
+                let ratio = cross_scale.follow_ratio(scale, 2 * u32::from(taper));
+                let sign = if wide == self.direction { 1 } else { -1 };
+                Some((ratio, sign, r.shared.cross.lock(|s| s.position())))
+            }
+            None => None,
+        };
+        // Retract position is on the side the cut starts from, so carriage accelerates before
+        // reaching the work
+        let away = match self.direction {
+            Direction::Left => 1,
+            Direction::Right => -1,
+        };
+
+        // Shoulder and retract positions are kept from the previous thread (or session)
+        let saved = match r.session.and_then(|s| s.thread) {
+            Some(thread)
+                if super::util::run_selection_idx(r, "Shoulder?", &["> Saved", "> New"], 0)?
+                    == 0 =>
+            {
+                Some(thread)
+            }
+            _ => None,
+        };
+        if let Some((shoulder_pos, retract_pos)) = saved {
+            self.shoulder_pos = shoulder_pos;
+            self.retract_pos = shoulder_pos + away * (retract_pos - shoulder_pos).abs();
+        } else {
+            // FIXME: allow using feed to go to the desired position precisely?
+            r.display.clear();
+            r.display.position(0, 0);
+            let _ = write!(r.display, "At shoulder?    ");
+            wait_loop::<B, _>(r.controls, r.estop, || {});
+
+            self.shoulder_pos = r.shared.stepper.lock(|s| s.position());
+            self.retract_pos = capture_retract_position(r, scale, units, away)?;
+        }
+        let thread = Some((self.shoulder_pos, self.retract_pos));
+        steputil::save_session(&mut r.shared, &mut r.session, |s| s.thread = thread);
+
+        // Lead is the pitch times the amount of starts
+        let pitch = self.thread.to_ratio(scale);
+        let lead = Ratio::new(pitch.numerator * u32::from(self.starts), pitch.denominator);
+
+        // Existing thread (like the one interrupted mid-pass) could be picked up
+        self.shift = 0;
+        if super::util::run_selection_idx(r, "Thread?", &["> New", "> Pick Up"], 0)? == 1 {
+            let (start, target) = (self.retract_pos, self.shoulder_pos);
+            self.shift = pick_up_thread(r, scale, units, lead, start, target, reversed)?;
+            self.phase = 0;
+        }
+
+        // Main thread cutting thread
+        loop {
+            // Cross-slide finishes following the carriage and goes back along the taper
+            if let Some((ratio, sign, shoulder)) = taper {
+                let distance = ratio.steps(self.shoulder_pos.abs_diff(self.retract_pos)) as i32;
+                r.shared.cross.lock(|s| s.stop());
+                steputil::wait_axis_stopped::<B>(&mut r.shared.cross);
+                let result = r
+                    .shared
+                    .cross
+                    .lock(|s| s.move_to(shoulder - sign * distance));
+                check_move(r, result)?;
+            }
+
+            // Retract to the starting position (if needed)
+            if r.shared.stepper.lock(|s| s.position()) != self.retract_pos {
+                r.display.position(0, 0);
+                let _ = write!(r.display, "Retracting...   ");
+                let result = r.shared.stepper.lock(|s| s.move_to(self.retract_pos));
+                check_move(r, result)?;
+                steputil::wait_stopped(&mut r.shared);
+            }
+            steputil::wait_axis_stopped::<B>(&mut r.shared.cross);
+            steputil::save_session(&mut r.shared, &mut r.session, |_| {});
+
+            // Cutting thread. Every start is cut through the whole infeed schedule, extra passes
+            // go over every start in turn.
+            r.display.clear();
+            r.display.position(0, 0);
+            let (start, passes) = (self.start + 1, schedule.passes());
+            let infeed = if self.pass > passes {
+                if self.starts > 1 {
+                    let _ = write!(r.display, "Extra S{} cut?", start);
+                } else {
+                    let _ = write!(r.display, "Extra pass cut?");
+                }
+                0
+            } else {
+                if self.starts > 1 {
+                    let _ = write!(r.display, "S{} P{}/{} cut?", start, self.pass, passes);
+                } else {
+                    let _ = write!(r.display, "P{}/{} cut?", self.pass, passes);
+                }
+                form.compound_infeed(schedule.infeed(self.pass))
+            };
+            let infeed = Infeed {
+                nanometers: infeed,
+                units,
+            };
+            self.phase = capture_phase(r, self.phase, Some(infeed))?;
+
+            // Carriage should get up to the speed before reaching the work
+            let (shoulder, retract) = (self.shoulder_pos, self.retract_pos);
+            if let Some(extension) = check_ramp(r, scale, units, lead, shoulder, retract)? {
+                self.retract_pos += away * extension;
+                let thread = Some((self.shoulder_pos, self.retract_pos));
+                steputil::save_session(&mut r.shared, &mut r.session, |s| s.thread = thread);
+                continue;
+            }
+
+            let phase = Phase::new(self.phase)
+                .with_start(self.start, self.starts)
+                .with_shift(self.shift);
+            if let Some((ratio, _, shoulder)) = taper {
+                let retract = self.retract_pos;
+                let result = r
+                    .shared
+                    .cross
+                    .lock(|s| s.follow_to(shoulder, retract, ratio));
+                check_move(r, result)?;
+            }
+            cut_thread_to(r, lead, self.shoulder_pos, phase);
+            steputil::save_session(&mut r.shared, &mut r.session, |_| {});
+            if self.pass < schedule.passes() {
+                self.pass += 1;
+            } else if self.pass == schedule.passes() && self.start + 1 < self.starts {
+                // Start is complete, next one is cut from the surface again
+                self.start += 1;
+                self.pass = 1;
+                r.display.clear();
+                r.display.position(0, 0);
+                let _ = write!(r.display, "Next start {}/{}", self.start + 1, self.starts);
+                r.display.position(0, 1);
+                let _ = write!(r.display, "Infeed to zero!");
+                wait_loop::<B, _>(r.controls, r.estop, || {})?;
+                r.display.clear();
+            } else {
+                self.pass = schedule.passes() + 1;
+                self.start = (self.start + 1) % self.starts;
+            }
+
+            // Ask to retract back
+            r.display.position(0, 0);
+            let _ = write!(r.display, "Retract?        ");
+            self.phase = capture_phase(r, self.phase, None)?;
+        }
+    }
+}
+
+fn cut_thread_to<B: Board>(r: &mut MenuResources<B>, ratio: Ratio, position: i32, phase: Phase) {
+    while let Err(err) = r.shared.stepper.lock(|s| {
+        s.thread_start(
+            position,
+            ratio,
+            phase,
+            r.shared.hall.lock(|hall| hall.rpm()),
+        )
+    }) {
+        r.display.position(0, 0);
+        let _ = match err {
+            StepperError::StepgenError(StepgenError::TooSlow) => {
+                write!(r.display, "RPM is too low! ")
+            }
+            StepperError::StepgenError(StepgenError::TooFast) => {
+                write!(r.display, "RPM is too high!")
+            }
+            StepperError::HardLimit => write!(r.display, "Hard limit!     "),
+            _ => unreachable!(),
+        };
+
+        r.display.position(0, 1);
+        let _ = write!(r.display, "Retry?          ");
+        wait_loop::<B, _>(r.controls, r.estop, || {});
+    }
+
+    r.display.position(0, 0);
+    if phase.starts > 1 {
+        let _ = write!(
+            r.display,
+            "Cutting {}/{}...  ",
+            phase.start + 1,
+            phase.starts
+        );
+    } else {
+        let _ = write!(r.display, "Cutting...      ");
+    }
+
+    loop {
+        let (state, last_error) = r
+            .shared
+            .stepper
+            .lock(|s| (s.state(), s.last_error_degrees()));
+        if state == stepper::State::Stopped {
+            break;
+        }
+
+        // Display thread cutting error
+        r.display.position(0, 1);
+        let sign = if last_error < 0 { "-" } else { " " };
+        let le = last_error.abs();
+        let _ = write!(r.display, "Err: {}{}.{}        ", sign, le / 10, le % 10);
+    }
+}
+
+enum ThreadSystem {
+    Inch,
+    Metric,
+    BritishAssociation,
+    Npt,
+    Bspt,
+}
+
+impl core::fmt::Display for ThreadSystem {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let str = match self {
+            ThreadSystem::Inch => "Inch",
+            ThreadSystem::Metric => "Metric",
+            ThreadSystem::BritishAssociation => "BA",
+            ThreadSystem::Npt => "NPT",
+            ThreadSystem::Bspt => "BSPT",
+        };
+        f.pad(str)
+    }
+}
+
+fn select_thread_size<B: Board>(r: &mut MenuResources<B>) -> Option<ThreadSize> {
+    // Longest of the lists
+    const INCH_THREADS: [u16; 21] = [
+        4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 16, 18, 20, 24, 28, 32, 40, 48, 56, 64,
+    ];
+    const METRIC_THREADS: [u16; 20] = [
+        35, 40, 45, 50, 60, 70, 80, 100, 125, 150, 175, 200, 250, 300, 350, 400, 450, 500, 550, 600,
+    ];
+    const BA_THREADS: [u16; 11] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    // Threads per inch, in tenths
+    const NPT_THREADS: [u16; 5] = [270, 180, 140, 115, 80];
+    const BSPT_THREADS: [u16; 4] = [280, 190, 140, 110];
+
+    let kind = super::util::run_selection(
+        r,
+        "Thread Type?",
+        &[
+            ThreadSystem::Inch,
+            ThreadSystem::Metric,
+            ThreadSystem::BritishAssociation,
+            ThreadSystem::Npt,
+            ThreadSystem::Bspt,
+        ],
+        0,
+    )?;
+    // Select thread size, sizes of each system are listed along with the default one
+    let (sizes, size, default): (&[u16], fn(u16) -> ThreadSize, u16) = match kind {
+        // Well, there is also 4 1/2, but we just ignore it for simplicity :)
+        ThreadSystem::Inch => (&INCH_THREADS, ThreadSize::Tpi, 16),
+        ThreadSystem::Metric => (&METRIC_THREADS, ThreadSize::Metric, 100),
+        ThreadSystem::BritishAssociation => (&BA_THREADS, ThreadSize::Ba, 6),
+        ThreadSystem::Npt => (&NPT_THREADS, ThreadSize::Npt, 180),
+        ThreadSystem::Bspt => (&BSPT_THREADS, ThreadSize::Bspt, 190),
+    };
+    let mut threads = [ThreadSize::Tpi(0); INCH_THREADS.len()];
+    for (thread, &value) in threads.iter_mut().zip(sizes) {
+        *thread = size(value);
+    }
+    let default = sizes.iter().position(|v| *v == default).unwrap_or(0);
+    super::util::run_selection(r, "Thread Size?", &threads[..sizes.len()], default).copied()
+}
+
+/// Inch threads could be either Unified or Whitworth, form of other threads is known (BSPT threads
+/// are Whitworth).
+fn select_form<B: Board>(r: &mut MenuResources<B>, thread: ThreadSize) -> Option<ThreadForm> {
+    match thread {
+        ThreadSize::Tpi(_) => {
+            let forms = [ThreadForm::Iso, ThreadForm::Whitworth];
+            super::util::run_selection(r, "Thread Form?", &forms, 0).copied()
+        }
+        ThreadSize::Metric(_) => Some(ThreadForm::Iso),
+        ThreadSize::Ba(_) => Some(ThreadForm::Ba),
+        ThreadSize::Npt(_) => Some(ThreadForm::Npt),
+        ThreadSize::Bspt(_) => Some(ThreadForm::Whitworth),
+    }
+}
+
+/// Cutting towards the headstock is the usual way for right-hand threads, cutting towards the
+/// tailstock is the usual way for left-hand ones.
+fn select_direction<B: Board>(r: &mut MenuResources<B>, hand: Hand) -> Option<Direction> {
+    let initial = match hand {
+        Hand::Right => Direction::Left,
+        Hand::Left => Direction::Right,
+    };
+    select_end(r, "Cut Toward?", initial)
+}
+
+/// Select the end of the work, headstock one is to the left.
+fn select_end<B: Board>(
+    r: &mut MenuResources<B>,
+    header: &str,
+    initial: Direction,
+) -> Option<Direction> {
+    let labels = ["> Headstock", "> Tailstock"];
+    let initial = usize::from(initial == Direction::Right);
+    match super::util::run_selection_idx(r, header, &labels, initial)? {
+        0 => Some(Direction::Left),
+        _ => Some(Direction::Right),
+    }
+}
+
+fn select_starts<B: Board>(r: &mut MenuResources<B>, starts: u16) -> Option<u16> {
+    const STARTS: [u16; 6] = [1, 2, 3, 4, 5, 6];
+    let initial = usize::from(starts - 1);
+    super::util::run_selection(r, "Starts?", &STARTS, initial).copied()
+}
+
+/// Capture retract position, `sign` is the direction it is in from the current position.
+fn capture_retract_position<B: Board>(
+    r: &mut MenuResources<B>,
+    scale: Scale,
+    units: Units,
+    sign: i32,
+) -> Option<i32> {
+    // Retract distance is set in 0.100 inch or in millimeters
+    let (unit, label) = match units {
+        Units::Inch => (NM_PER_INCH / 10, "inch"),
+        Units::Metric => (NM_PER_MM, "mm"),
+    };
+    let mut deltaenc = r.encoder.delta_encoder();
+    r.display.clear();
+    let start = r.shared.stepper.lock(|s| s.position());
+    let move_to = |shared: &mut SharedResources<B>, distance: i64| {
+        let target = start + sign * scale.to_steps(distance * unit) as i32;
+        // FIXME: print "MOVING..."
+        steputil::jog_to(target, shared)
+    };
+    // Moves towards the tripped hard limit switch are refused, distance stays where it was
+    let mut distance = if move_to(&mut r.shared, 5) { 5 } else { 0 };
+    wait_loop::<B, _>(r.controls, r.estop, || {
+        let delta = i64::from(deltaenc.delta());
+        if delta != 0 {
+            let next = (distance + delta).max(0);
+            if move_to(&mut r.shared, next) {
+                distance = next;
+            }
+        }
+
+        let (current, limit) = r.shared.stepper.lock(|s| (s.position(), s.hard_limit()));
+
+        // Update screen
+        r.display.position(0, 0);
+        let _ = match limit {
+            None => write!(r.display, "Retract Distance"),
+            Some(_) => write!(r.display, "Hard limit!     "),
+        };
+        r.display.position(0, 1);
+        let _ = write!(
+            r.display,
+            "{} {}    ",
+            printable_position(sign * (current - start), scale, units),
+            label
+        );
+        current
+    })
+}
+
+/// Check there is enough room between the retract position and the shoulder to get up to the
+/// speed and to stop. If there is not, operator is offered to extend the retract distance, by the
+/// whole amount of leads so the thread stays in phase with the previous passes. Returns the
+/// extension, in steps.
+fn check_ramp<B: Board>(
+    r: &mut MenuResources<B>,
+    scale: Scale,
+    units: Units,
+    lead: Ratio,
+    shoulder: i32,
+    retract: i32,
+) -> Option<Option<i32>> {
+    let rpm = r.shared.hall.lock(|hall| hall.rpm());
+    // Spindle speed errors are reported once the cut is started
+    let Ok(ramp) = r.shared.stepper.lock(|s| s.thread_ramp(lead, rpm)) else {
+        return Some(None);
+    };
+    let gap = shoulder.abs_diff(retract);
+    if gap >= ramp {
+        return Some(None);
+    }
+
+    let short = u64::from(ramp - gap) * u64::from(lead.denominator);
+    let leads = short.div_ceil(u64::from(lead.numerator));
+    let label = match units {
+        Units::Inch => "inch",
+        Units::Metric => "mm",
+    };
+    r.display.clear();
+    r.display.position(0, 0);
+    let _ = write!(r.display, "Extend retract? ");
+    r.display.position(0, 1);
+    let min = printable_position(ramp as i32, scale, units);
+    let _ = write!(r.display, "Min {} {}", min, label);
+    wait_loop::<B, _>(r.controls, r.estop, || {})?;
+    r.display.clear();
+    Some(Some(lead.steps(leads as u32) as i32))
+}
+
+/// Pick up the existing thread: operator jogs the tool into the groove with the encoder, carriage
+/// position and spindle angle at that moment give the shift of the thread. Hall sensor only knows
+/// the start of the revolution, so with it the spindle should be turned to the sensor first.
+fn pick_up_thread<B: Board>(
+    r: &mut MenuResources<B>,
+    scale: Scale,
+    units: Units,
+    lead: Ratio,
+    start: i32,
+    target: i32,
+    reversed: bool,
+) -> Option<u16> {
+    let mut deltaenc = r.encoder.delta_encoder();
+    r.display.clear();
+    let origin = r.shared.stepper.lock(|s| s.position());
+    let mut distance = 0;
+    let (position, angle) = wait_loop::<B, _>(r.controls, r.estop, || {
+        let delta = i64::from(deltaenc.delta());
+        if delta != 0 {
+            let next = distance + delta;
+            let target = origin + scale.to_steps(next * units.resolution()) as i32;
+            // Click towards the tripped hard limit switch is ignored
+            if steputil::jog_to(target, &mut r.shared) {
+                distance = next;
+            }
+        }
+
+        let (current, limit) = r.shared.stepper.lock(|s| (s.position(), s.hard_limit()));
+        r.display.position(0, 0);
+        let _ = match limit {
+            None => write!(r.display, "Jog into groove "),
+            Some(_) => write!(r.display, "Hard limit!     "),
+        };
+        r.display.position(0, 1);
+        let _ = write!(
+            r.display,
+            "{}        ",
+            printable_position(current, scale, units)
+        );
+        (current, r.shared.hall.lock(|hall| hall.angle()))
+    })?;
+    drop(deltaenc);
+
+    // Tool is in the groove, carriage should not move until it is backed out
+    r.display.clear();
+    r.display.position(0, 0);
+    let _ = write!(r.display, "Back tool out?");
+    wait_loop::<B, _>(r.controls, r.estop, || {})?;
+    // Angle is measured in the forward direction, revolution starts at the same point
+    let angle = if reversed {
+        angle.wrapping_neg()
+    } else {
+        angle
+    };
+    Some(Phase::pickup_shift(lead, start, target, position, angle))
+}
+
+/// Infeed of the pass, displayed to a tenth of a thousandth of an inch or to a micrometer. Both
+/// take nine columns.
+struct Infeed {
+    nanometers: u64,
+    units: Units,
+}
+
+impl core::fmt::Display for Infeed {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let nanometers = self.nanometers as i64;
+        match self.units {
+            Units::Inch => {
+                let value = div_round(nanometers, NM_PER_INCH / 10_000) as u32;
+                write!(f, "+{}.{:0>4}in", value / 10_000, value % 10_000)
+            }
+            Units::Metric => {
+                let value = div_round(nanometers, NM_PER_MM / 1_000) as u32;
+                write!(f, " +{}.{:0>3}mm", value / 1_000, value % 1_000)
+            }
+        }
+    }
+}
+
+/// Capture the thread phase with the encoder. Infeed for the next pass, if given, is displayed
+/// next to the phase.
+fn capture_phase<B: Board>(
+    r: &mut MenuResources<B>,
+    phase: u16,
+    infeed: Option<Infeed>,
+) -> Option<u16> {
+    let encoder = r.encoder.set_current_limit(phase, 360);
+    wait_loop::<B, _>(r.controls, r.estop, || {
+        let phase = encoder.current();
+        r.display.position(0, 1);
+        let _ = match infeed {
+            Some(ref infeed) => write!(r.display, "{} {: >3}deg", infeed, phase),
+            None => write!(r.display, "Phase: {: >3} deg  ", phase),
+        };
+        phase
+    })
+}
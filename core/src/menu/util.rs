@@ -0,0 +1,212 @@
+use crate::hal::{Board, Button, Controls, EStop, Event, QuadEncoder, Stopwatch};
+use crate::menu::MenuResources;
+use crate::settings;
+use crate::stepper::StepperError;
+use crate::units::{div_round, Scale, Units};
+use core::fmt::Write;
+use rtic_core::Mutex;
+
+/// Run a "selection menu", a menu where one of the several items is selected. Items could be
+/// selected both by pressing "Fast" button or by pressing "Select" button for a short period.
+/// Pressing "Select" for longer acts as an "Exit" action (no selection is returned).
+#[inline(never)]
+pub fn run_selection<'a, B: Board, T: core::fmt::Display>(
+    r: &mut MenuResources<B>,
+    header: &str,
+    elements: &'a [T],
+    initial: usize,
+) -> Option<&'a T> {
+    run_selection_internal(r, header, &|pos| &elements[pos], initial, elements.len())
+        .map(|pos| &elements[pos])
+}
+
+/// Run a "selection menu", a menu where one of the several items is selected. Items could be
+/// selected both by pressing "Fast" button or by pressing "Select" button for a short period.
+/// Pressing "Select" for longer acts as an "Exit" action (no selection is returned).
+pub fn run_selection_idx<B: Board, T: core::fmt::Display>(
+    r: &mut MenuResources<B>,
+    header: &str,
+    elements: &[T],
+    initial: usize,
+) -> Option<usize> {
+    run_selection_internal(r, header, &|pos| &elements[pos], initial, elements.len())
+}
+
+/// Run a "selection menu", a menu where one of the several items is selected. Items could be
+/// selected both by pressing "Fast" button or by pressing "Select" button for a short period.
+/// Pressing "Select" for longer acts as an "Exit" action (no selection is returned).
+fn run_selection_internal<'a, B: Board>(
+    r: &mut MenuResources<B>,
+    header: &str,
+    labels: &'a dyn Fn(usize) -> &'a dyn core::fmt::Display,
+    initial: usize,
+    total: usize,
+) -> Option<usize> {
+    let encoder = r.encoder.set_current_limit(initial as u16, total as u16);
+    r.display.clear();
+    wait_loop::<B, _>(r.controls, r.estop, || {
+        let selected = usize::from(encoder.current());
+        let label = labels(selected);
+        r.display.position(0, 0);
+        let _ = write!(r.display, "{: <16}", header);
+        r.display.position(0, 1);
+        let _ = write!(r.display, "{: <16}", label);
+        selected
+    })
+}
+
+/// Edit the setting value with the encoder. Holding "Fast" button changes value ten times faster.
+pub fn run_setting<B: Board>(r: &mut MenuResources<B>, setting: &settings::RawSetting) {
+    r.display.clear();
+
+    let (min, max) = setting.range();
+    let orig = r.shared.flash.lock(|f| setting.read(f));
+    let mut current = orig;
+    let mut deltaenc = r.encoder.delta_encoder();
+    loop {
+        if let Event::Unpressed(Button::Encoder) = r.controls.read_event() {
+            break;
+        }
+        let step = if r.controls.state().fast { 10 } else { 1 };
+        current = current
+            .saturating_add(i32::from(deltaenc.delta()) * step)
+            .max(min)
+            .min(max);
+
+        r.display.position(0, 0);
+        let _ = write!(r.display, "{: <16}", setting.label());
+        r.display.position(0, 1);
+        // Trailing spaces clear the previous (longer) value
+        let _ = write!(r.display, "{}        ", setting.format(current));
+    }
+    drop(deltaenc);
+
+    if current != orig {
+        r.shared.flash.lock(|f| setting.write(f, current)).unwrap();
+    }
+}
+
+/// How long "Select" button needs to be pressed to be interpreted as "exit current menu"
+const EXIT_DURATION_US: u32 = 1_500_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavStatus {
+    Exit,
+    Select,
+}
+
+pub struct Navigation<W: Stopwatch> {
+    /// Duration for which `Select` button was pressed.
+    pressed_duration: Option<W>,
+}
+
+impl<W: Stopwatch> Navigation<W> {
+    pub fn new() -> Self {
+        Self {
+            pressed_duration: None,
+        }
+    }
+    pub fn check(&mut self, estop: &impl EStop, event: Event) -> Option<NavStatus> {
+        if estop.is_emergency_stop() {
+            panic!("*E-STOP*");
+        }
+
+        if let Some(ref mut pressed_duration) = self.pressed_duration {
+            if pressed_duration.elapsed_us() > EXIT_DURATION_US {
+                return Some(NavStatus::Exit);
+            }
+        }
+        match event {
+            Event::Pressed(Button::Encoder) => self.pressed_duration = Some(W::start()),
+            Event::Unpressed(Button::Encoder)
+                if core::mem::take(&mut self.pressed_duration).is_some() =>
+            {
+                return Some(NavStatus::Select);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+pub struct PrintablePosition {
+    position: i32,
+    scale: Scale,
+    units: Units,
+}
+
+pub fn printable_position(position: i32, scale: Scale, units: Units) -> PrintablePosition {
+    PrintablePosition {
+        position,
+        scale,
+        units,
+    }
+}
+
+impl core::fmt::Display for PrintablePosition {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        // Thousandths of an inch or hundredths of a millimeter
+        let nanometers = self.scale.to_nanometers(i64::from(self.position));
+        let value = div_round(nanometers, self.units.resolution());
+        let sign = if value < 0 { "-" } else { "" };
+        // Formatting as 32-bit keeps 64-bit formatting out of the firmware
+        let value = value.unsigned_abs() as u32;
+        match self.units {
+            Units::Inch => write!(f, "{}{}.{:0>3}", sign, value / 1000, value % 1000),
+            Units::Metric => write!(f, "{}{}.{:0>2}", sign, value / 100, value % 100),
+        }
+    }
+}
+
+/// Run a "wait" loop: execute given callback in a loop until operator presses `Select` button
+/// or `Fast` button. If `Select` is pressed for a long period, the function returns `None`
+/// (indicating "exit"). Otherwise, the return value is the value returned from the callback.
+pub fn wait_loop<B: Board, R>(
+    controls: &mut B::Controls,
+    estop: &mut B::EStop,
+    mut cb: impl FnMut() -> R,
+) -> Option<R> {
+    let mut nav = Navigation::<B::Stopwatch>::new();
+    loop {
+        let result = cb();
+        match wait_event::<B>(controls, estop, &mut nav) {
+            Some(NavStatus::Exit) => return None,
+            Some(NavStatus::Select) => return Some(result),
+            None => {}
+        }
+    }
+}
+
+/// Check the stepper accepted the move. If it did not, the error is shown until operator presses
+/// `Select` or `Fast` and `None` is returned, so the operation is given up on.
+pub fn check_move<B: Board>(
+    r: &mut MenuResources<B>,
+    result: Result<(), StepperError>,
+) -> Option<()> {
+    let Err(err) = result else {
+        return Some(());
+    };
+    r.display.clear();
+    r.display.position(0, 0);
+    let _ = match err {
+        StepperError::HardLimit => write!(r.display, "Hard limit!"),
+        _ => write!(r.display, "Stepper error!"),
+    };
+    wait_loop::<B, _>(r.controls, r.estop, || {});
+    None
+}
+
+// Not inlined into every `wait_loop` caller, to save flash
+#[inline(never)]
+fn wait_event<B: Board>(
+    controls: &mut B::Controls,
+    estop: &mut B::EStop,
+    nav: &mut Navigation<B::Stopwatch>,
+) -> Option<NavStatus> {
+    // We use `Fast` button for continuing the operation instead of typical `Encoder` button.
+    let event = controls.read_event();
+    match nav.check(estop, event) {
+        None if matches!(event, Event::Pressed(Button::Fast)) => Some(NavStatus::Select),
+        status => status,
+    }
+}
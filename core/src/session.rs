@@ -0,0 +1,157 @@
+//! Last session of the machine (stepper position, feed limits and thread positions), saved into
+//! the settings storage so it can be restored after the power cycle.
+
+use crate::settings::{SettingsStorage, Value};
+
+/// Which parts of the session are saved
+const FLAGS: u16 = 0x40;
+const FLAG_SAVED: u16 = 1;
+const FLAG_LEFT: u16 = 1 << 1;
+const FLAG_RIGHT: u16 = 1 << 2;
+const FLAG_THREAD: u16 = 1 << 3;
+
+const POSITION: Value = Value::new(0x41);
+const LEFT: Value = Value::new(0x43);
+const RIGHT: Value = Value::new(0x45);
+const SHOULDER: Value = Value::new(0x47);
+const RETRACT: Value = Value::new(0x49);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Session {
+    /// Stepper position
+    pub position: i32,
+    /// Left and right limits of the power feed
+    pub limits: (Option<i32>, Option<i32>),
+    /// Shoulder and retract positions of the thread being cut
+    pub thread: Option<(i32, i32)>,
+}
+
+impl Session {
+    /// Load the saved session. Returns `None` if session was never saved.
+    pub fn load<S: SettingsStorage>(storage: &mut S) -> Option<Session> {
+        let flags = storage.read(FLAGS)?;
+        if flags & FLAG_SAVED == 0 {
+            return None;
+        }
+        let mut read = |flag: u16, value: Value| {
+            if flags & flag != 0 {
+                value.read(storage)
+            } else {
+                None
+            }
+        };
+        let position = read(FLAG_SAVED, POSITION)?;
+        let limits = (read(FLAG_LEFT, LEFT), read(FLAG_RIGHT, RIGHT));
+        let thread = read(FLAG_THREAD, SHOULDER).zip(read(FLAG_THREAD, RETRACT));
+        Some(Session {
+            position,
+            limits,
+            thread,
+        })
+    }
+
+    /// Save the session. Only values that changed since the last save are written, to reduce
+    /// flash wear; values which are not set are left as is.
+    pub fn save<S: SettingsStorage>(&self, storage: &mut S) -> Result<(), S::Error> {
+        let mut flags = FLAG_SAVED;
+        POSITION.write(storage, self.position)?;
+        if let Some(left) = self.limits.0 {
+            flags |= FLAG_LEFT;
+            LEFT.write(storage, left)?;
+        }
+        if let Some(right) = self.limits.1 {
+            flags |= FLAG_RIGHT;
+            RIGHT.write(storage, right)?;
+        }
+        if let Some((shoulder, retract)) = self.thread {
+            flags |= FLAG_THREAD;
+            SHOULDER.write(storage, shoulder)?;
+            RETRACT.write(storage, retract)?;
+        }
+        if storage.read(FLAGS) != Some(flags) {
+            storage.write(FLAGS, flags)?;
+        }
+        Ok(())
+    }
+
+    /// Forget the saved session.
+    pub fn clear<S: SettingsStorage>(storage: &mut S) -> Result<(), S::Error> {
+        match storage.read(FLAGS) {
+            None | Some(0) => Ok(()),
+            Some(_) => storage.write(FLAGS, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Storage which counts the writes
+    #[derive(Default)]
+    struct MemoryStorage(HashMap<u16, u16>, usize);
+
+    impl SettingsStorage for MemoryStorage {
+        type Error = ();
+
+        fn read(&mut self, tag: u16) -> Option<u16> {
+            self.0.get(&tag).copied()
+        }
+
+        fn write(&mut self, tag: u16, value: u16) -> Result<(), ()> {
+            self.0.insert(tag, value);
+            self.1 += 1;
+            Ok(())
+        }
+
+        fn erase(&mut self) -> Result<(), ()> {
+            self.0.clear();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut storage = MemoryStorage::default();
+        assert_eq!(None, Session::load(&mut storage));
+
+        let session = Session {
+            position: -70_000,
+            limits: (Some(-100_000), None),
+            thread: Some((1_000, 2_000)),
+        };
+        session.save(&mut storage).unwrap();
+        assert_eq!(Some(session), Session::load(&mut storage));
+
+        let session = Session {
+            limits: (None, Some(5)),
+            thread: None,
+            ..session
+        };
+        session.save(&mut storage).unwrap();
+        assert_eq!(Some(session), Session::load(&mut storage));
+
+        Session::clear(&mut storage).unwrap();
+        assert_eq!(None, Session::load(&mut storage));
+    }
+
+    #[test]
+    fn only_changes_written() {
+        let mut storage = MemoryStorage::default();
+        let mut session = Session {
+            position: 100,
+            limits: (Some(-100), Some(200)),
+            thread: None,
+        };
+        session.save(&mut storage).unwrap();
+        assert_eq!(7, storage.1);
+        session.save(&mut storage).unwrap();
+        assert_eq!(7, storage.1);
+
+        // Upper half of the position stays the same
+        session.position = 101;
+        session.save(&mut storage).unwrap();
+        assert_eq!(8, storage.1);
+    }
+}
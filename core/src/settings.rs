@@ -0,0 +1,905 @@
+use crate::units::{Scale, Units};
+use core::marker::PhantomData;
+
+/// Persistent storage for the settings values (EEPROM emulation on the device). Values are
+/// addressed by their tags.
+pub trait SettingsStorage {
+    type Error: core::fmt::Debug;
+
+    /// Read value stored under the given tag. Returns `None` if value was never written.
+    fn read(&mut self, tag: u16) -> Option<u16>;
+
+    /// Write value under the given tag.
+    fn write(&mut self, tag: u16, value: u16) -> Result<(), Self::Error>;
+
+    /// Erase all stored values.
+    fn erase(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Type of the setting value, converted to and from the raw (integer) value stored in the
+/// EEPROM.
+pub trait SettingType: Copy {
+    fn from_raw(raw: i32) -> Self;
+    fn to_raw(self) -> i32;
+}
+
+impl SettingType for u16 {
+    fn from_raw(raw: i32) -> Self {
+        raw as u16
+    }
+
+    fn to_raw(self) -> i32 {
+        i32::from(self)
+    }
+}
+
+impl SettingType for u32 {
+    fn from_raw(raw: i32) -> Self {
+        raw as u32
+    }
+
+    fn to_raw(self) -> i32 {
+        self as i32
+    }
+}
+
+impl SettingType for i32 {
+    fn from_raw(raw: i32) -> Self {
+        raw
+    }
+
+    fn to_raw(self) -> i32 {
+        self
+    }
+}
+
+impl SettingType for bool {
+    fn from_raw(raw: i32) -> Self {
+        raw != 0
+    }
+
+    fn to_raw(self) -> i32 {
+        i32::from(self)
+    }
+}
+
+impl SettingType for Units {
+    fn from_raw(raw: i32) -> Self {
+        match raw {
+            0 => Units::Inch,
+            _ => Units::Metric,
+        }
+    }
+
+    fn to_raw(self) -> i32 {
+        match self {
+            Units::Inch => 0,
+            Units::Metric => 1,
+        }
+    }
+}
+
+/// How the setting value is displayed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Integer,
+    /// "No" or "Yes"
+    Flag,
+    /// Fixed point number with the given amount of decimal places
+    Fixed(u8),
+    /// One of the given labels, selected by the value
+    Choice(&'static [&'static str]),
+}
+
+/// Setting, regardless of its value type: how it is stored, displayed and edited. Values which fit
+/// into 16 bits are stored under a single tag, wider (or signed) ones span two consecutive tags.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RawSetting {
+    /// Name of the setting in the serial console
+    name: &'static str,
+    tag: u16,
+    default: i32,
+    min: i32,
+    max: i32,
+    format: Format,
+    label: &'static str,
+}
+
+impl core::fmt::Display for RawSetting {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.pad(self.label)
+    }
+}
+
+impl RawSetting {
+    pub fn range(&self) -> (i32, i32) {
+        (self.min, self.max)
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn is_wide(&self) -> bool {
+        self.min < 0 || self.max > i32::from(u16::MAX)
+    }
+
+    /// Read value from the active profile.
+    pub fn read<S: SettingsStorage>(&self, storage: &mut S) -> i32 {
+        let profile = profile(storage);
+        self.read_in(storage, profile)
+    }
+
+    /// Write value into the active profile and update the checksum of the settings.
+    pub fn write<S: SettingsStorage>(&self, storage: &mut S, value: i32) -> Result<(), S::Error> {
+        let profile = profile(storage);
+        begin_update(storage)?;
+        self.write_in(storage, profile, value)?;
+        finish_update(storage)
+    }
+
+    /// Read value from the given profile.
+    pub fn read_in<S: SettingsStorage>(&self, storage: &mut S, profile: u16) -> i32 {
+        let tag = self.tag + profile * PROFILE_STRIDE;
+        let value = if self.is_wide() {
+            Value::new(tag).read(storage)
+        } else {
+            storage.read(tag).map(i32::from)
+        };
+        // Not `clamp`: its range assertion pulls the integer debug formatting into the firmware
+        value
+            .map(|v| v.max(self.min).min(self.max))
+            .unwrap_or(self.default)
+    }
+
+    fn write_in<S: SettingsStorage>(
+        &self,
+        storage: &mut S,
+        profile: u16,
+        value: i32,
+    ) -> Result<(), S::Error> {
+        let tag = self.tag + profile * PROFILE_STRIDE;
+        let value = value.max(self.min).min(self.max);
+        if self.is_wide() {
+            Value::new(tag).write(storage, value)
+        } else {
+            storage.write(tag, value as u16)
+        }
+    }
+
+    /// Tags the setting is stored under, in the given profile
+    fn tags(&self, profile: u16) -> core::ops::Range<u16> {
+        let tag = self.tag + profile * PROFILE_STRIDE;
+        let words = if self.is_wide() { 2 } else { 1 };
+        tag..tag + words
+    }
+
+    /// Format the value of this setting for display.
+    pub fn format(&self, value: i32) -> impl core::fmt::Display {
+        Formatted {
+            format: self.format,
+            value,
+        }
+    }
+}
+
+struct Formatted {
+    format: Format,
+    value: i32,
+}
+
+impl core::fmt::Display for Formatted {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.format {
+            Format::Integer => write!(f, "{}", self.value),
+            Format::Flag if self.value != 0 => f.write_str("Yes"),
+            Format::Flag => f.write_str("No"),
+            Format::Fixed(decimals) => {
+                let unit = 10i32.pow(u32::from(decimals));
+                let sign = if self.value < 0 { "-" } else { "" };
+                let value = self.value.unsigned_abs();
+                write!(f, "{}{}", sign, value / unit.unsigned_abs())?;
+                if decimals > 0 {
+                    let fraction = value % unit.unsigned_abs();
+                    write!(f, ".{:0>width$}", fraction, width = usize::from(decimals))?;
+                }
+                Ok(())
+            }
+            Format::Choice(labels) => {
+                let label = usize::try_from(self.value)
+                    .ok()
+                    .and_then(|idx| labels.get(idx))
+                    .unwrap_or(&"?");
+                f.write_str(label)
+            }
+        }
+    }
+}
+
+/// Typed setting. Value is clamped to its range when read or written.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Setting<T> {
+    raw: RawSetting,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> Setting<T> {
+    /// Setting displayed as an integer, with the default, minimum and maximum raw values. Name is
+    /// used by the serial console, label by the menus.
+    pub const fn new(
+        name: &'static str,
+        label: &'static str,
+        tag: u16,
+        default: i32,
+        min: i32,
+        max: i32,
+    ) -> Self {
+        Setting {
+            raw: RawSetting {
+                name,
+                label,
+                tag,
+                default,
+                min,
+                max,
+                format: Format::Integer,
+            },
+            value: PhantomData,
+        }
+    }
+
+    /// Setting selecting one of the given labels; the first one is the default.
+    pub const fn choice(
+        name: &'static str,
+        label: &'static str,
+        tag: u16,
+        labels: &'static [&'static str],
+    ) -> Self {
+        let mut setting = Self::new(name, label, tag, 0, 0, labels.len() as i32 - 1);
+        setting.raw.format = Format::Choice(labels);
+        setting
+    }
+
+    /// Display raw value as a fixed point number with the given amount of decimal places.
+    pub const fn fixed(mut self, decimals: u8) -> Self {
+        self.raw.format = Format::Fixed(decimals);
+        self
+    }
+
+    pub const fn raw(&self) -> RawSetting {
+        self.raw
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.raw.label
+    }
+}
+
+impl Setting<bool> {
+    /// "Yes" or "No" setting, "No" by default.
+    pub const fn flag(name: &'static str, label: &'static str, tag: u16) -> Self {
+        let mut setting = Self::new(name, label, tag, 0, 0, 1);
+        setting.raw.format = Format::Flag;
+        setting
+    }
+}
+
+impl<T: SettingType> Setting<T> {
+    pub fn read<S: SettingsStorage>(&self, storage: &mut S) -> T {
+        T::from_raw(self.raw.read(storage))
+    }
+
+    pub fn write<S: SettingsStorage>(&self, storage: &mut S, value: T) -> Result<(), S::Error> {
+        self.raw.write(storage, value.to_raw())
+    }
+}
+
+/// Signed 32-bit value, stored under two consecutive tags (lower half first). Unlike `Setting`, it
+/// has neither default nor range. Halves are only written if they change, to reduce flash wear.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Value {
+    tag: u16,
+}
+
+impl Value {
+    pub const fn new(tag: u16) -> Value {
+        Value { tag }
+    }
+
+    /// Read value. Returns `None` if value was never written.
+    pub fn read<S: SettingsStorage>(&self, storage: &mut S) -> Option<i32> {
+        let low = storage.read(self.tag)?;
+        let high = storage.read(self.tag + 1)?;
+        Some(((u32::from(high) << 16) | u32::from(low)) as i32)
+    }
+
+    pub fn write<S: SettingsStorage>(&self, storage: &mut S, value: i32) -> Result<(), S::Error> {
+        let value = value as u32;
+        for (tag, half) in [
+            (self.tag, value as u16),
+            (self.tag + 1, (value >> 16) as u16),
+        ] {
+            if storage.read(tag) != Some(half) {
+                storage.write(tag, half)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub const IS_LATHE: Setting<bool> = Setting::flag("lathe", "Is Lathe?", 0x01);
+pub const IS_REVERSED: Setting<bool> = Setting::flag("reversed", "Reverse Dir?", 0x02);
+pub const MICROSTEPS: Setting<u16> = Setting::new("microsteps", "Microsteps", 0x03, 16, 1, 125);
+pub const PITCH: Setting<u16> = Setting::new("pitch", "Pitch", 0x04, 16, 1, 32);
+pub const MAX_IPM: Setting<u16> = Setting::new("max_ipm", "Max IPM", 0x05, 30, 1, 30);
+// Steps per second per second
+pub const ACCELERATION: Setting<u16> =
+    Setting::new("acceleration", "Acceleration", 0x06, 1200, 200, 2400);
+pub const TRAVERSAL: Setting<u16> = Setting::new("traversal", "Traversal IPM", 0x07, 10, 1, 30);
+// Percent of the thread cutting phase error corrected per spindle revolution
+pub const PHASE_GAIN: Setting<u16> = Setting::new("phase_gain", "Phase Gain %", 0x08, 50, 0, 100);
+// Spindle sensor: hall sensor (no) or quadrature encoder (yes). Takes effect after restart.
+pub const SPINDLE_ENCODER: Setting<bool> = Setting::flag("spindle_encoder", "Spindle Enc?", 0x09);
+// Counts per revolution of the spindle quadrature encoder
+pub const SPINDLE_CPR: Setting<u16> =
+    Setting::new("spindle_cpr", "Spindle CPR", 0x0a, 1024, 100, 4096);
+// Leadscrew backlash, in thousandths of an inch. Taken up every time direction changes.
+pub const BACKLASH: Setting<u16> = Setting::new("backlash", "Backlash thou", 0x0b, 0, 0, 100);
+// Units positions and feed rates are displayed in
+pub const UNITS: Setting<Units> = Setting::choice("units", "Units", 0x0c, &["Inch", "Metric"]);
+// Leadscrew pitch is given in millimeters (`METRIC_PITCH`) rather than threads per inch (`PITCH`)
+pub const METRIC_SCREW: Setting<bool> = Setting::flag("metric_screw", "Metric Screw?", 0x0d);
+// Pitch of the metric leadscrew, in hundredths of a millimeter
+pub const METRIC_PITCH: Setting<u16> =
+    Setting::new("metric_pitch", "Pitch mm", 0x0e, 200, 25, 1000).fixed(2);
+// Home switch is at the right end of the travel (rather than the left one)
+pub const HOME_RIGHT: Setting<bool> = Setting::flag("home_right", "Home Right?", 0x0f);
+// Hard limit switches are connected. Takes effect after restart.
+pub const HARD_LIMITS: Setting<bool> = Setting::flag("hard_limits", "Hard Limits?", 0x10);
+// Full steps per motor revolution: 200 for 1.8 degree motors, 400 for 0.9 degree ones
+pub const MOTOR_STEPS: Setting<u16> =
+    Setting::new("motor_steps", "Motor Steps", 0x11, 200, 1, 1000);
+// Teeth of the motor and the leadscrew pulleys (or gears) of the reduction between them
+pub const MOTOR_TEETH: Setting<u16> = Setting::new("motor_teeth", "Motor Pulley", 0x12, 1, 1, 200);
+pub const SCREW_TEETH: Setting<u16> = Setting::new("screw_teeth", "Screw Pulley", 0x13, 1, 1, 200);
+// Cross-slide (second) axis, driven by the same kind of motor
+pub const CROSS_REVERSED: Setting<bool> = Setting::flag("cross_reversed", "Cross Reverse?", 0x14);
+pub const CROSS_MICROSTEPS: Setting<u16> =
+    Setting::new("cross_microsteps", "Cross Microstep", 0x15, 16, 1, 125);
+pub const CROSS_PITCH: Setting<u16> = Setting::new("cross_pitch", "Cross Pitch", 0x16, 16, 1, 32);
+pub const CROSS_ACCELERATION: Setting<u16> =
+    Setting::new("cross_acceleration", "Cross Accel", 0x17, 1200, 200, 2400);
+pub const CROSS_METRIC_SCREW: Setting<bool> =
+    Setting::flag("cross_metric_screw", "Cross Metric?", 0x18);
+pub const CROSS_METRIC_PITCH: Setting<u16> =
+    Setting::new("cross_metric_pitch", "Cross Pitch mm", 0x19, 200, 25, 1000).fixed(2);
+pub const CROSS_MOTOR_TEETH: Setting<u16> =
+    Setting::new("cross_motor_teeth", "Cross Mot Pulley", 0x1a, 1, 1, 200);
+pub const CROSS_SCREW_TEETH: Setting<u16> =
+    Setting::new("cross_screw_teeth", "Cross Scr Pulley", 0x1b, 1, 1, 200);
+
+/// Settings of the drive of an axis: microstepping, leadscrew pitch and the reduction between the
+/// motor and the leadscrew. Full steps per motor revolution are shared by both axes.
+#[derive(Clone, Copy)]
+pub struct Drive {
+    pub microsteps: Setting<u16>,
+    pub pitch: Setting<u16>,
+    pub metric_screw: Setting<bool>,
+    pub metric_pitch: Setting<u16>,
+    pub motor_teeth: Setting<u16>,
+    pub screw_teeth: Setting<u16>,
+}
+
+/// Drive of the main (carriage or table) axis
+pub const MAIN_DRIVE: Drive = Drive {
+    microsteps: MICROSTEPS,
+    pitch: PITCH,
+    metric_screw: METRIC_SCREW,
+    metric_pitch: METRIC_PITCH,
+    motor_teeth: MOTOR_TEETH,
+    screw_teeth: SCREW_TEETH,
+};
+
+/// Drive of the cross-slide axis
+pub const CROSS_DRIVE: Drive = Drive {
+    microsteps: CROSS_MICROSTEPS,
+    pitch: CROSS_PITCH,
+    metric_screw: CROSS_METRIC_SCREW,
+    metric_pitch: CROSS_METRIC_PITCH,
+    motor_teeth: CROSS_MOTOR_TEETH,
+    screw_teeth: CROSS_SCREW_TEETH,
+};
+
+impl Drive {
+    /// Read settings and calculate how many steps the axis makes per unit of length
+    pub fn scale<S: SettingsStorage>(&self, storage: &mut S) -> Scale {
+        let steps_per_rev =
+            u32::from(self.microsteps.read(storage)) * u32::from(MOTOR_STEPS.read(storage));
+        let scale = if self.metric_screw.read(storage) {
+            Scale::metric(steps_per_rev, u32::from(self.metric_pitch.read(storage)))
+        } else {
+            Scale::inch(steps_per_rev, u32::from(self.pitch.read(storage)))
+        };
+        scale.with_reduction(
+            u32::from(self.motor_teeth.read(storage)),
+            u32::from(self.screw_teeth.read(storage)),
+        )
+    }
+}
+
+/// All settings, in the order they are listed in the settings menu and by the serial console.
+pub const ALL: [RawSetting; 27] = [
+    IS_LATHE.raw(),
+    IS_REVERSED.raw(),
+    UNITS.raw(),
+    MICROSTEPS.raw(),
+    MOTOR_STEPS.raw(),
+    MOTOR_TEETH.raw(),
+    SCREW_TEETH.raw(),
+    PITCH.raw(),
+    METRIC_SCREW.raw(),
+    METRIC_PITCH.raw(),
+    MAX_IPM.raw(),
+    ACCELERATION.raw(),
+    TRAVERSAL.raw(),
+    PHASE_GAIN.raw(),
+    BACKLASH.raw(),
+    HOME_RIGHT.raw(),
+    HARD_LIMITS.raw(),
+    SPINDLE_ENCODER.raw(),
+    SPINDLE_CPR.raw(),
+    CROSS_REVERSED.raw(),
+    CROSS_MICROSTEPS.raw(),
+    CROSS_PITCH.raw(),
+    CROSS_METRIC_SCREW.raw(),
+    CROSS_METRIC_PITCH.raw(),
+    CROSS_MOTOR_TEETH.raw(),
+    CROSS_SCREW_TEETH.raw(),
+    CROSS_ACCELERATION.raw(),
+];
+
+/// Version of the settings layout. Bump it (and add the migration to `migrate`) every time stored
+/// values change their meaning or tags are reused.
+pub const VERSION: u16 = 2;
+
+/// Tag of the settings layout version
+const VERSION_TAG: u16 = 0x7f00;
+/// Tag of the checksum of all settings values
+const CRC_TAG: u16 = 0x7f01;
+/// Tag of the active profile
+const PROFILE_TAG: u16 = 0x7f02;
+/// Tag of the flag to select profile at startup
+const PROFILE_PROMPT_TAG: u16 = 0x7f03;
+/// Tag of the marker of the settings update in progress
+const UPDATE_TAG: u16 = 0x7f04;
+/// Tags of the profile names, `PROFILE_NAME_WORDS` per profile
+const PROFILE_NAME_TAG: u16 = 0x7e00;
+const PROFILE_NAME_WORDS: u16 = (PROFILE_NAME_LEN as u16).div_ceil(2);
+
+/// Amount of machine profiles, each holding a full set of settings.
+pub const PROFILES: u16 = 4;
+/// Settings of each next profile are stored under tags offset by this amount.
+const PROFILE_STRIDE: u16 = 0x100;
+/// Maximum length of the profile name
+pub const PROFILE_NAME_LEN: usize = 10;
+
+/// Outcome of the settings check done at startup.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Status {
+    /// Settings are of the current version and checksum matches
+    Valid,
+    /// Settings were migrated from the given version
+    Migrated(u16),
+    /// Settings were corrupted or written by a newer firmware, all reverted to defaults
+    Reset,
+}
+
+/// Check settings version and checksum at startup: migrate settings from the older version or
+/// revert them to the defaults if they cannot be trusted.
+///
+/// Checksum not matching after the update was interrupted (by a power loss) is not corruption:
+/// values written before the interruption are kept.
+pub fn check<S: SettingsStorage>(storage: &mut S) -> Result<Status, S::Error> {
+    // Settings written before the versioning was introduced are version `0`
+    let version = storage.read(VERSION_TAG).unwrap_or(0);
+    if version == VERSION {
+        let crc = checksum(storage);
+        if storage.read(CRC_TAG) == Some(crc) {
+            return Ok(Status::Valid);
+        }
+        if storage.read(UPDATE_TAG).unwrap_or(0) != 0 {
+            storage.write(CRC_TAG, crc)?;
+            storage.write(UPDATE_TAG, 0)?;
+            return Ok(Status::Valid);
+        }
+    }
+    if version >= VERSION {
+        factory_reset(storage)?;
+        return Ok(Status::Reset);
+    }
+    migrate(storage, version)?;
+    storage.write(VERSION_TAG, VERSION)?;
+    let crc = checksum(storage);
+    storage.write(CRC_TAG, crc)?;
+    Ok(Status::Migrated(version))
+}
+
+/// Migrate settings from the given version to the current one, one version at a time.
+fn migrate<S: SettingsStorage>(_storage: &mut S, from: u16) -> Result<(), S::Error> {
+    for version in from..VERSION {
+        match version {
+            // Layout did not change when versioning was introduced
+            0 => {}
+            // Profiles were introduced, existing settings became the first profile. Checksum is
+            // updated after the migration.
+            1 => {}
+            _ => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+/// Erase everything stored (including the saved session), reverting all settings to their
+/// defaults.
+pub fn factory_reset<S: SettingsStorage>(storage: &mut S) -> Result<(), S::Error> {
+    storage.erase()?;
+    storage.write(VERSION_TAG, VERSION)?;
+    let crc = checksum(storage);
+    storage.write(CRC_TAG, crc)
+}
+
+/// Index of the active profile, starting from `0`.
+pub fn profile<S: SettingsStorage>(storage: &mut S) -> u16 {
+    storage
+        .read(PROFILE_TAG)
+        .filter(|p| *p < PROFILES)
+        .unwrap_or(0)
+}
+
+/// Make the given profile active.
+pub fn set_profile<S: SettingsStorage>(storage: &mut S, profile: u16) -> Result<(), S::Error> {
+    begin_update(storage)?;
+    storage.write(PROFILE_TAG, profile.min(PROFILES - 1))?;
+    finish_update(storage)
+}
+
+/// Copy all settings of the given profile into another one. Name is not copied.
+pub fn copy_profile<S: SettingsStorage>(
+    storage: &mut S,
+    from: u16,
+    to: u16,
+) -> Result<(), S::Error> {
+    begin_update(storage)?;
+    for setting in ALL.iter() {
+        let value = setting.read_in(storage, from);
+        if setting.read_in(storage, to) != value {
+            setting.write_in(storage, to, value)?;
+        }
+    }
+    finish_update(storage)
+}
+
+/// Name of the given profile, "Profile N" by default.
+pub fn profile_name<S: SettingsStorage>(storage: &mut S, profile: u16) -> ProfileName {
+    let mut name = ProfileName(*b"Profile 0 ");
+    name.0[8] += (profile + 1) as u8;
+    for idx in 0..PROFILE_NAME_WORDS {
+        let tag = PROFILE_NAME_TAG + profile * PROFILE_NAME_WORDS + idx;
+        if let Some(word) = storage.read(tag) {
+            let [first, second] = word.to_be_bytes();
+            name.0[usize::from(idx) * 2] = first;
+            name.0[usize::from(idx) * 2 + 1] = second;
+        }
+    }
+    name
+}
+
+pub fn set_profile_name<S: SettingsStorage>(
+    storage: &mut S,
+    profile: u16,
+    name: &ProfileName,
+) -> Result<(), S::Error> {
+    begin_update(storage)?;
+    for (idx, pair) in name.0.chunks(2).enumerate() {
+        let tag = PROFILE_NAME_TAG + profile * PROFILE_NAME_WORDS + idx as u16;
+        let word = u16::from_be_bytes([pair[0], pair[1]]);
+        if storage.read(tag) != Some(word) {
+            storage.write(tag, word)?;
+        }
+    }
+    finish_update(storage)
+}
+
+/// Whether operator is asked to select the profile at startup.
+pub fn profile_prompt<S: SettingsStorage>(storage: &mut S) -> bool {
+    storage.read(PROFILE_PROMPT_TAG).unwrap_or(0) != 0
+}
+
+pub fn set_profile_prompt<S: SettingsStorage>(
+    storage: &mut S,
+    prompt: bool,
+) -> Result<(), S::Error> {
+    begin_update(storage)?;
+    storage.write(PROFILE_PROMPT_TAG, u16::from(prompt))?;
+    finish_update(storage)
+}
+
+/// Name of the profile, ASCII, padded with spaces.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ProfileName(pub [u8; PROFILE_NAME_LEN]);
+
+impl core::fmt::Display for ProfileName {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.pad(
+            core::str::from_utf8(&self.0)
+                .unwrap_or("?")
+                .trim_ascii_end(),
+        )
+    }
+}
+
+/// Mark the update of settings values as in progress. Values and the checksum are written one by
+/// one, so settings are not reverted to the defaults if the power is lost in between.
+fn begin_update<S: SettingsStorage>(storage: &mut S) -> Result<(), S::Error> {
+    storage.write(UPDATE_TAG, 1)
+}
+
+/// Update the checksum of the settings once all values are written.
+fn finish_update<S: SettingsStorage>(storage: &mut S) -> Result<(), S::Error> {
+    let crc = checksum(storage);
+    if storage.read(CRC_TAG) != Some(crc) {
+        storage.write(CRC_TAG, crc)?;
+    }
+    storage.write(UPDATE_TAG, 0)
+}
+
+/// CRC-16 (CCITT) of all stored settings values (of every profile) along with their tags, profile
+/// names and the active profile.
+fn checksum<S: SettingsStorage>(storage: &mut S) -> u16 {
+    let mut crc = 0xffffu16;
+    // Plain loops rather than chained iterators, which take much more flash
+    let mut update = |tag: u16| {
+        if let Some(value) = storage.read(tag) {
+            for byte in tag.to_be_bytes().into_iter().chain(value.to_be_bytes()) {
+                crc ^= u16::from(byte) << 8;
+                for _ in 0..8 {
+                    crc = if crc & 0x8000 != 0 {
+                        (crc << 1) ^ 0x1021
+                    } else {
+                        crc << 1
+                    };
+                }
+            }
+        }
+    };
+    for profile in 0..PROFILES {
+        for setting in ALL.iter() {
+            setting.tags(profile).for_each(&mut update);
+        }
+    }
+    (PROFILE_NAME_TAG..PROFILE_NAME_TAG + PROFILES * PROFILE_NAME_WORDS).for_each(&mut update);
+    update(PROFILE_TAG);
+    update(PROFILE_PROMPT_TAG);
+    crc
+}
+
+/// Read settings and calculate how many steps do we make per unit of length
+pub fn scale<S: SettingsStorage>(storage: &mut S) -> Scale {
+    MAIN_DRIVE.scale(storage)
+}
+
+/// Read settings and calculate how many steps the cross-slide axis makes per unit of length
+pub fn cross_scale<S: SettingsStorage>(storage: &mut S) -> Scale {
+    CROSS_DRIVE.scale(storage)
+}
+
+/// Units positions and feed rates are displayed in
+pub fn units<S: SettingsStorage>(storage: &mut S) -> Units {
+    UNITS.read(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemoryStorage(HashMap<u16, u16>);
+
+    impl SettingsStorage for MemoryStorage {
+        type Error = ();
+
+        fn read(&mut self, tag: u16) -> Option<u16> {
+            self.0.get(&tag).copied()
+        }
+
+        fn write(&mut self, tag: u16, value: u16) -> Result<(), ()> {
+            self.0.insert(tag, value);
+            Ok(())
+        }
+
+        fn erase(&mut self) -> Result<(), ()> {
+            self.0.clear();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let mut storage = MemoryStorage::default();
+        assert_eq!(16, MICROSTEPS.read(&mut storage));
+        assert_eq!(1200, ACCELERATION.read(&mut storage));
+        assert_eq!(Scale::inch(16 * 200, 16), scale(&mut storage));
+        assert_eq!(Units::Inch, units(&mut storage));
+    }
+
+    #[test]
+    fn clamped() {
+        let mut storage = MemoryStorage::default();
+        PITCH.write(&mut storage, 100).unwrap();
+        assert_eq!(Some(32), storage.0.get(&0x04).copied());
+        assert_eq!(32, PITCH.read(&mut storage));
+
+        // Values written by some other firmware version are clamped, too
+        storage.0.insert(0x06, 10);
+        assert_eq!(200, ACCELERATION.read(&mut storage));
+    }
+
+    #[test]
+    fn versioning() {
+        // Settings written before the versioning are kept
+        let mut storage = MemoryStorage::default();
+        storage.0.insert(0x04, 20);
+        assert_eq!(Ok(Status::Migrated(0)), check(&mut storage));
+        assert_eq!(Ok(Status::Valid), check(&mut storage));
+        assert_eq!(20, PITCH.read(&mut storage));
+
+        // Settings before profiles were introduced become the first profile
+        storage.0.insert(VERSION_TAG, 1);
+        assert_eq!(Ok(Status::Migrated(1)), check(&mut storage));
+        assert_eq!(0, profile(&mut storage));
+        assert_eq!(20, PITCH.read(&mut storage));
+
+        PITCH.write(&mut storage, 24).unwrap();
+        assert_eq!(Ok(Status::Valid), check(&mut storage));
+
+        // Value changed behind our back
+        storage.0.insert(0x04, 8);
+        assert_eq!(Ok(Status::Reset), check(&mut storage));
+        assert_eq!(16, PITCH.read(&mut storage));
+        assert_eq!(Ok(Status::Valid), check(&mut storage));
+
+        // Power lost after the value was written, but before the checksum
+        storage.0.insert(UPDATE_TAG, 1);
+        storage.0.insert(0x04, 12);
+        assert_eq!(Ok(Status::Valid), check(&mut storage));
+        assert_eq!(12, PITCH.read(&mut storage));
+        assert_eq!(Some(0), storage.0.get(&UPDATE_TAG).copied());
+        assert_eq!(Ok(Status::Valid), check(&mut storage));
+
+        // Written by a newer firmware
+        PITCH.write(&mut storage, 24).unwrap();
+        storage.0.insert(VERSION_TAG, VERSION + 1);
+        assert_eq!(Ok(Status::Reset), check(&mut storage));
+        assert_eq!(16, PITCH.read(&mut storage));
+    }
+
+    #[test]
+    fn profiles() {
+        let mut storage = MemoryStorage::default();
+        factory_reset(&mut storage).unwrap();
+        PITCH.write(&mut storage, 20).unwrap();
+        IS_LATHE.write(&mut storage, true).unwrap();
+        assert_eq!("Profile 2", profile_name(&mut storage, 1).to_string());
+
+        set_profile(&mut storage, 1).unwrap();
+        assert_eq!(16, PITCH.read(&mut storage));
+        PITCH.write(&mut storage, 8).unwrap();
+        assert_eq!(Some(8), storage.0.get(&0x104).copied());
+
+        copy_profile(&mut storage, 0, 1).unwrap();
+        assert_eq!(20, PITCH.read(&mut storage));
+        assert!(IS_LATHE.read(&mut storage));
+
+        let name = ProfileName(*b"Lathe     ");
+        set_profile_name(&mut storage, 1, &name).unwrap();
+        assert_eq!(name, profile_name(&mut storage, 1));
+        assert_eq!("Lathe", name.to_string());
+        assert_eq!(Ok(Status::Valid), check(&mut storage));
+
+        set_profile(&mut storage, 0).unwrap();
+        assert_eq!(20, PITCH.read(&mut storage));
+    }
+
+    #[test]
+    fn typed() {
+        let mut storage = MemoryStorage::default();
+        assert!(!IS_LATHE.read(&mut storage));
+        IS_LATHE.write(&mut storage, true).unwrap();
+        assert_eq!(Some(1), storage.0.get(&0x01).copied());
+        UNITS.write(&mut storage, Units::Metric).unwrap();
+        assert_eq!(Units::Metric, units(&mut storage));
+
+        // Signed values span two tags
+        const OFFSET: Setting<i32> = Setting::new("offset", "Offset", 0x20, 0, -100_000, 100_000);
+        OFFSET.write(&mut storage, -200_000).unwrap();
+        assert_eq!(-100_000, OFFSET.read(&mut storage));
+        assert_eq!(Some(0xfffe), storage.0.get(&0x21).copied());
+    }
+
+    #[test]
+    fn tags() {
+        // Every setting (both halves of the wide ones) has its own tag, below the session tags
+        // starting at `0x40`
+        let mut used = std::collections::HashSet::new();
+        for setting in ALL {
+            for tag in setting.tags(0) {
+                assert!(tag < 0x40, "{} is stored under {:#x}", setting, tag);
+                assert!(used.insert(tag), "{} reuses {:#x}", setting, tag);
+            }
+        }
+
+        // Last profile stays clear of the profile names and the metadata
+        let last = ALL.iter().flat_map(|s| s.tags(PROFILES - 1)).max();
+        assert!(last < Some(PROFILE_NAME_TAG));
+    }
+
+    #[test]
+    fn formatted() {
+        let pitch = METRIC_PITCH.raw();
+        assert_eq!("1.50", format!("{}", pitch.format(150)));
+        assert_eq!("-0.05", format!("{}", pitch.format(-5)));
+        assert_eq!("Yes", format!("{}", IS_LATHE.raw().format(1)));
+        assert_eq!("Metric", format!("{}", UNITS.raw().format(1)));
+        assert_eq!("1024", format!("{}", SPINDLE_CPR.raw().format(1024)));
+    }
+
+    #[test]
+    fn value() {
+        const VALUE: Value = Value::new(0x20);
+        let mut storage = MemoryStorage::default();
+        assert_eq!(None, VALUE.read(&mut storage));
+        VALUE.write(&mut storage, -100_000).unwrap();
+        assert_eq!(Some(-100_000), VALUE.read(&mut storage));
+        VALUE.write(&mut storage, i32::MAX).unwrap();
+        assert_eq!(Some(i32::MAX), VALUE.read(&mut storage));
+        assert_eq!(Some(0x7fff), storage.0.get(&0x21).copied());
+    }
+
+    #[test]
+    fn scale_from_settings() {
+        let mut storage = MemoryStorage::default();
+        PITCH.write(&mut storage, 20).unwrap();
+        MICROSTEPS.write(&mut storage, 8).unwrap();
+        assert_eq!(Scale::inch(8 * 200, 20), scale(&mut storage));
+
+        // Metric leadscrew, 1.5 mm pitch
+        METRIC_SCREW.write(&mut storage, true).unwrap();
+        METRIC_PITCH.write(&mut storage, 150).unwrap();
+        assert_eq!(Scale::metric(8 * 200, 150), scale(&mut storage));
+
+        // 0.9 degree motor with 20:36 belt reduction
+        MOTOR_STEPS.write(&mut storage, 400).unwrap();
+        MOTOR_TEETH.write(&mut storage, 20).unwrap();
+        SCREW_TEETH.write(&mut storage, 36).unwrap();
+        assert_eq!(Scale::metric(8 * 720, 150), scale(&mut storage));
+
+        // Cross-slide has its own microsteps and pitch
+        assert_eq!(Scale::inch(16 * 400, 16), cross_scale(&mut storage));
+        CROSS_MICROSTEPS.write(&mut storage, 4).unwrap();
+        CROSS_PITCH.write(&mut storage, 10).unwrap();
+        assert_eq!(Scale::inch(4 * 400, 10), cross_scale(&mut storage));
+
+        // Metric cross-slide screw with its own reduction
+        CROSS_METRIC_SCREW.write(&mut storage, true).unwrap();
+        CROSS_METRIC_PITCH.write(&mut storage, 100).unwrap();
+        CROSS_MOTOR_TEETH.write(&mut storage, 15).unwrap();
+        CROSS_SCREW_TEETH.write(&mut storage, 30).unwrap();
+        assert_eq!(Scale::metric(4 * 800, 100), cross_scale(&mut storage));
+        assert_eq!(Scale::metric(8 * 720, 150), scale(&mut storage));
+    }
+}
@@ -0,0 +1,274 @@
+//! Software model of the stepper motor driver, for running `Stepper` on the host.
+use crate::driver::StepperDriver;
+
+/// Width of the step pulse, in timer ticks.
+const STEP_PULSE_WIDTH_TICKS: u16 = 1;
+
+/// Step pulse emitted by the simulated driver.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Step {
+    /// Timer tick at which the pulse was emitted.
+    pub time: u64,
+    /// State of the direction pin when the pulse was emitted.
+    pub direction: bool,
+}
+
+/// Simulated stepper motor driver. Models the behavior of the advanced-control timer generating
+/// step pulses in PWM mode: auto-reload (ARR) and compare (CCR) registers are preloaded and only
+/// take effect at the update event, one-pulse mode stops the timer at the next update event and
+/// every update event sets the pending interrupt flag.
+///
+/// Time only moves when `advance` is called. Every emitted step pulse is recorded together with
+/// the state of the direction pin.
+#[derive(Default)]
+pub struct SimDriver {
+    /// Current time, in timer ticks
+    now: u64,
+    /// Time of the last update event (start of the current timer period)
+    period_start: u64,
+
+    // Timer registers
+    arr: u16,
+    arr_preload: u16,
+    ccr: u16,
+    ccr_preload: u16,
+    /// Counter enabled
+    running: bool,
+    /// One-pulse mode
+    one_pulse: bool,
+    /// Update interrupt pending
+    update_pending: bool,
+    /// Timer output channel enabled
+    output: bool,
+    /// Step pulse was already emitted in the current period
+    pulse_emitted: bool,
+
+    // Control pins
+    enabled: bool,
+    direction: bool,
+
+    steps: Vec<Step>,
+}
+
+impl SimDriver {
+    pub fn new() -> SimDriver {
+        SimDriver {
+            // Timer output channel is enabled at initialization
+            output: true,
+            ..Default::default()
+        }
+    }
+
+    /// Current time, in timer ticks.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Time of the next update event, if timer is running.
+    pub fn next_update(&self) -> Option<u64> {
+        if self.running {
+            Some(self.period_start + u64::from(self.arr) + 1)
+        } else {
+            None
+        }
+    }
+
+    /// Advance the time up to `until`, stopping early at the update event. Returns `true` if
+    /// stopped at the update event (and interrupt is pending now).
+    pub fn advance(&mut self, until: u64) -> bool {
+        assert!(until >= self.now, "time cannot go backwards");
+        if !self.running {
+            self.now = until;
+            return false;
+        }
+
+        // Step pulse starts once counter reaches the compare register
+        let pulse = self.period_start + u64::from(self.ccr);
+        if !self.pulse_emitted && pulse <= until {
+            if self.output {
+                self.steps.push(Step {
+                    time: pulse,
+                    direction: self.direction,
+                });
+            }
+            self.pulse_emitted = true;
+        }
+
+        match self.next_update() {
+            Some(update) if update <= until => {
+                self.now = update;
+                self.update_event();
+                true
+            }
+            _ => {
+                self.now = until;
+                false
+            }
+        }
+    }
+
+    /// Advance the time to the next update event. Returns `false` if timer is not running.
+    pub fn advance_to_update(&mut self) -> bool {
+        match self.next_update() {
+            Some(update) => self.advance(update),
+            None => false,
+        }
+    }
+
+    fn update_event(&mut self) {
+        self.period_start = self.now;
+        self.arr = self.arr_preload;
+        self.ccr = self.ccr_preload;
+        self.pulse_emitted = false;
+        self.update_pending = true;
+        if self.one_pulse {
+            self.running = false;
+        }
+    }
+
+    /// Steps emitted so far.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Take steps emitted so far, clearing the record.
+    pub fn take_steps(&mut self) -> Vec<Step> {
+        core::mem::take(&mut self.steps)
+    }
+
+    /// Returns `true` if driver outputs are enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Current state of the direction pin.
+    pub fn direction(&self) -> bool {
+        self.direction
+    }
+
+    /// Returns `true` if timer output channel is enabled.
+    pub fn is_output_enabled(&self) -> bool {
+        self.output
+    }
+}
+
+impl StepperDriver for SimDriver {
+    fn set_enable(&mut self, enable: bool) {
+        self.enabled = enable;
+    }
+
+    fn set_timer_output(&mut self, enable: bool) {
+        self.output = enable;
+    }
+
+    fn set_direction(&mut self, bit: bool) {
+        self.direction = bit;
+    }
+
+    fn start(&mut self, first_delay: u16) {
+        self.preload_delay(first_delay);
+
+        // Update generation reloads registers from preload, but does not set the interrupt flag.
+        self.period_start = self.now;
+        self.arr = self.arr_preload;
+        self.ccr = self.ccr_preload;
+        self.pulse_emitted = false;
+        self.one_pulse = false;
+        self.running = true;
+    }
+
+    fn preload_delay(&mut self, delay: u16) {
+        assert!(delay > 0, "delay must not be zero");
+        self.arr_preload = delay - 1;
+        self.ccr_preload = delay.saturating_sub(STEP_PULSE_WIDTH_TICKS);
+    }
+
+    fn set_last(&mut self) {
+        self.one_pulse = true;
+    }
+
+    fn is_running(&self) -> bool {
+        self.running
+    }
+
+    fn interrupt(&mut self) -> bool {
+        core::mem::replace(&mut self.update_pending, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle() {
+        let mut driver = SimDriver::new();
+        assert!(!driver.is_running());
+        assert_eq!(None, driver.next_update());
+        assert!(!driver.advance(1000));
+        assert_eq!(1000, driver.now());
+        assert!(!driver.interrupt());
+        assert!(driver.steps().is_empty());
+    }
+
+    #[test]
+    fn preloaded_delays() {
+        let mut driver = SimDriver::new();
+        driver.set_direction(true);
+        driver.start(100);
+        driver.preload_delay(50);
+        assert_eq!(Some(100), driver.next_update());
+
+        // Nothing happens before the update event
+        assert!(!driver.advance(98));
+        assert!(!driver.interrupt());
+
+        assert!(driver.advance(1000));
+        assert_eq!(100, driver.now());
+        assert!(driver.interrupt());
+        assert!(!driver.interrupt());
+
+        // Preloaded delay is active now
+        assert_eq!(Some(150), driver.next_update());
+        driver.set_direction(false);
+        assert!(driver.advance_to_update());
+        assert_eq!(
+            &[
+                Step {
+                    time: 99,
+                    direction: true
+                },
+                Step {
+                    time: 149,
+                    direction: false
+                }
+            ],
+            driver.steps()
+        );
+        assert!(driver.is_running());
+    }
+
+    #[test]
+    fn one_pulse() {
+        let mut driver = SimDriver::new();
+        driver.start(100);
+        driver.preload_delay(100);
+        driver.set_last();
+        assert!(driver.advance_to_update());
+        assert!(!driver.is_running());
+        assert!(driver.interrupt());
+        assert!(!driver.advance_to_update());
+        assert_eq!(1, driver.take_steps().len());
+        assert!(driver.steps().is_empty());
+    }
+
+    #[test]
+    fn output_disabled() {
+        let mut driver = SimDriver::new();
+        driver.set_timer_output(false);
+        driver.start(100);
+        driver.set_last();
+        assert!(driver.advance_to_update());
+        assert!(driver.steps().is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+//! Spindle position and speed from the quadrature encoder mounted on the spindle.
+//!
+//! Encoder counter wraps around once per revolution. Counter range is split into `SEGMENTS` equal
+//! segments and firmware reports every crossing of the segment boundary (along with the
+//! timestamp), so spindle speed is measured over the whole last revolution, but updated several
+//! times per revolution.
+//!
+//! Firmware watches two counter values: the next boundary in the direction of rotation and one
+//! count back over the boundary crossed last, which catches the spindle reversing inside the
+//! segment.
+
+/// Amount of speed updates per spindle revolution.
+pub const SEGMENTS: u16 = 8;
+
+/// Spindle is assumed to be stopped if it is slower than this.
+const MIN_RPM: u32 = 10;
+
+pub struct EncoderSpindle {
+    /// Encoder counts per revolution (four times the encoder CPR)
+    counts: u16,
+    /// Frequency of the timestamps, ticks per second
+    frequency: u32,
+    /// Segment boundary crossed last
+    last: u16,
+    /// Direction the last boundary was crossed in
+    forward: bool,
+    /// Timestamps of the last boundary crossings
+    times: [u32; SEGMENTS as usize],
+    /// Position in `times` to record the next crossing to
+    next: usize,
+    /// Amount of recorded crossings, up to `SEGMENTS`
+    recorded: usize,
+    /// Duration of the last revolution, in ticks. `0` if unknown.
+    period: u32,
+}
+
+impl EncoderSpindle {
+    /// Create new encoder spindle for the encoder with given amount of counts per revolution
+    /// (CPR, before quadrature decoding). `frequency` is the frequency of the timestamps.
+    pub fn new(cpr: u16, frequency: u32) -> EncoderSpindle {
+        EncoderSpindle {
+            counts: cpr * 4,
+            frequency,
+            last: 0,
+            forward: true,
+            times: [0; SEGMENTS as usize],
+            next: 0,
+            recorded: 0,
+            period: 0,
+        }
+    }
+
+    /// Amount of encoder counts per revolution (after quadrature decoding).
+    pub fn counts(&self) -> u16 {
+        self.counts
+    }
+
+    /// Counter value at the given segment boundary.
+    fn boundary(&self, boundary: u16) -> u16 {
+        (u32::from(boundary) * u32::from(self.counts) / u32::from(SEGMENTS)) as u16
+    }
+
+    /// Next segment boundary in the direction of rotation.
+    fn ahead(&self) -> u16 {
+        if self.forward {
+            (self.last + 1) % SEGMENTS
+        } else {
+            (self.last + SEGMENTS - 1) % SEGMENTS
+        }
+    }
+
+    /// Counter value at which next segment boundary is crossed.
+    pub fn next_compare(&self) -> u16 {
+        self.boundary(self.ahead())
+    }
+
+    /// Counter value at which spindle is back over the last crossed boundary, one count past it,
+    /// so the counter jittering at the boundary is not taken for a reversal.
+    pub fn back_compare(&self) -> u16 {
+        let boundary = self.boundary(self.last);
+        if self.forward {
+            boundary.checked_sub(1).unwrap_or(self.counts - 1)
+        } else {
+            boundary + 1
+        }
+    }
+
+    /// Next segment boundary was crossed at `time` (counter reached `next_compare`). Returns `true`
+    /// if crossed boundary is the start of the revolution.
+    pub fn crossed(&mut self, time: u32) -> bool {
+        if self.recorded == SEGMENTS as usize {
+            self.period = time.wrapping_sub(self.times[self.next]);
+        } else {
+            self.recorded += 1;
+        }
+        self.times[self.next] = time;
+        self.next = (self.next + 1) % SEGMENTS as usize;
+
+        self.last = self.ahead();
+        self.last == 0
+    }
+
+    /// Spindle reversed and crossed the last boundary back at `time` (counter reached
+    /// `back_compare`). Next boundary is stepped from the last one in the new direction. Speed
+    /// measurement starts over, as the last revolution was not in one direction. Returns `true`
+    /// if crossed boundary is the start of the revolution.
+    pub fn reversed(&mut self, time: u32) -> bool {
+        self.forward = !self.forward;
+        self.restart(time);
+        self.last == 0
+    }
+
+    /// Index pulse: counter is reset to `0` and new revolution starts. Speed measurement starts
+    /// over, as the counter jumps.
+    pub fn index(&mut self, time: u32) {
+        self.last = 0;
+        self.forward = true;
+        self.restart(time);
+    }
+
+    fn restart(&mut self, time: u32) {
+        self.times[0] = time;
+        self.next = 1;
+        self.recorded = 1;
+        self.period = 0;
+    }
+
+    /// Spindle angle since the start of the revolution, in 0.16 format (fraction of the
+    /// revolution). `count` is the current encoder counter.
+    pub fn angle(&self, count: u16) -> u16 {
+        ((u32::from(count) << 16) / u32::from(self.counts)) as u16
+    }
+
+    /// Spindle speed, in 24.8 format. `now` is the current timestamp, used to detect that spindle
+    /// has stopped.
+    pub fn rpm(&self, now: u32) -> u32 {
+        if self.period == 0 {
+            return 0;
+        }
+
+        let last = self.times[(self.next + SEGMENTS as usize - 1) % SEGMENTS as usize];
+        // Divide first, `60 * frequency` overflows with the 72MHz cycle counter
+        let max_segment = self.frequency / (MIN_RPM * u32::from(SEGMENTS)) * 60;
+        if now.wrapping_sub(last) > max_segment {
+            return 0;
+        }
+        (((60 * u64::from(self.frequency)) << 8) / u64::from(self.period)) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FREQUENCY: u32 = 1_000_000;
+
+    /// Rotate spindle at constant speed for the given amount of segments, starting at `start`.
+    /// Returns the time of the last crossing and the amount of revolution starts seen.
+    fn rotate(spindle: &mut EncoderSpindle, start: u32, segments: u32, segment: u32) -> (u32, u32) {
+        let mut starts = 0;
+        let mut time = start;
+        for _ in 0..segments {
+            time = time.wrapping_add(segment);
+            if spindle.crossed(time) {
+                starts += 1;
+            }
+        }
+        (time, starts)
+    }
+
+    #[test]
+    fn compare_values() {
+        let mut spindle = EncoderSpindle::new(1000, FREQUENCY);
+        assert_eq!(4000, spindle.counts());
+        assert_eq!(500, spindle.next_compare());
+        assert_eq!(3999, spindle.back_compare());
+        spindle.crossed(0);
+        assert_eq!(1000, spindle.next_compare());
+        assert_eq!(499, spindle.back_compare());
+        // Reversed before the next boundary: back over 500, then waiting for 0
+        spindle.reversed(0);
+        assert_eq!(0, spindle.next_compare());
+        assert_eq!(501, spindle.back_compare());
+        assert!(spindle.crossed(0));
+        assert_eq!(3500, spindle.next_compare());
+        assert_eq!(1, spindle.back_compare());
+        spindle.crossed(0);
+        assert_eq!(3000, spindle.next_compare());
+        // Reversed again, back over 3500 and waiting for 0 going forward
+        spindle.reversed(0);
+        assert_eq!(0, spindle.next_compare());
+        assert_eq!(3499, spindle.back_compare());
+    }
+
+    #[test]
+    fn revolution_start() {
+        let mut spindle = EncoderSpindle::new(1000, FREQUENCY);
+        // Waiting for the boundary 1, so seven crossings till the start of the revolution
+        let (_, starts) = rotate(&mut spindle, 0, 7, 100);
+        assert_eq!(0, starts);
+        let (_, starts) = rotate(&mut spindle, 0, 1, 100);
+        assert_eq!(1, starts);
+        let (_, starts) = rotate(&mut spindle, 0, 80, 100);
+        assert_eq!(10, starts);
+    }
+
+    #[test]
+    fn rpm() {
+        let mut spindle = EncoderSpindle::new(1024, FREQUENCY);
+        // 600 RPM is 100ms per revolution
+        let (time, _) = rotate(&mut spindle, 0, u32::from(SEGMENTS), 12_500);
+        // Not a full revolution yet
+        assert_eq!(0, spindle.rpm(time));
+        let (time, _) = rotate(&mut spindle, time, 1, 12_500);
+        assert_eq!(600 << 8, spindle.rpm(time));
+
+        // Updated after every segment: half a revolution at 300 RPM
+        let (time, _) = rotate(&mut spindle, time, 4, 25_000);
+        assert_eq!(400 << 8, spindle.rpm(time));
+
+        // Spindle stopped
+        assert_eq!(0, spindle.rpm(time + FREQUENCY));
+    }
+
+    #[test]
+    fn rpm_cycle_counter() {
+        // Firmware timestamps are CPU cycles
+        const FREQUENCY: u32 = 72_000_000;
+        let mut spindle = EncoderSpindle::new(1024, FREQUENCY);
+        // 100 RPM is 600ms per revolution
+        let (time, _) = rotate(&mut spindle, 0, u32::from(SEGMENTS) + 1, 5_400_000);
+        assert_eq!(100 << 8, spindle.rpm(time));
+        // Still turning, 10 RPM is 750ms per segment
+        assert_eq!(100 << 8, spindle.rpm(time + 54_000_000));
+        assert_eq!(0, spindle.rpm(time + 54_000_001));
+    }
+
+    #[test]
+    fn reversal() {
+        let mut spindle = EncoderSpindle::new(1024, FREQUENCY);
+        let (time, _) = rotate(&mut spindle, 0, 19, 12_500);
+        assert_eq!(600 << 8, spindle.rpm(time));
+
+        // Turned back over the last boundary, speed is measured again
+        let time = time + 12_500;
+        spindle.reversed(time);
+        assert_eq!(0, spindle.rpm(time));
+        // Crossed boundary 3 going forward, next is 2 going backward
+        assert_eq!(1024, spindle.next_compare());
+        let (time, starts) = rotate(&mut spindle, time, 3, 12_500);
+        assert_eq!(1, starts);
+        let (time, _) = rotate(&mut spindle, time, 4, 12_500);
+        assert_eq!(0, spindle.rpm(time));
+        let (time, _) = rotate(&mut spindle, time, 1, 12_500);
+        assert_eq!(600 << 8, spindle.rpm(time));
+    }
+
+    #[test]
+    fn timestamps_wrap() {
+        let mut spindle = EncoderSpindle::new(1024, FREQUENCY);
+        let (time, _) = rotate(&mut spindle, u32::MAX - 50_000, 9, 12_500);
+        assert_eq!(600 << 8, spindle.rpm(time));
+    }
+
+    #[test]
+    fn index() {
+        let mut spindle = EncoderSpindle::new(1024, FREQUENCY);
+        rotate(&mut spindle, 0, 20, 12_500);
+        spindle.index(250_000);
+        assert_eq!(0, spindle.rpm(250_000));
+        assert_eq!(512, spindle.next_compare());
+        // Revolution starts right at the index, so next start is a full revolution later
+        let (time, starts) = rotate(&mut spindle, 250_000, 7, 12_500);
+        assert_eq!(0, starts);
+        assert_eq!(0, spindle.rpm(time));
+        let (time, starts) = rotate(&mut spindle, time, 1, 12_500);
+        assert_eq!(1, starts);
+        assert_eq!(600 << 8, spindle.rpm(time));
+    }
+
+    #[test]
+    fn angle() {
+        let spindle = EncoderSpindle::new(1000, FREQUENCY);
+        assert_eq!(0, spindle.angle(0));
+        assert_eq!(0x4000, spindle.angle(1000));
+        assert_eq!(0x8000, spindle.angle(2000));
+    }
+}
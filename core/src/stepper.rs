@@ -0,0 +1,1397 @@
+use crate::driver::StepperDriver;
+use crate::gearing::{GearCommand, Gearing, Ratio};
+use crate::threads::Phase;
+
+/// Direction of stepper motor movement
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    /// Not stepping
+    Stopped,
+    /// Stepping and stop command was requested
+    StopRequested(Direction),
+    /// Stopping the motor
+    Stopping(Direction),
+    /// Stepper motor is running
+    Running {
+        dir: Direction,
+        /// If chasing the thread
+        is_cutting_thread: bool,
+    },
+    /// Awaiting for the spindle sync event to initiate thread cutting
+    ThreadStart,
+    /// Awaiting for the delay before we start accelerating our stepper
+    ThreadDelay,
+    /// Following the spindle, but not moving: waiting for the spindle to rotate further
+    GearHold(Direction),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StepperError {
+    /// Stepper is not stopped to run given command
+    NotStopped,
+
+    /// Stepgen error
+    StepgenError(stepgen::Error),
+
+    /// Hard limit switch was hit moving in this direction
+    HardLimit,
+}
+
+/// Hard limit switch state, while it is pressed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Limit {
+    /// Switch was hit moving in this direction. Moves in this direction are refused.
+    Hit(Direction),
+    /// Switch was pressed with nothing moved yet (like at power-up), so which end it is at is
+    /// unknown. All moves are refused.
+    Unknown,
+}
+
+// Written out, as the derived one pulls the generic debug formatting into the firmware (it is only
+// used for the panic messages)
+impl core::fmt::Debug for StepperError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(match self {
+            StepperError::NotStopped => "NotStopped",
+            StepperError::StepgenError(stepgen::Error::TooSlow) => "TooSlow",
+            StepperError::StepgenError(stepgen::Error::TooFast) => "TooFast",
+            StepperError::StepgenError(_) => "SpeedAccelerationNotSet",
+            StepperError::HardLimit => "HardLimit",
+        })
+    }
+}
+
+impl From<stepgen::Error> for StepperError {
+    fn from(err: stepgen::Error) -> Self {
+        StepperError::StepgenError(err)
+    }
+}
+
+/// Move following the other axis rather than the spindle (see `Stepper::follow_to`)
+struct Follow {
+    /// Position of the leading axis the move started at
+    origin: i32,
+    /// Position of the leading axis at the previous spindle event
+    last: i32,
+    /// (Micro-)steps per step of the leading axis
+    ratio: Ratio,
+    /// Stepgen step the move started at (once the backlash is taken up)
+    start: u32,
+}
+
+pub struct Stepper<S: StepperDriver> {
+    stepgen: stepgen::Stepgen,
+    threads: crate::threads::ThreadInfo,
+    gearing: Gearing,
+    follow: Option<Follow>,
+    driver: S,
+    reversed: bool,
+    // We never manually control screw on a the lathe, so we never want to disable the driver,
+    // to prevent accidental screw changes.
+    disable_at_stop: bool,
+
+    base_step: u32,
+    position: i32,
+
+    /// Backlash of the leadscrew, in (micro-)steps
+    backlash: u32,
+    /// Where leadscrew is within its backlash: `0` once taken up moving left, `backlash` once taken
+    /// up moving right. `None` if nothing moved yet, so it is unknown.
+    play: Option<u32>,
+    /// Steps at the start of the current move which take up the backlash and do not move the
+    /// carriage (not counted in the position)
+    take_up: u32,
+
+    /// Home switch is pressed
+    home_switch: bool,
+    /// Stop once the home switch is pressed and remember the position (see `seek_home`)
+    seeking_home: bool,
+    /// Position home switch was pressed at while seeking home
+    home: Option<i32>,
+    /// Hard limit switch state, `None` if it is released
+    limit: Option<Limit>,
+    /// Direction of the last move, `None` if nothing moved yet
+    last_dir: Option<Direction>,
+
+    state: State,
+}
+
+// Round value from 16.8 format to u16
+fn round16_8(delay: u32) -> u16 {
+    let d = (delay + 128) >> 8;
+    d as u16
+}
+
+impl<S: StepperDriver> Stepper<S> {
+    pub fn new(freq: u32, driver: S, disable_at_stop: bool) -> Stepper<S> {
+        Stepper {
+            driver,
+            stepgen: stepgen::Stepgen::new(freq),
+            threads: crate::threads::ThreadInfo::new(freq),
+            gearing: Gearing::new(),
+            follow: None,
+            reversed: false,
+            disable_at_stop,
+            base_step: 0,
+            position: 0,
+            backlash: 0,
+            play: None,
+            take_up: 0,
+            home_switch: false,
+            seeking_home: false,
+            home: None,
+            limit: None,
+            last_dir: None,
+            state: State::Stopped,
+        }
+    }
+
+    /// Set reversed flag
+    pub fn set_reversed(&mut self, reversed: bool) {
+        self.reversed = reversed;
+    }
+
+    /// Set whether driver is disabled once stepper stops (on the mill) or kept enabled (on the
+    /// lathe).
+    pub fn set_disable_at_stop(&mut self, disable_at_stop: bool) {
+        self.disable_at_stop = disable_at_stop;
+    }
+
+    /// Set backlash of the leadscrew, in (micro-)steps. Backlash is taken up every time direction
+    /// changes; these steps are not counted in the position.
+    pub fn set_backlash(&mut self, backlash: u32) {
+        if backlash != self.backlash {
+            // Don't know where we are within the new backlash, next move will tell
+            self.backlash = backlash;
+            self.play = None;
+        }
+    }
+
+    /// Set new acceleration (steps per second per second), in 24.8 format.
+    pub fn set_acceleration(&mut self, acceleration: u32) -> Result<(), StepperError> {
+        self.stepgen.set_acceleration(acceleration)?;
+        self.threads.set_acceleration(acceleration);
+        self.gearing.set_acceleration(acceleration);
+        Ok(())
+    }
+
+    /// Set gain of the thread cutting phase correction, in percent. `0` disables the correction.
+    pub fn set_phase_gain(&mut self, gain: u32) {
+        self.threads.set_phase_gain(gain);
+    }
+
+    /// Set slew speed (maximum speed stepper motor would run).
+    ///
+    /// Sets desired slew speed, a maximum speed stepper motor would accelerate to. Note that
+    /// stepper motor would only reach this speed if destination step is far enough, so there is
+    /// enough time for deceleration.
+    ///
+    /// * `speed` - target slew speed to reach, in (micro-)steps per second, 24.8 format
+    pub fn set_speed(&mut self, speed: u32) -> Result<(), StepperError> {
+        Ok(self.stepgen.set_target_speed(speed)?)
+    }
+
+    /// Returns `false` no new delay was loaded
+    fn preload_delay(&mut self) {
+        match self.stepgen.next() {
+            Some(delay) => self.driver.preload_delay(round16_8(delay)),
+            None => {
+                if let State::Running { dir, .. } = self.state {
+                    self.state = State::Stopping(dir);
+                }
+                self.driver.set_last()
+            }
+        }
+    }
+
+    pub fn interrupt(&mut self) {
+        if !self.driver.interrupt() {
+            return;
+        }
+
+        match self.state {
+            State::StopRequested(dir) => {
+                // Initiate stopping sequence -- set target step to 0
+                self.stepgen.set_target_step(0).unwrap();
+                self.state = State::Stopping(dir);
+                self.preload_delay();
+            }
+            State::Stopping(dir) if !self.driver.is_running() && self.gearing.is_engaged() => {
+                // Got ahead of the spindle, wait for it to rotate further
+                self.state = State::GearHold(dir);
+                self.update_position(dir);
+            }
+            State::Stopping(dir) if !self.driver.is_running() => {
+                if self.disable_at_stop {
+                    self.driver.set_enable(false);
+                }
+                self.state = State::Stopped;
+                self.seeking_home = false;
+                // FIXME: reset thread cutting info
+
+                // Update internal position counter. We do it at the end to reduce amount of work
+                // we do per step (direction could not be changed while running, so all steps go
+                // in one direction).
+                self.update_position(dir);
+                // Do not preload the delay -- we are stopped now
+            }
+            State::Stopping(_) | State::Running { .. } => {
+                // Just preload the delay
+                self.preload_delay();
+            }
+            State::Stopped | State::GearHold(_) => {
+                panic!("Should not receive interrupts when stopped!")
+            }
+            State::ThreadStart => {
+                panic!("Should not receive interrupts when waiting for the spindle!")
+            }
+            State::ThreadDelay if !self.driver.is_running() => {
+                // Finished our delay, need to initiate thread cutting
+                self.driver.set_timer_output(true);
+                self.move_to(self.threads.target_position()).unwrap();
+            }
+            State::ThreadDelay => match self.threads.next_wait_delay() {
+                0 => self.driver.set_last(),
+                delay => self.driver.preload_delay(delay),
+            },
+        };
+    }
+
+    // Incorporate outstanding steps from the stepgen into current position
+    fn update_position(&mut self, dir: Direction) {
+        let step_pos = self.calc_position(dir);
+        let steps = step_pos.0 - self.base_step;
+        let play = self.play.unwrap_or(match dir {
+            Direction::Left => 0,
+            Direction::Right => self.backlash,
+        });
+        self.play = Some(match dir {
+            Direction::Left => play.saturating_sub(steps),
+            Direction::Right => (play + steps).min(self.backlash),
+        });
+        self.take_up -= steps.min(self.take_up);
+        self.base_step = step_pos.0;
+        self.position = step_pos.1;
+    }
+
+    // Compute current position based on stepgen step + last position
+    fn calc_position(&self, dir: Direction) -> (u32, i32) {
+        let step = self.stepgen.current_step();
+        let offset = (step - self.base_step).saturating_sub(self.take_up) as i32;
+        match dir {
+            Direction::Left => (step, self.position - offset),
+            Direction::Right => (step, self.position + offset),
+        }
+    }
+
+    /// Amount of steps needed to take up the backlash before carriage moves in the given direction.
+    fn backlash_steps(&self, dir: Direction) -> u32 {
+        match (self.play, dir) {
+            (None, _) => 0,
+            (Some(play), Direction::Left) => play,
+            (Some(play), Direction::Right) => self.backlash - play,
+        }
+    }
+
+    /// Move to given position. Note that no new move commands will be accepted while stepper is
+    /// running. However, other target parameter, target speed, could be changed any time.
+    pub fn move_to(&mut self, target: i32) -> Result<(), StepperError> {
+        if self.state != State::Stopped && self.state != State::ThreadDelay {
+            return Err(StepperError::NotStopped);
+        }
+
+        if self.position == target {
+            // Nothing to do!
+            self.state = State::Stopped;
+            return Ok(());
+        }
+
+        let delta = target - self.position;
+        let dir = if delta > 0 {
+            Direction::Right
+        } else {
+            Direction::Left
+        };
+        if self.is_limited(dir) {
+            return Err(StepperError::HardLimit);
+        }
+        let is_cutting_thread = self.state == State::ThreadDelay;
+        self.take_up = self.backlash_steps(dir);
+        self.start(
+            dir,
+            is_cutting_thread,
+            self.base_step + self.take_up + delta.unsigned_abs(),
+        )
+    }
+
+    /// Start running in the given direction until stepgen reaches `target_step`.
+    fn start(
+        &mut self,
+        dir: Direction,
+        is_cutting_thread: bool,
+        target_step: u32,
+    ) -> Result<(), StepperError> {
+        self.state = State::Running {
+            dir,
+            is_cutting_thread,
+        };
+        self.last_dir = Some(dir);
+        self.stepgen.set_target_step(target_step)?;
+
+        // Set direction and enable driver outputs
+        let dir_bit = match dir {
+            Direction::Left => self.reversed,
+            Direction::Right => !self.reversed,
+        };
+        self.driver.set_direction(dir_bit);
+        self.driver.set_enable(true);
+
+        // Start pulse generation
+        let delay = self.stepgen.next().unwrap();
+        self.driver.start(round16_8(delay));
+
+        // Immediately preload the second delay
+        self.preload_delay();
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.gearing.disengage();
+        match self.state {
+            State::Running { dir, .. } => self.state = State::StopRequested(dir),
+            State::GearHold(_) => self.release_hold(),
+            // Still waiting for the spindle, nothing is running yet
+            State::ThreadStart => self.state = State::Stopped,
+            _ => {}
+        }
+    }
+
+    fn release_hold(&mut self) {
+        if self.disable_at_stop {
+            self.driver.set_enable(false);
+        }
+        self.state = State::Stopped;
+    }
+
+    /// Set amount of steps per spindle revolution for moves following the spindle. If currently
+    /// following the spindle, new ratio takes effect from the next spindle event.
+    pub fn set_gear_ratio(&mut self, ratio: Ratio) {
+        self.gearing.set_ratio(ratio);
+    }
+
+    /// Move to given position following the spindle (electronic leadscrew). Stepper starts moving
+    /// at the next spindle event and stops if the spindle stops.
+    pub fn gear_to(&mut self, target: i32) -> Result<(), StepperError> {
+        if self.state != State::Stopped {
+            return Err(StepperError::NotStopped);
+        }
+        if self.position == target {
+            return Ok(());
+        }
+
+        let delta = target - self.position;
+        let dir = if delta > 0 {
+            Direction::Right
+        } else {
+            Direction::Left
+        };
+        if self.is_limited(dir) {
+            return Err(StepperError::HardLimit);
+        }
+        self.take_up = self.backlash_steps(dir);
+        self.gearing
+            .engage(self.base_step + self.take_up + delta.unsigned_abs());
+        self.follow = None;
+        self.driver.set_enable(true);
+        self.state = State::GearHold(dir);
+        self.last_dir = Some(dir);
+        Ok(())
+    }
+
+    /// Move to given position following the other axis (like the cross-slide following the
+    /// carriage to cut the taper): stepper makes `ratio` steps per step of the leading axis, which
+    /// is at `leading` now. Leading axis position is checked at every spindle event, see
+    /// `follow_sync`.
+    pub fn follow_to(
+        &mut self,
+        target: i32,
+        leading: i32,
+        ratio: Ratio,
+    ) -> Result<(), StepperError> {
+        self.gear_to(target)?;
+        self.follow = Some(Follow {
+            origin: leading,
+            last: leading,
+            ratio,
+            start: self.base_step + self.take_up,
+        });
+        Ok(())
+    }
+
+    /// Switch the current move between following the spindle and running at the speed set by
+    /// `set_speed`. Does nothing if not moving.
+    pub fn set_geared(&mut self, geared: bool) -> Result<(), StepperError> {
+        match self.state {
+            State::Running {
+                is_cutting_thread: false,
+                ..
+            } if geared != self.gearing.is_engaged() => {
+                if geared {
+                    // Stepgen target step is where the move finishes
+                    self.gearing.engage(self.stepgen.target_step());
+                } else {
+                    self.gearing.disengage();
+                    self.stepgen.set_target_step(self.gearing.final_step())?;
+                }
+            }
+            State::GearHold(dir) if !geared => {
+                self.gearing.disengage();
+                self.start(dir, false, self.gearing.final_step())?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Get the stepper state
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn position(&self) -> i32 {
+        if let State::Running { dir, .. } = self.state {
+            self.calc_position(dir).1
+        } else {
+            self.position
+        }
+    }
+
+    /// Move to given position. Note that no new move commands will be accepted while stepper is
+    /// running. However, other target parameter, target speed, could be changed any time.
+    pub fn thread_start(
+        &mut self,
+        target: i32,
+        ratio: Ratio,
+        phase: Phase,
+        estimated_rpm: u32,
+    ) -> Result<(), StepperError> {
+        if self.state != State::Stopped {
+            return Err(StepperError::NotStopped);
+        }
+        let dir = if target > self.position {
+            Direction::Right
+        } else {
+            Direction::Left
+        };
+        if self.is_limited(dir) {
+            return Err(StepperError::HardLimit);
+        }
+
+        // Backlash is taken up once the delay is over, nothing moves until then
+        let take_up = self.backlash_steps(dir);
+        self.threads
+            .setup_thread_cutting(target, ratio, phase, take_up, estimated_rpm)?;
+        let target_speed = self.threads.calculate_speed(estimated_rpm, 0, false);
+        self.stepgen.set_target_speed(target_speed)?;
+        self.state = State::ThreadStart;
+        Ok(())
+    }
+
+    /// Distance (in steps) stepper needs to get up to the thread cutting speed and to stop from it.
+    pub fn thread_ramp(&self, ratio: Ratio, estimated_rpm: u32) -> Result<u32, StepperError> {
+        Ok(2 * self.threads.acceleration_steps(ratio, estimated_rpm)?)
+    }
+
+    /// Synchronize stepper driver with the spindle rotation
+    pub fn spindle_sync(&mut self, rpm: u32) {
+        match self.state {
+            State::ThreadStart => {
+                // Initiate timer-driven delay for thread cutting
+                self.driver.set_timer_output(false);
+                let delay = self.threads.next_wait_delay();
+                self.state = State::ThreadDelay;
+                self.driver.start(delay);
+
+                // Preload the next delay
+                match self.threads.next_wait_delay() {
+                    0 => self.driver.set_last(),
+                    delay => self.driver.preload_delay(delay),
+                }
+            }
+            State::Running {
+                is_cutting_thread, ..
+            } if is_cutting_thread => {
+                let step = self.stepgen.current_step();
+                let steps_since_start = step - self.base_step;
+                let is_at_speed = self.stepgen.is_at_speed();
+                let target_speed =
+                    self.threads
+                        .calculate_speed(rpm, steps_since_start, is_at_speed);
+                self.stepgen.set_target_speed(target_speed).unwrap();
+            }
+
+            State::Running {
+                is_cutting_thread: false,
+                ..
+            }
+            | State::GearHold(_)
+                if self.gearing.is_engaged() && self.follow.is_none() =>
+            {
+                let cmd = self.gearing.spindle_sync(self.stepgen.current_step(), rpm);
+                self.gear_command(cmd);
+            }
+
+            _ => {
+                // Not cutting threads, do nothing
+            }
+        }
+    }
+
+    /// Synchronize stepper following the other axis (see `follow_to`) at the spindle event.
+    /// `leading` is the current position of the leading axis.
+    pub fn follow_sync(&mut self, leading: i32, rpm: u32) {
+        let Some(follow) = &mut self.follow else {
+            return;
+        };
+        match self.state {
+            State::Running {
+                is_cutting_thread: false,
+                ..
+            }
+            | State::GearHold(_)
+                if self.gearing.is_engaged() =>
+            {
+                // Expected position is calculated from the start, so rounding does not accumulate
+                let progress =
+                    u64::from(follow.start) + follow.ratio.steps(leading.abs_diff(follow.origin));
+                let expected = u32::try_from(progress).unwrap_or(u32::MAX);
+                let advance = follow.ratio.steps(leading.abs_diff(follow.last)) as u32;
+                follow.last = leading;
+                let step = self.stepgen.current_step();
+                let cmd = self.gearing.follow_sync(step, expected, advance, rpm);
+                self.gear_command(cmd);
+            }
+            _ => {}
+        }
+    }
+
+    /// Apply the command calculated at the spindle event to the stepper following the spindle (or
+    /// the other axis).
+    fn gear_command(&mut self, cmd: GearCommand) {
+        if let Some(speed) = cmd.speed {
+            // Keep the previous speed if new one is out of range
+            let _ = self.stepgen.set_target_speed(speed);
+        }
+        if let State::GearHold(dir) = self.state {
+            if cmd.speed.is_some() && cmd.target_step > self.base_step {
+                self.start(dir, false, cmd.target_step).unwrap();
+            } else if !self.gearing.is_engaged() {
+                // Already at the final step
+                self.release_hold();
+            }
+        } else {
+            self.stepgen.set_target_step(cmd.target_step).unwrap();
+        }
+    }
+
+    /// Set the current position, for example, to zero it at the home switch.
+    pub fn set_position(&mut self, position: i32) -> Result<(), StepperError> {
+        if self.state != State::Stopped {
+            return Err(StepperError::NotStopped);
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    /// Move towards the given position, stopping as soon as the home switch is pressed. Position
+    /// the switch was pressed at is available via `home_position` once stepper is stopped.
+    pub fn seek_home(&mut self, target: i32) -> Result<(), StepperError> {
+        self.home = None;
+        self.move_to(target)?;
+        self.seeking_home = self.state != State::Stopped;
+        Ok(())
+    }
+
+    /// Position the home switch was pressed at during the last `seek_home`, `None` if it was not
+    /// pressed.
+    pub fn home_position(&self) -> Option<i32> {
+        self.home
+    }
+
+    /// Update the state of the home switch. Called from the switch interrupt, so the position is
+    /// captured as soon as the switch is pressed.
+    pub fn set_home_switch(&mut self, pressed: bool) {
+        self.home_switch = pressed;
+        if !pressed || !self.seeking_home {
+            return;
+        }
+        if let State::Running { dir, .. } | State::StopRequested(dir) | State::Stopping(dir) =
+            self.state
+        {
+            self.home = Some(self.calc_position(dir).1);
+            self.seeking_home = false;
+            self.stop();
+        }
+    }
+
+    /// Check if the home switch is pressed.
+    pub fn is_home_switch_pressed(&self) -> bool {
+        self.home_switch
+    }
+
+    /// Update the state of the hard limit switches. Called from the switch interrupt: stepper is
+    /// stopped (with deceleration, so switches should allow some overtravel) and moves in the
+    /// same direction are refused until the switch is released. Switch pressed while stopped is
+    /// taken to be at the end the last move went to.
+    pub fn set_limit_switch(&mut self, pressed: bool) {
+        if !pressed {
+            self.limit = None;
+            return;
+        }
+        let dir = match self.state {
+            State::Running { dir, .. }
+            | State::StopRequested(dir)
+            | State::Stopping(dir)
+            | State::GearHold(dir) => Some(dir),
+            _ => self.last_dir,
+        };
+        self.limit = Some(dir.map_or(Limit::Unknown, Limit::Hit));
+        self.stop();
+    }
+
+    /// Hard limit switch state, if it is pressed.
+    pub fn hard_limit(&self) -> Option<Limit> {
+        self.limit
+    }
+
+    fn is_limited(&self, dir: Direction) -> bool {
+        match self.limit {
+            Some(Limit::Hit(limit)) => limit == dir,
+            Some(Limit::Unknown) => true,
+            None => false,
+        }
+    }
+
+    /// Get last out-of-phase error for thread cutting
+    pub fn last_error_degrees(&self) -> i32 {
+        self.threads.last_error_degrees()
+    }
+
+    /// Get the stepper motor driver
+    pub fn driver(&self) -> &S {
+        &self.driver
+    }
+
+    /// Get the stepper motor driver, mutable
+    pub fn driver_mut(&mut self) -> &mut S {
+        &mut self.driver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimDriver;
+
+    const FREQUENCY: u32 = 1_000_000;
+    const ACCELERATION: u32 = 1200 << 8;
+    const SPEED: u32 = 800 << 8;
+
+    fn stepper(disable_at_stop: bool) -> Stepper<SimDriver> {
+        let mut stepper = Stepper::new(FREQUENCY, SimDriver::new(), disable_at_stop);
+        stepper.set_acceleration(ACCELERATION).unwrap();
+        stepper.set_speed(SPEED).unwrap();
+        stepper
+    }
+
+    /// Run the timer and the stepper interrupt handler until `until` or until stepper stops.
+    fn run_until(stepper: &mut Stepper<SimDriver>, until: u64) {
+        while stepper.state() != State::Stopped && stepper.driver_mut().advance(until) {
+            stepper.interrupt();
+        }
+    }
+
+    fn run_until_stopped(stepper: &mut Stepper<SimDriver>) {
+        run_until(stepper, u64::MAX);
+        assert_eq!(State::Stopped, stepper.state());
+    }
+
+    /// Delays between consecutive steps
+    fn intervals(stepper: &Stepper<SimDriver>) -> Vec<u64> {
+        let steps = stepper.driver().steps();
+        steps.windows(2).map(|w| w[1].time - w[0].time).collect()
+    }
+
+    #[test]
+    fn move_to_makes_exact_steps() {
+        let mut stepper = stepper(true);
+        stepper.move_to(1000).unwrap();
+        assert_eq!(
+            State::Running {
+                dir: Direction::Right,
+                is_cutting_thread: false
+            },
+            stepper.state()
+        );
+        assert!(stepper.driver().is_enabled());
+
+        run_until_stopped(&mut stepper);
+        assert_eq!(1000, stepper.driver().steps().len());
+        assert!(stepper.driver().steps().iter().all(|s| s.direction));
+        assert_eq!(1000, stepper.position());
+        assert!(!stepper.driver().is_enabled());
+        assert!(!stepper.driver().is_running());
+
+        stepper.driver_mut().take_steps();
+        stepper.move_to(-500).unwrap();
+        run_until_stopped(&mut stepper);
+        assert_eq!(1500, stepper.driver().steps().len());
+        assert!(stepper.driver().steps().iter().all(|s| !s.direction));
+        assert_eq!(-500, stepper.position());
+    }
+
+    #[test]
+    fn move_to_current_position() {
+        let mut stepper = stepper(true);
+        stepper.move_to(0).unwrap();
+        assert_eq!(State::Stopped, stepper.state());
+        assert!(!stepper.driver().is_running());
+    }
+
+    #[test]
+    fn reversed() {
+        let mut stepper = stepper(true);
+        stepper.set_reversed(true);
+        stepper.move_to(10).unwrap();
+        run_until_stopped(&mut stepper);
+        assert!(stepper.driver().steps().iter().all(|s| !s.direction));
+        assert_eq!(10, stepper.position());
+    }
+
+    #[test]
+    fn acceleration_profile() {
+        let mut stepper = stepper(true);
+        stepper.move_to(2000).unwrap();
+        run_until_stopped(&mut stepper);
+
+        let intervals = intervals(&stepper);
+        // 800 steps per second
+        let slew = u64::from(FREQUENCY / 800);
+        // It takes v^2/2a = 266 steps to accelerate to 800 steps per second at 1200 steps/s^2
+        let ramp = 266;
+        let (accel, rest) = intervals.split_at(ramp);
+        let (slewing, decel) = rest.split_at(rest.len() - ramp);
+        assert!(accel.windows(2).all(|w| w[0] >= w[1]));
+        assert!(decel.windows(2).all(|w| w[0] <= w[1]));
+        assert!(slewing.iter().all(|&d| d.abs_diff(slew) <= 1));
+        assert!(accel[0] > 10 * slew);
+        assert!(decel[decel.len() - 1] > 10 * slew);
+    }
+
+    #[test]
+    fn position_while_running() {
+        let mut stepper = stepper(true);
+        stepper.move_to(-1000).unwrap();
+        run_until(&mut stepper, 500_000);
+        let emitted = stepper.driver().steps().len() as i32;
+        assert!(emitted > 0);
+        // Position is tracked by the stepgen, which is ahead of the emitted pulses by the loaded
+        // and the preloaded delays
+        assert!((stepper.position() + emitted).abs() <= 2);
+    }
+
+    #[test]
+    fn busy_while_running() {
+        let mut stepper = stepper(true);
+        stepper.move_to(1000).unwrap();
+        assert_eq!(Err(StepperError::NotStopped), stepper.move_to(0));
+        assert_eq!(
+            Err(StepperError::NotStopped),
+            stepper.thread_start(0, Ratio::new(3200, 1), Phase::new(0), 200 << 8)
+        );
+    }
+
+    #[test]
+    fn stop_decelerates() {
+        let mut stepper = stepper(false);
+        stepper.move_to(-100_000).unwrap();
+        run_until(&mut stepper, 1_000_000);
+        let position = stepper.position();
+
+        stepper.stop();
+        assert_eq!(State::StopRequested(Direction::Left), stepper.state());
+        run_until_stopped(&mut stepper);
+
+        // Stopped after the deceleration ramp (266 steps), well before the target
+        let ramp = position - stepper.position();
+        assert!((260..=270).contains(&ramp), "ramp {}", ramp);
+        assert_eq!(stepper.driver().steps().len() as i32, -stepper.position());
+        let intervals = intervals(&stepper);
+        let decel = &intervals[intervals.len() - 260..];
+        assert!(decel.windows(2).all(|w| w[0] <= w[1]));
+        // Lathe never disables the driver
+        assert!(stepper.driver().is_enabled());
+    }
+
+    #[test]
+    fn stop_when_stopped() {
+        let mut stepper = stepper(true);
+        stepper.stop();
+        assert_eq!(State::Stopped, stepper.state());
+    }
+
+    #[test]
+    fn stop_waiting_for_spindle() {
+        let mut stepper = stepper(false);
+        stepper
+            .thread_start(10_000, Ratio::new(3200, 1), Phase::new(0), 200 << 8)
+            .unwrap();
+        assert_eq!(State::ThreadStart, stepper.state());
+        stepper.stop();
+        assert_eq!(State::Stopped, stepper.state());
+        // Spindle events are ignored now
+        stepper.spindle_sync(200 << 8);
+        assert_eq!(State::Stopped, stepper.state());
+        assert!(stepper.driver().steps().is_empty());
+    }
+
+    /// Move to the target, return amount of steps made.
+    fn steps_to(stepper: &mut Stepper<SimDriver>, target: i32) -> usize {
+        let before = stepper.driver().steps().len();
+        stepper.move_to(target).unwrap();
+        run_until_stopped(stepper);
+        assert_eq!(target, stepper.position());
+        stepper.driver().steps().len() - before
+    }
+
+    #[test]
+    fn backlash_taken_up_on_reversal() {
+        let mut stepper = stepper(false);
+        stepper.set_backlash(50);
+        // Nothing is known about the backlash before the first move
+        assert_eq!(1000, steps_to(&mut stepper, 1000));
+        assert_eq!(1050, steps_to(&mut stepper, 0));
+        assert_eq!(100, steps_to(&mut stepper, -100));
+        assert_eq!(150, steps_to(&mut stepper, 0));
+
+        // Changing the backlash forgets where we are
+        stepper.set_backlash(20);
+        assert_eq!(100, steps_to(&mut stepper, -100));
+        assert_eq!(120, steps_to(&mut stepper, 0));
+        // Setting the same value again changes nothing
+        stepper.set_backlash(20);
+        assert_eq!(120, steps_to(&mut stepper, -100));
+    }
+
+    #[test]
+    fn backlash_not_counted_in_position() {
+        let mut stepper = stepper(false);
+        stepper.set_backlash(200);
+        steps_to(&mut stepper, 1000);
+        stepper.move_to(0).unwrap();
+        let mut positions = Vec::new();
+        while stepper.state() != State::Stopped && stepper.driver_mut().advance(u64::MAX) {
+            stepper.interrupt();
+            if let State::Running { .. } = stepper.state() {
+                positions.push(stepper.position());
+            }
+        }
+        // Position does not change while backlash is taken up, then goes down to the target
+        // (stepgen runs a couple of steps ahead of the driver)
+        let unchanged = positions.iter().filter(|&&p| p == 1000).count();
+        assert!((195..=200).contains(&unchanged), "unchanged {}", unchanged);
+        assert!(positions.windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(0, stepper.position());
+    }
+
+    #[test]
+    fn backlash_partially_taken_up() {
+        let mut stepper = stepper(false);
+        stepper.set_backlash(500);
+        steps_to(&mut stepper, 10_000);
+        // Stop while still taking up the backlash
+        stepper.move_to(0).unwrap();
+        let until = stepper.driver().now() + 100_000;
+        run_until(&mut stepper, until);
+        stepper.stop();
+        run_until_stopped(&mut stepper);
+        assert_eq!(10_000, stepper.position());
+        let made = stepper.driver().steps().len() - 10_000;
+        assert!(made > 0 && made < 500, "made {}", made);
+
+        // Remaining backlash is taken up when continuing, what was taken up is given back when
+        // reversing
+        assert_eq!(500 - made + 1000, steps_to(&mut stepper, 9_000));
+        assert_eq!(500 + 1000, steps_to(&mut stepper, 10_000));
+    }
+
+    #[test]
+    fn seek_home_captures_position() {
+        let mut stepper = stepper(true);
+        stepper.seek_home(-10_000).unwrap();
+        let until = stepper.driver().now() + 200_000;
+        run_until(&mut stepper, until);
+        let pressed_at = stepper.position();
+        stepper.set_home_switch(true);
+        assert!(stepper.is_home_switch_pressed());
+        run_until_stopped(&mut stepper);
+
+        // Stopped with deceleration, past the switch
+        let home = stepper.home_position().unwrap();
+        assert!((pressed_at - 2..=pressed_at).contains(&home));
+        assert!(stepper.position() < home);
+        assert!(stepper.position() > -10_000);
+
+        // Zero the position at the switch
+        let position = stepper.position();
+        stepper.set_position(position - home).unwrap();
+        stepper.move_to(0).unwrap();
+        run_until_stopped(&mut stepper);
+        assert_eq!(0, stepper.position());
+
+        // Switch does not stop regular moves
+        stepper.set_home_switch(false);
+        stepper.move_to(-100).unwrap();
+        stepper.set_home_switch(true);
+        run_until_stopped(&mut stepper);
+        assert_eq!(-100, stepper.position());
+    }
+
+    #[test]
+    fn hard_limit_stops_and_refuses_moves() {
+        let mut stepper = stepper(true);
+        stepper.move_to(10_000).unwrap();
+        let until = stepper.driver().now() + 200_000;
+        run_until(&mut stepper, until);
+        stepper.set_limit_switch(true);
+        assert_eq!(State::StopRequested(Direction::Right), stepper.state());
+        run_until_stopped(&mut stepper);
+        assert!(stepper.position() < 10_000);
+        assert_eq!(Some(Limit::Hit(Direction::Right)), stepper.hard_limit());
+
+        // Only moving away from the limit is allowed
+        let position = stepper.position();
+        assert_eq!(
+            Err(StepperError::HardLimit),
+            stepper.move_to(position + 100)
+        );
+        assert_eq!(
+            Err(StepperError::HardLimit),
+            stepper.gear_to(position + 100)
+        );
+        stepper.move_to(position - 100).unwrap();
+        run_until_stopped(&mut stepper);
+
+        stepper.set_limit_switch(false);
+        assert_eq!(None, stepper.hard_limit());
+        stepper.move_to(position).unwrap();
+        run_until_stopped(&mut stepper);
+        assert_eq!(position, stepper.position());
+    }
+
+    #[test]
+    fn hard_limit_pressed_while_stopped() {
+        // At power-up it is unknown which end the switch is at
+        let mut stepper = stepper(true);
+        stepper.set_limit_switch(true);
+        assert_eq!(State::Stopped, stepper.state());
+        assert_eq!(Some(Limit::Unknown), stepper.hard_limit());
+        assert_eq!(Err(StepperError::HardLimit), stepper.move_to(100));
+        assert_eq!(Err(StepperError::HardLimit), stepper.move_to(-100));
+        assert_eq!(Err(StepperError::HardLimit), stepper.gear_to(100));
+        stepper.set_limit_switch(false);
+
+        // Move stopping right at the switch: it is at the end the move went to
+        stepper.move_to(-100).unwrap();
+        run_until_stopped(&mut stepper);
+        stepper.set_limit_switch(true);
+        assert_eq!(Some(Limit::Hit(Direction::Left)), stepper.hard_limit());
+        assert_eq!(Err(StepperError::HardLimit), stepper.move_to(-200));
+        stepper.move_to(0).unwrap();
+        run_until_stopped(&mut stepper);
+        assert_eq!(0, stepper.position());
+    }
+
+    // 200 RPM, 3200 steps per revolution (16 TPI)
+    const THREAD_RPM: u32 = 200;
+    const STEPS_PER_THREAD: u32 = 3200;
+
+    /// Cut the thread of 100 revolutions, with spindle revolution taking `period(revolution)`
+    /// ticks. Returns amount of steps made at each spindle event.
+    fn cut_thread(phase_gain: u32, period: impl Fn(usize) -> u64) -> Vec<u32> {
+        cut_thread_ratio(Ratio::new(STEPS_PER_THREAD, 1), phase_gain, period)
+    }
+
+    fn cut_thread_ratio(ratio: Ratio, phase_gain: u32, period: impl Fn(usize) -> u64) -> Vec<u32> {
+        let target = ratio.steps(100) as i32;
+
+        let mut stepper = stepper(false);
+        stepper.set_acceleration((1200 * 16) << 8).unwrap();
+        stepper.set_phase_gain(phase_gain);
+        stepper
+            .thread_start(target, ratio, Phase::new(0), THREAD_RPM << 8)
+            .unwrap();
+        assert_eq!(State::ThreadStart, stepper.state());
+
+        let mut revolutions = Vec::new();
+        let mut spindle = 0;
+        let mut rpm = THREAD_RPM << 8;
+        while stepper.state() != State::Stopped {
+            if !stepper.driver_mut().advance(spindle) {
+                stepper.spindle_sync(rpm);
+                revolutions.push(stepper.driver().steps().len() as u32);
+                let next = period(revolutions.len());
+                // Hall sensor measures the speed over the last revolution
+                rpm = (((60 * u64::from(FREQUENCY)) << 8) / next) as u32;
+                spindle += next;
+            } else {
+                stepper.interrupt();
+            }
+
+            if stepper.state() == State::ThreadDelay {
+                // No pulses are generated while waiting
+                assert!(!stepper.driver().is_output_enabled());
+            }
+        }
+
+        assert_eq!(target, stepper.position());
+        assert_eq!(target as usize, stepper.driver().steps().len());
+        // Thread starts at the first spindle event
+        assert_eq!(0, revolutions[0]);
+        // Last revolution is where stepper decelerates to stop at the target
+        revolutions.pop();
+        revolutions
+    }
+
+    /// Out-of-phase error, in steps
+    fn phase_error(steps: u32) -> u32 {
+        match steps % STEPS_PER_THREAD {
+            e if e > STEPS_PER_THREAD / 2 => STEPS_PER_THREAD - e,
+            e => e,
+        }
+    }
+
+    /// Spindle revolution period at the constant thread cutting RPM
+    fn constant_period(_revolution: usize) -> u64 {
+        60 * u64::from(FREQUENCY) / u64::from(THREAD_RPM)
+    }
+
+    #[test]
+    fn thread_cutting() {
+        let revolutions = cut_thread(0, constant_period);
+        // Once accelerated, stepper is in phase with the spindle
+        for &steps in &revolutions[2..6] {
+            assert!(
+                phase_error(steps) < STEPS_PER_THREAD / 50,
+                "error at {}",
+                steps
+            );
+        }
+        // ...but drifts away without phase correction (rounding of the step delays)
+        let last = *revolutions.last().unwrap();
+        assert!(
+            phase_error(last) > STEPS_PER_THREAD / 50,
+            "error at {}",
+            last
+        );
+    }
+
+    #[test]
+    fn thread_cutting_phase_locked() {
+        let revolutions = cut_thread(50, constant_period);
+        for &steps in &revolutions[2..] {
+            assert!(
+                phase_error(steps) < STEPS_PER_THREAD / 50,
+                "error at {}",
+                steps
+            );
+        }
+        // Error stays within one timer tick worth of speed (delays are rounded to whole ticks)
+        for &steps in &revolutions[15..] {
+            assert!(
+                phase_error(steps) <= STEPS_PER_THREAD / 100,
+                "error at {}",
+                steps
+            );
+        }
+    }
+
+    #[test]
+    fn thread_cutting_phase_kept() {
+        // Thread is started at 90 degrees, correction keeps it there
+        let mut stepper = stepper(false);
+        stepper.set_acceleration((1200 * 16) << 8).unwrap();
+        stepper.set_phase_gain(50);
+        let phase = Phase::new(90);
+        let target = 100 * STEPS_PER_THREAD as i32;
+        stepper
+            .thread_start(
+                target,
+                Ratio::new(STEPS_PER_THREAD, 1),
+                phase,
+                THREAD_RPM << 8,
+            )
+            .unwrap();
+        let mut revolutions = Vec::new();
+        let mut spindle = 0;
+        while stepper.state() != State::Stopped {
+            if !stepper.driver_mut().advance(spindle) {
+                stepper.spindle_sync(THREAD_RPM << 8);
+                revolutions.push(stepper.driver().steps().len() as u32);
+                spindle += constant_period(0);
+            } else {
+                stepper.interrupt();
+            }
+        }
+        for &steps in &revolutions[15..95] {
+            let steps = steps + STEPS_PER_THREAD - STEPS_PER_THREAD / 4;
+            assert!(
+                phase_error(steps) <= STEPS_PER_THREAD / 100,
+                "error at {}",
+                steps
+            );
+        }
+    }
+
+    #[test]
+    fn thread_cutting_takes_up_backlash() {
+        // Carriage came from the right, so backlash is taken up at the start of the cut
+        let mut stepper = stepper(false);
+        stepper.set_acceleration((1200 * 16) << 8).unwrap();
+        stepper.set_phase_gain(50);
+        stepper.set_backlash(400);
+        stepper.move_to(-100).unwrap();
+        run_until_stopped(&mut stepper);
+        let base = stepper.driver().steps().len() as u32;
+        let mut spindle = stepper.driver().now();
+
+        let target = 100 * STEPS_PER_THREAD as i32 - 100;
+        stepper
+            .thread_start(
+                target,
+                Ratio::new(STEPS_PER_THREAD, 1),
+                Phase::new(0),
+                THREAD_RPM << 8,
+            )
+            .unwrap();
+        let mut revolutions = Vec::new();
+        while stepper.state() != State::Stopped {
+            if !stepper.driver_mut().advance(spindle) {
+                stepper.spindle_sync(THREAD_RPM << 8);
+                revolutions.push(stepper.driver().steps().len() as u32 - base);
+                spindle += constant_period(0);
+            } else {
+                stepper.interrupt();
+            }
+        }
+        assert_eq!(target, stepper.position());
+        // Carriage is in phase with the spindle, stepper is ahead by the backlash
+        for &steps in &revolutions[15..95] {
+            assert!(
+                phase_error(steps - 400) <= STEPS_PER_THREAD / 100,
+                "error at {}",
+                steps
+            );
+        }
+    }
+
+    #[test]
+    fn thread_cutting_fractional() {
+        // 13 TPI on 16 TPI leadscrew: 3938.46 steps per revolution
+        let ratio = Ratio::new(51_200, 13);
+        let revolutions = cut_thread_ratio(ratio, 50, constant_period);
+        // Error (in steps) from the exact thread, not accumulating over 100 revolutions
+        let error = |steps: u32| {
+            let error = u64::from(steps) * 13 % 51_200;
+            error.min(51_200 - error) / 13
+        };
+        for (revolution, &steps) in revolutions.iter().enumerate().take(100).skip(15) {
+            assert!(error(steps) <= 3938 / 100, "error at {}", revolution);
+        }
+        // Rounding steps per revolution would be 46 steps off after 100 revolutions
+        assert_eq!(46, error(3938 * 100));
+    }
+
+    #[test]
+    fn thread_cutting_spindle_sags() {
+        // Spindle slows down by 3% under load for 10 revolutions, then recovers
+        let period = |revolution: usize| match revolution {
+            20..=29 => constant_period(revolution) * 103 / 100,
+            _ => constant_period(revolution),
+        };
+
+        // Speed follows the spindle with one revolution lag, so sag shifts the phase
+        let revolutions = cut_thread(0, period);
+        assert!(phase_error(revolutions[40]) > STEPS_PER_THREAD / 50);
+
+        // ...which is corrected back
+        let revolutions = cut_thread(50, period);
+        for &steps in &revolutions[2..] {
+            assert!(
+                phase_error(steps) < STEPS_PER_THREAD / 30,
+                "error at {}",
+                steps
+            );
+        }
+        for &steps in &revolutions[40..] {
+            assert!(
+                phase_error(steps) <= STEPS_PER_THREAD / 100,
+                "error at {}",
+                steps
+            );
+        }
+    }
+
+    #[test]
+    fn thread_cutting_followed() {
+        // Cross-slide makes a step per 16 steps of the carriage cutting the thread
+        let ratio = Ratio::new(1, 16);
+        let target = -(STEPS_PER_THREAD as i32) * 50;
+        let mut carriage = stepper(false);
+        carriage.set_acceleration((1200 * 16) << 8).unwrap();
+        let thread = Ratio::new(STEPS_PER_THREAD, 1);
+        carriage
+            .thread_start(target, thread, Phase::new(0), THREAD_RPM << 8)
+            .unwrap();
+        let mut cross = stepper(false);
+        cross.set_acceleration((1200 * 16) << 8).unwrap();
+        cross.follow_to(-target / 16, 0, ratio).unwrap();
+
+        let mut errors = Vec::new();
+        let mut spindle = 0;
+        while carriage.state() != State::Stopped || cross.state() != State::Stopped {
+            let next = [
+                carriage.driver().next_update(),
+                cross.driver().next_update(),
+                Some(spindle),
+            ];
+            let now = next.into_iter().flatten().min().unwrap();
+            if carriage.driver_mut().advance(now) {
+                carriage.interrupt();
+            }
+            if cross.driver_mut().advance(now) {
+                cross.interrupt();
+            }
+            if now == spindle {
+                // Position is only updated at stop
+                if let State::Running { .. } = cross.state() {
+                    errors.push(cross.position() + carriage.position() / 16);
+                }
+                carriage.spindle_sync(THREAD_RPM << 8);
+                cross.follow_sync(carriage.position(), THREAD_RPM << 8);
+                spindle += constant_period(0);
+            }
+        }
+        // Catches up once carriage is at speed, then stays within a couple of steps until
+        // carriage decelerates at the end
+        for (revolution, &error) in errors.iter().enumerate().take(errors.len() - 1).skip(4) {
+            assert!(error.abs() <= 2, "error {} at {}", error, revolution);
+        }
+
+        assert_eq!(target, carriage.position());
+        assert_eq!(-target / 16, cross.position());
+        assert_eq!(-target as usize / 16, cross.driver().steps().len());
+    }
+
+    // 0.004 IPR with 16 TPI leadscrew and 3200 steps per revolution: 204.8 steps per revolution
+    const GEAR_RATIO: Ratio = Ratio::new(2048, 10);
+    // 600 RPM
+    const GEAR_PERIOD: u64 = 100_000;
+
+    fn geared_stepper() -> Stepper<SimDriver> {
+        let mut stepper = stepper(true);
+        stepper.set_acceleration((1200 * 16) << 8).unwrap();
+        stepper.set_gear_ratio(GEAR_RATIO);
+        stepper
+    }
+
+    /// Rotate spindle for given amount of revolutions at 600 RPM, then let stepper run till it
+    /// settles. Returns the distance from the start at each spindle event.
+    fn rotate_spindle(stepper: &mut Stepper<SimDriver>, revolutions: usize) -> Vec<i32> {
+        let mut steps = Vec::new();
+        let mut spindle = stepper.driver().now();
+        while steps.len() < revolutions && stepper.state() != State::Stopped {
+            if stepper.driver_mut().advance(spindle) {
+                stepper.interrupt();
+            } else {
+                stepper.spindle_sync(600 << 8);
+                steps.push(stepper.position().abs());
+                spindle += GEAR_PERIOD;
+            }
+        }
+        while stepper.driver_mut().advance_to_update() {
+            stepper.interrupt();
+        }
+        steps
+    }
+
+    #[test]
+    fn geared_feed_follows_spindle() {
+        let mut stepper = geared_stepper();
+        stepper.gear_to(1_000_000).unwrap();
+        assert_eq!(State::GearHold(Direction::Right), stepper.state());
+        assert!(stepper.driver().is_enabled());
+
+        let steps = rotate_spindle(&mut stepper, 500);
+        // Catches up after accelerating, then stays within a step of the spindle with no drift
+        for (revolution, &steps) in steps.iter().enumerate().skip(10) {
+            let expected = GEAR_RATIO.steps(revolution as u32) as i32;
+            assert!((steps - expected).abs() <= 1, "{} at {}", steps, revolution);
+        }
+    }
+
+    #[test]
+    fn geared_feed_holds_when_spindle_stops() {
+        let mut stepper = geared_stepper();
+        stepper.gear_to(-1_000_000).unwrap();
+        rotate_spindle(&mut stepper, 50);
+
+        // Stepper does not run far ahead of the spindle
+        assert_eq!(State::GearHold(Direction::Left), stepper.state());
+        let position = stepper.position();
+        assert_eq!(stepper.driver().steps().len() as i32, -position);
+        let ahead = -position - GEAR_RATIO.steps(49) as i32;
+        assert!(ahead > 0 && ahead < 1000, "{} steps ahead", ahead);
+        assert!(stepper.driver().is_enabled());
+
+        // Spindle starts again
+        stepper.driver_mut().take_steps();
+        let steps = rotate_spindle(&mut stepper, 50);
+        assert!(steps[20] > GEAR_RATIO.steps(10) as i32);
+        assert_eq!(State::GearHold(Direction::Left), stepper.state());
+
+        stepper.stop();
+        assert_eq!(State::Stopped, stepper.state());
+        assert!(!stepper.driver().is_enabled());
+    }
+
+    #[test]
+    fn geared_feed_stops_at_target() {
+        let mut stepper = geared_stepper();
+        stepper.gear_to(5000).unwrap();
+        let steps = rotate_spindle(&mut stepper, 100);
+        assert!(steps.len() < 100);
+        assert_eq!(State::Stopped, stepper.state());
+        assert_eq!(5000, stepper.position());
+        assert_eq!(5000, stepper.driver().steps().len());
+    }
+
+    #[test]
+    fn geared_feed_released() {
+        let mut stepper = geared_stepper();
+        stepper.gear_to(5000).unwrap();
+        rotate_spindle(&mut stepper, 5);
+        assert_eq!(State::GearHold(Direction::Right), stepper.state());
+
+        // Runs on its own to the target
+        stepper.set_geared(false).unwrap();
+        run_until_stopped(&mut stepper);
+        assert_eq!(5000, stepper.position());
+    }
+}
@@ -0,0 +1,838 @@
+use crate::gearing::Ratio;
+use crate::stepper::Direction;
+use crate::units::{div_round, Scale, Units, NM_PER_INCH, NM_PER_MM};
+
+/// Phase correction never changes the speed by more than `1 / MAX_CORRECTION` of the target speed.
+const MAX_CORRECTION: u32 = 8;
+/// Infeed schedule makes at least that many cutting passes...
+const MIN_PASSES: u64 = 4;
+/// ...plus that many passes per millimeter of the thread depth.
+const PASSES_PER_MM: u64 = 3;
+/// Passes without infeed at the end of the schedule, to remove the spring of the tool and the work.
+const SPRING_PASSES: u16 = 2;
+
+/// Integral term of the phase correction is `1 / INTEGRAL_DIVISOR` of the accumulated error. It
+/// compensates for the constant speed offset (like rounding of the step delays), which
+/// proportional term alone would leave as a constant phase error.
+const INTEGRAL_DIVISOR: i64 = 4;
+
+/// Where the thread starts relative to the spindle event.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Phase {
+    /// Offset of the thread start, in degrees. `180` would mean thread will have exactly half
+    /// pitch offset.
+    pub degrees: u16,
+    /// Start to cut, from `0` to `starts - 1`
+    pub start: u16,
+    /// Amount of starts of the multi-start thread, evenly spaced around the revolution
+    pub starts: u16,
+    /// Additional offset, in `1 / 65536` of the revolution (like the one of the picked up thread)
+    pub shift: u16,
+}
+
+impl Phase {
+    /// Phase of the single-start thread.
+    pub const fn new(degrees: u16) -> Phase {
+        Phase {
+            degrees,
+            start: 0,
+            starts: 1,
+            shift: 0,
+        }
+    }
+
+    /// Phase of the given start of the multi-start thread.
+    pub const fn with_start(self, start: u16, starts: u16) -> Phase {
+        Phase {
+            start,
+            starts,
+            ..self
+        }
+    }
+
+    /// Phase shifted by the given part of the revolution, in `1 / 65536` of the revolution.
+    pub const fn with_shift(self, shift: u16) -> Phase {
+        Phase { shift, ..self }
+    }
+
+    /// Offset, as a part of `revolution`. Starts are indexed exactly, not rounded to degrees.
+    fn offset(self, revolution: u64) -> u64 {
+        let starts = u64::from(self.starts);
+        let turns = (u64::from(self.degrees) * starts + 360 * u64::from(self.start)) << 16;
+        let turns = turns + u64::from(self.shift) * 360 * starts;
+        revolution * turns / ((360 * starts) << 16)
+    }
+
+    /// Shift of the existing thread, which is at the given `position` when the spindle is at the
+    /// given `angle` (in `1 / 65536` of the revolution). Thread is cut with `ratio` steps per
+    /// spindle revolution, from `start` towards `target`.
+    pub fn pickup_shift(ratio: Ratio, start: i32, target: i32, position: i32, angle: u16) -> u16 {
+        // At every spindle event, thread is where stepper should be, offset by the shift. Groove
+        // passes `position` at `angle`, so it was `angle` revolutions behind at the last event.
+        let distance = i64::from(position) - i64::from(start);
+        let distance = if target < start { -distance } else { distance };
+        let turns = div_round(
+            (distance * i64::from(ratio.denominator)) << 16,
+            i64::from(ratio.numerator),
+        );
+        (turns - i64::from(angle)).rem_euclid(1 << 16) as u16
+    }
+}
+
+pub struct ThreadInfo {
+    /// Frequency of the timer we use for delay
+    timer_freq: u32,
+    /// Acceleration
+    acceleration: u32,
+    /// Remaining ticks to wait before we start cutting the thread
+    delay_remaining: u32,
+    /// How many stepper steps per spindle revolution do we need to make. Not necessarily a whole
+    /// number, so the lead matches the thread pitch exactly over any length.
+    ratio: Ratio,
+    /// Target to stop when cutting threads
+    target: i32,
+    /// At every spindle event, stepper should be that far past the whole amount of revolutions, in
+    /// `1 / ratio.denominator` of the step
+    phase_offset: u32,
+    /// Thread cutting phase error (how many steps we were off on the last spindle event), in
+    /// `1 / ratio.denominator` of the step, from `0` to `ratio.numerator`
+    last_error: u32,
+    /// Gain of the phase correction, in percent: which part of the phase error is corrected over
+    /// the next spindle revolution. `0` disables the correction.
+    phase_gain: u32,
+    /// Sum of the phase errors since the stepper got up to the speed, in `1 / ratio.denominator`
+    /// of the step
+    error_sum: i32,
+    /// Stepper got up to the speed, phase is corrected now
+    is_locked: bool,
+}
+
+impl ThreadInfo {
+    pub fn new(timer_freq: u32) -> ThreadInfo {
+        ThreadInfo {
+            timer_freq,
+            acceleration: 0,
+            delay_remaining: 0,
+            ratio: Ratio::new(0, 1),
+            target: 0,
+            phase_offset: 0,
+            last_error: 0,
+            phase_gain: 0,
+            error_sum: 0,
+            is_locked: false,
+        }
+    }
+
+    /// Configure gain of the phase correction, in percent.
+    pub fn set_phase_gain(&mut self, gain: u32) {
+        self.phase_gain = gain.min(100);
+    }
+
+    /// Configure acceleration of the stepper motor. Used to calculate an initial delay when cutting
+    /// threads.
+    pub fn set_acceleration(&mut self, acceleration: u32) {
+        self.acceleration = acceleration;
+    }
+
+    // FIXME: unwraps...
+
+    /// Calculate the time we need to wait before starting to accelerate stepper motor to make stepper
+    /// to run in sync with the spindle. This delay is calculated so our "out-of-phase error" is
+    /// minimal once stepper is fully accelerated.
+    ///
+    /// First `take_up` steps of the move take up the leadscrew backlash and do not move the
+    /// carriage. They are added to the phase, so the thread is at the same carriage positions
+    /// whichever direction the carriage moved before.
+    pub fn setup_thread_cutting(
+        &mut self,
+        target: i32,
+        ratio: Ratio,
+        phase: Phase,
+        take_up: u32,
+        estimated_rpm: u32,
+    ) -> Result<(), stepgen::Error> {
+        self.ratio = ratio;
+        self.target = target;
+        self.last_error = 0;
+        self.error_sum = 0;
+        self.is_locked = false;
+        let accelerated = self.acceleration_steps(ratio, estimated_rpm)?;
+        // Steps to accelerate is amount of steps we need to get up to the speed plus phase offset,
+        // both in `1 / ratio.denominator` of the step, so fractional steps per revolution are exact
+        let numerator = u64::from(ratio.numerator);
+        let phase_offset =
+            phase.offset(numerator) + u64::from(take_up) * u64::from(ratio.denominator);
+        self.phase_offset = (phase_offset % numerator) as u32;
+        let steps_to_accelerate =
+            u64::from(accelerated) * u64::from(ratio.denominator) + phase_offset;
+
+        let revolutions_to_accelerate = (steps_to_accelerate / numerator) + 1;
+        let start_at_step = revolutions_to_accelerate * numerator - steps_to_accelerate;
+
+        // Amount of driver timer ticks we need to wait before starting thread cutting. This is to time
+        // our acceleration such that out-of-phase error is minimal once fully accelerated. Out-of-phase
+        // error is amount of steps we are away from our projected thread. We assume thread begins at
+        // spindle event at the starting position (so, at each spindle event the amount of steps taken
+        // so far should be a multiple of the steps per revolution).
+        // 60 seconds (RPM to revolutions per second)
+        // 256 is RPM divider (RPM is in 24.8 format)
+        self.delay_remaining = (60 * 256 * u64::from(self.timer_freq) * start_at_step
+            / (numerator * u64::from(estimated_rpm))) as u32;
+
+        // We added +1 to revolutions_to_accelerate, so our delay should never be too short.
+        assert!(self.delay_remaining > 10_000, "should never wait too short");
+        Ok(())
+    }
+
+    /// Amount of steps stepper makes getting up to the thread cutting speed (stopping from it takes
+    /// the same amount of steps).
+    pub fn acceleration_steps(
+        &self,
+        ratio: Ratio,
+        estimated_rpm: u32,
+    ) -> Result<u32, stepgen::Error> {
+        let mut stepgen: stepgen::Stepgen = stepgen::Stepgen::new(self.timer_freq);
+        // RPM is in 24.8 already
+        let speed = (u64::from(estimated_rpm / 60) * u64::from(ratio.numerator)
+            / u64::from(ratio.denominator)) as u32;
+        stepgen.set_acceleration(self.acceleration)?;
+        stepgen.set_target_speed(speed)?;
+        stepgen.set_target_step(u32::MAX)?;
+        // FIXME: limit in case we have an error in algorithm?...
+        while !stepgen.is_at_speed() {
+            let _delay = stepgen.next().unwrap();
+        }
+        Ok(stepgen.current_step())
+    }
+
+    /// Calculate next delay for our waiting timer.
+    pub fn next_wait_delay(&mut self) -> u16 {
+        if self.delay_remaining < u32::from(u16::MAX) {
+            // We can wait for the whole period in one go
+            core::mem::replace(&mut self.delay_remaining, 0) as u16
+        } else {
+            // Wait for half of the period or longest delay, whichever is smaller.
+            let first_delay = (self.delay_remaining / 2).min(u32::from(u16::MAX));
+            self.delay_remaining -= first_delay;
+            first_delay as u16
+        }
+    }
+
+    /// Calculate target speed based on current RPM and error. Should be called at a spindle event.
+    /// Speed is adjusted to reduce error to 0. Error is calculated as amount of phase we are off from
+    /// desired thread location. Speed is only adjusted once stepper got up to the speed
+    /// (`is_at_speed`), as it is lagging behind the spindle while accelerating.
+    ///
+    /// At every spindle event, stepper should have made a whole amount of thread leads (plus the
+    /// phase offset), so the error is how far `steps_since_start` is from that. It never depends
+    /// on the amount of revolutions made, so the error does not accumulate along the thread.
+    pub fn calculate_speed(&mut self, rpm: u32, steps_since_start: u32, is_at_speed: bool) -> u32 {
+        let target_speed = self.ratio.to_speed(rpm);
+        let numerator = u64::from(self.ratio.numerator);
+        let steps = u64::from(steps_since_start) * u64::from(self.ratio.denominator);
+        self.last_error =
+            ((steps % numerator + numerator - u64::from(self.phase_offset)) % numerator) as u32;
+        self.is_locked |= is_at_speed;
+        if !self.is_locked || self.phase_gain == 0 {
+            return target_speed;
+        }
+
+        let error = self.signed_error();
+        let max_sum = self.ratio.numerator as i32;
+        self.error_sum = (self.error_sum + error).max(-max_sum).min(max_sum);
+
+        // Positive error means we are ahead of the spindle, so we need to slow down. Removing
+        // `error` steps over the next revolution takes `error / steps_per_revolution` of the speed.
+        let error = i64::from(error) + i64::from(self.error_sum) / INTEGRAL_DIVISOR;
+        let correction = i64::from(target_speed) * error * i64::from(self.phase_gain)
+            / (100 * i64::from(self.ratio.numerator));
+        let bound = i64::from(target_speed / MAX_CORRECTION);
+        (i64::from(target_speed) - correction.max(-bound).min(bound)) as u32
+    }
+
+    /// Last thread cutting error, in `1 / ratio.denominator` of the step, from
+    /// `-ratio.numerator / 2` to `ratio.numerator / 2`. Positive if we are ahead of the spindle.
+    fn signed_error(&self) -> i32 {
+        if self.last_error > self.ratio.numerator / 2 {
+            self.last_error as i32 - self.ratio.numerator as i32
+        } else {
+            self.last_error as i32
+        }
+    }
+
+    /// Last thread cutting error, in degrees.
+    pub fn last_error_degrees(&self) -> i32 {
+        let steps_per_revolution = u64::from(self.ratio.numerator);
+        if steps_per_revolution == 0 {
+            // No thread was cut yet
+            return 0;
+        }
+        let bias = steps_per_revolution / 2;
+        match (360 * u64::from(self.last_error) + bias) / steps_per_revolution {
+            degree if degree > 180 => (degree as i32) - 360,
+            degree => degree as i32,
+        }
+    }
+
+    pub fn target_position(&self) -> i32 {
+        self.target
+    }
+}
+
+/// Hand of the thread.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Hand {
+    Right,
+    Left,
+}
+
+impl Hand {
+    /// If spindle runs in reverse to cut the thread with the carriage moving in the given
+    /// direction. Headstock is on the left, so right-hand threads are cut moving left with the
+    /// spindle running forward, and left-hand threads are cut moving right.
+    pub fn is_spindle_reversed(self, dir: Direction) -> bool {
+        (self == Hand::Right) != (dir == Direction::Left)
+    }
+}
+
+impl core::fmt::Display for Hand {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let str = match self {
+            Hand::Right => "Right-Hand",
+            Hand::Left => "Left-Hand",
+        };
+        f.pad(str)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ThreadSize {
+    /// Threads per inch
+    Tpi(u16),
+    /// 1/100 of millimeter
+    Metric(u16),
+    /// British Association threads
+    Ba(u16),
+    /// Tapered pipe threads (NPT), threads per inch in tenths (`115` is 11.5 TPI)
+    Npt(u16),
+    /// Tapered British Standard pipe threads (BSPT), threads per inch in tenths
+    Bspt(u16),
+}
+
+impl ThreadSize {
+    /// Exact amount of (micro-)steps per spindle revolution (that is, per thread).
+    pub fn to_ratio(self, scale: Scale) -> Ratio {
+        let metric = match self {
+            ThreadSize::Tpi(tpi) => return scale.to_ratio_per(NM_PER_INCH as u64, u32::from(tpi)),
+            ThreadSize::Npt(tpi) | ThreadSize::Bspt(tpi) => {
+                return scale.to_ratio_per(10 * NM_PER_INCH as u64, u32::from(tpi))
+            }
+            ThreadSize::Metric(metric) => metric,
+            ThreadSize::Ba(ba) => british_association_mm(ba),
+        };
+        scale.to_ratio(u64::from(metric) * Units::Metric.resolution() as u64)
+    }
+
+    /// Thread pitch, in nanometers.
+    pub fn pitch(self) -> u64 {
+        let metric = match self {
+            ThreadSize::Tpi(tpi) => return NM_PER_INCH as u64 / u64::from(tpi),
+            ThreadSize::Npt(tpi) | ThreadSize::Bspt(tpi) => {
+                return 10 * NM_PER_INCH as u64 / u64::from(tpi)
+            }
+            ThreadSize::Metric(metric) => metric,
+            ThreadSize::Ba(ba) => british_association_mm(ba),
+        };
+        u64::from(metric) * Units::Metric.resolution() as u64
+    }
+
+    /// Taper of the thread, as the length over which the diameter changes by one (`16` is 1:16).
+    /// `None` for parallel threads.
+    pub fn taper(self) -> Option<u16> {
+        match self {
+            ThreadSize::Npt(_) | ThreadSize::Bspt(_) => Some(16),
+            _ => None,
+        }
+    }
+}
+
+/// Thread form, defines the depth of the thread.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ThreadForm {
+    /// 60 degree Unified and ISO metric threads
+    Iso,
+    /// 55 degree Whitworth threads
+    Whitworth,
+    /// 47.5 degree British Association threads
+    Ba,
+    /// 60 degree tapered pipe threads, with flat crests and roots
+    Npt,
+}
+
+impl ThreadForm {
+    /// Depth of the external thread for the given pitch, both in nanometers.
+    pub fn depth(self, pitch: u64) -> u64 {
+        let permille = match self {
+            ThreadForm::Iso => 613,
+            ThreadForm::Whitworth => 640,
+            ThreadForm::Ba => 600,
+            ThreadForm::Npt => 800,
+        };
+        pitch * permille / 1000
+    }
+
+    /// Angle the compound slide is set over to, in tenths of a degree: a bit less than half of
+    /// the thread angle, so the tool cuts mostly with its leading edge.
+    pub fn compound_angle(self) -> u16 {
+        match self {
+            ThreadForm::Iso | ThreadForm::Npt => 295,
+            ThreadForm::Whitworth => 270,
+            ThreadForm::Ba => 233,
+        }
+    }
+
+    /// Infeed along the compound slide for the given radial infeed, both in nanometers.
+    pub fn compound_infeed(self, radial: u64) -> u64 {
+        // Cosine of the compound angle, in millionths
+        let cos = match self {
+            ThreadForm::Iso | ThreadForm::Npt => 870_356,
+            ThreadForm::Whitworth => 891_007,
+            ThreadForm::Ba => 918_446,
+        };
+        (radial * 1_000_000 + cos / 2) / cos
+    }
+}
+
+impl core::fmt::Display for ThreadForm {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let str = match self {
+            ThreadForm::Iso => "60deg UN/ISO",
+            ThreadForm::Whitworth => "55deg Whitworth",
+            ThreadForm::Ba => "47.5deg BA",
+            ThreadForm::Npt => "60deg NPT",
+        };
+        f.pad(str)
+    }
+}
+
+/// Infeed schedule which removes the same area of the material on every pass: depth after the
+/// pass `n` of `N` cutting passes is `depth * sqrt(n / N)`. Cutting passes are followed by spring
+/// passes, without infeed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct InfeedSchedule {
+    /// Thread depth, in nanometers
+    depth: u64,
+    /// Amount of cutting passes
+    cutting: u16,
+}
+
+impl InfeedSchedule {
+    pub fn new(depth: u64) -> InfeedSchedule {
+        let mm = NM_PER_MM as u64;
+        let cutting = MIN_PASSES + (depth * PASSES_PER_MM).div_ceil(mm);
+        InfeedSchedule {
+            depth,
+            cutting: cutting as u16,
+        }
+    }
+
+    /// Amount of passes, including spring passes.
+    pub fn passes(&self) -> u16 {
+        self.cutting + SPRING_PASSES
+    }
+
+    /// Total depth after the given pass (counting from `1`), in nanometers.
+    pub fn depth_after(&self, pass: u16) -> u64 {
+        let pass = u64::from(pass.min(self.cutting));
+        isqrt(self.depth * self.depth * pass / u64::from(self.cutting))
+    }
+
+    /// Infeed for the given pass (counting from `1`), in nanometers.
+    pub fn infeed(&self, pass: u16) -> u64 {
+        self.depth_after(pass) - self.depth_after(pass.saturating_sub(1))
+    }
+}
+
+/// Integer square root, rounded down. Written out, as `u64::isqrt` takes half a kilobyte of flash.
+fn isqrt(value: u64) -> u64 {
+    // Digit by digit, two bits of the value per bit of the root
+    let (mut rem, mut root) = (value, 0);
+    let mut bit = 1u64 << 62;
+    while bit > value {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+/// Convert [British Association](https://en.wikipedia.org/wiki/British_Association_screw_threads)
+/// thread size to metric.
+fn british_association_mm(ba: u16) -> u16 {
+    let mut size = 100u16;
+    for _ in 0..ba {
+        size = (size * 9 + 5) / 10;
+    }
+    size
+}
+
+impl core::fmt::Display for ThreadSize {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            ThreadSize::Tpi(tpi) => write!(f, "{: >3} TPI", tpi),
+            ThreadSize::Metric(m100th) => write!(f, "{}.{:0>2}mm", m100th / 100, m100th % 100),
+            ThreadSize::Ba(size) => write!(f, "{}BA", size),
+            ThreadSize::Npt(tpi) => write_pipe(f, tpi, "NPT"),
+            ThreadSize::Bspt(tpi) => write_pipe(f, tpi, "BSPT"),
+        }
+    }
+}
+
+/// Pipe threads are shown in threads per inch, with tenths only if there are any.
+fn write_pipe(f: &mut core::fmt::Formatter, tpi: u16, name: &str) -> core::fmt::Result {
+    match tpi % 10 {
+        0 => write!(f, "{: >3} {}", tpi / 10, name),
+        tenths => write!(f, "{}.{} {}", tpi / 10, tenths, name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FREQUENCY: u32 = 1_000_000;
+    // 1200 steps per second per second, 16 microsteps
+    const ACCELERATION: u32 = (1200 * 16) << 8;
+
+    fn total_wait(info: &mut ThreadInfo) -> u32 {
+        let mut total = 0;
+        loop {
+            match info.next_wait_delay() {
+                0 => return total,
+                delay => total += u32::from(delay),
+            }
+        }
+    }
+
+    fn setup(phase: u16, rpm: u32) -> ThreadInfo {
+        let mut info = ThreadInfo::new(FREQUENCY);
+        info.set_acceleration(ACCELERATION);
+        info.setup_thread_cutting(1000, Ratio::new(3200, 1), Phase::new(phase), 0, rpm << 8)
+            .unwrap();
+        info
+    }
+
+    #[test]
+    fn acceleration_distance() {
+        // 10662.5 steps per second at 200 RPM, v^2 / 2a is 2960 steps
+        let info = setup(0, 200);
+        let steps = info.acceleration_steps(Ratio::new(3200, 1), 200 << 8);
+        assert!((2950..=2970).contains(&steps.unwrap()), "{:?}", steps);
+        // Twice the speed takes four times the distance
+        let steps = info.acceleration_steps(Ratio::new(3200, 1), 400 << 8);
+        assert!((11_800..=11_900).contains(&steps.unwrap()), "{:?}", steps);
+        assert_eq!(
+            Err(stepgen::Error::TooSlow),
+            info.acceleration_steps(Ratio::new(3200, 1), 0)
+        );
+    }
+
+    #[test]
+    fn wait_delay_is_split() {
+        let mut info = ThreadInfo::new(FREQUENCY);
+        info.delay_remaining = 200_000;
+        assert_eq!(65535, info.next_wait_delay());
+        assert_eq!(65535, info.next_wait_delay());
+        assert_eq!(34465, info.next_wait_delay());
+        assert_eq!(34465, info.next_wait_delay());
+        assert_eq!(0, info.next_wait_delay());
+    }
+
+    #[test]
+    fn wait_is_shorter_than_revolution() {
+        // One revolution at 200 RPM takes 300ms
+        for phase in [0, 45, 90, 180, 270, 359] {
+            let total = total_wait(&mut setup(phase, 200));
+            assert!(total > 10_000 && total <= 300_000, "phase {}", phase);
+        }
+    }
+
+    #[test]
+    fn phase_shifts_the_wait() {
+        // Half of the revolution at 200 RPM is 150ms
+        let base = total_wait(&mut setup(0, 200));
+        let shifted = total_wait(&mut setup(180, 200));
+        let diff = (base + 300_000 - shifted) % 300_000;
+        assert!((149_900..=150_100).contains(&diff), "diff {}", diff);
+        assert_eq!(1000, setup(180, 200).target_position());
+    }
+
+    #[test]
+    fn starts_shift_the_wait() {
+        // One third of the revolution at 200 RPM is 100ms
+        let mut info = setup(0, 200);
+        let base = total_wait(&mut info);
+        for start in 0..3 {
+            let phase = Phase::new(0).with_start(start, 3);
+            info.setup_thread_cutting(1000, Ratio::new(3200, 1), phase, 0, 200 << 8)
+                .unwrap();
+            let shifted = total_wait(&mut info);
+            let diff = (base + 300_000 - shifted) % 300_000;
+            let expected = 100_000 * u32::from(start);
+            assert!(
+                diff.abs_diff(expected) <= 100,
+                "start {}, diff {}",
+                start,
+                diff
+            );
+        }
+
+        // Starts are indexed exactly, not rounded to degrees
+        assert_eq!(457, Phase::new(0).with_start(1, 7).offset(3200));
+        assert_eq!(1371, Phase::new(0).with_start(3, 7).offset(3200));
+        assert_eq!(1600, Phase::new(180).offset(3200));
+        assert_eq!(2666, Phase::new(180).with_start(1, 3).offset(3200));
+    }
+
+    #[test]
+    fn pickup() {
+        let ratio = Ratio::new(3200, 1);
+        // Groove is at the start position at the spindle event
+        assert_eq!(0, Phase::pickup_shift(ratio, 1000, 0, 1000, 0));
+        assert_eq!(0, Phase::pickup_shift(ratio, 1000, 0, 1000 - 3 * 3200, 0));
+        // Quarter of the revolution away (in the cutting direction) from the start position
+        assert_eq!(0x4000, Phase::pickup_shift(ratio, 1000, 0, 1000 - 800, 0));
+        assert_eq!(0x4000, Phase::pickup_shift(ratio, 0, 1000, 800, 0));
+        // ...which it is after the quarter of the revolution since the event
+        assert_eq!(0, Phase::pickup_shift(ratio, 1000, 0, 1000 - 800, 0x4000));
+        assert_eq!(0xc000, Phase::pickup_shift(ratio, 1000, 0, 1000, 0x4000));
+
+        // Shift is applied exactly
+        let phase = Phase::new(0).with_shift(0x4000);
+        assert_eq!(800, phase.offset(3200));
+        assert_eq!(1600, phase.with_start(1, 4).offset(3200));
+        // 13 TPI: 3938.46 steps per revolution, 1000 steps past the start
+        let ratio = Ratio::new(51_200, 13);
+        let shift = Phase::pickup_shift(ratio, 0, 100_000, 1000, 0);
+        assert_eq!(16_640, shift);
+        assert_eq!(13_000, Phase::new(0).with_shift(shift).offset(51_200));
+    }
+
+    #[test]
+    fn error_is_relative_to_phase() {
+        let mut info = setup(90, 200);
+        info.calculate_speed(200 << 8, 5 * 3200 + 800, false);
+        assert_eq!(0, info.last_error_degrees());
+        info.calculate_speed(200 << 8, 5 * 3200, false);
+        assert_eq!(-90, info.last_error_degrees());
+
+        // Backlash take-up is added to the phase: carriage is at 90 degrees, not the stepper
+        let mut info = ThreadInfo::new(FREQUENCY);
+        info.set_acceleration(ACCELERATION);
+        info.setup_thread_cutting(1000, Ratio::new(3200, 1), Phase::new(90), 400, 200 << 8)
+            .unwrap();
+        info.calculate_speed(200 << 8, 5 * 3200 + 800 + 400, false);
+        assert_eq!(0, info.last_error_degrees());
+    }
+
+    #[test]
+    fn spindle_direction() {
+        assert!(!Hand::Right.is_spindle_reversed(Direction::Left));
+        assert!(Hand::Right.is_spindle_reversed(Direction::Right));
+        assert!(!Hand::Left.is_spindle_reversed(Direction::Right));
+        assert!(Hand::Left.is_spindle_reversed(Direction::Left));
+    }
+
+    #[test]
+    fn speed_and_error() {
+        let mut info = setup(0, 200);
+        // 200 RPM, 3200 steps per thread: 10666.(6) steps per second
+        assert_eq!(10666 << 8, info.calculate_speed(200 << 8, 0, false) & !0xff);
+        assert_eq!(0, info.last_error_degrees());
+
+        info.calculate_speed(200 << 8, 5 * 3200 + 800, false);
+        assert_eq!(90, info.last_error_degrees());
+
+        info.calculate_speed(200 << 8, 5 * 3200 + 2400, false);
+        assert_eq!(-90, info.last_error_degrees());
+    }
+
+    #[test]
+    fn no_correction_while_accelerating() {
+        let mut info = setup(0, 200);
+        info.set_phase_gain(50);
+        let speed = info.calculate_speed(200 << 8, 0, false);
+        assert_eq!(speed, info.calculate_speed(200 << 8, 100, false));
+        assert_ne!(speed, info.calculate_speed(200 << 8, 3200 + 100, true));
+        // Once locked, stays locked
+        assert_ne!(speed, info.calculate_speed(200 << 8, 2 * 3200 + 100, false));
+    }
+
+    #[test]
+    fn correction_disabled() {
+        let mut info = setup(0, 200);
+        let speed = info.calculate_speed(200 << 8, 0, true);
+        assert_eq!(speed, info.calculate_speed(200 << 8, 3200 + 100, true));
+    }
+
+    #[test]
+    fn correction_direction() {
+        let mut info = setup(0, 200);
+        info.set_phase_gain(50);
+        let speed = info.calculate_speed(200 << 8, 0, true);
+
+        // Ahead of the spindle: slow down
+        let mut ahead = setup(0, 200);
+        ahead.set_phase_gain(50);
+        assert!(ahead.calculate_speed(200 << 8, 3200 + 16, true) < speed);
+
+        // Behind the spindle: speed up
+        let mut behind = setup(0, 200);
+        behind.set_phase_gain(50);
+        assert!(behind.calculate_speed(200 << 8, 2 * 3200 - 16, true) > speed);
+    }
+
+    #[test]
+    fn correction_is_bounded() {
+        let mut info = setup(0, 200);
+        info.set_phase_gain(100);
+        let speed = info.calculate_speed(200 << 8, 0, true);
+        let mut info = setup(0, 200);
+        info.set_phase_gain(100);
+        let slowest = info.calculate_speed(200 << 8, 3200 + 1600, true);
+        assert_eq!(speed - speed / 8, slowest);
+
+        let mut info = setup(0, 200);
+        info.set_phase_gain(100);
+        let fastest = info.calculate_speed(200 << 8, 3200 + 1601, true);
+        assert_eq!(speed + speed / 8, fastest);
+    }
+
+    #[test]
+    fn fractional_error() {
+        // 13 TPI on 16 TPI leadscrew: 3938.46 steps per revolution
+        let mut info = ThreadInfo::new(FREQUENCY);
+        info.set_acceleration(ACCELERATION);
+        info.setup_thread_cutting(1000, Ratio::new(51_200, 13), Phase::new(0), 0, 200 << 8)
+            .unwrap();
+        // After 13 revolutions, stepper made exactly 51200 steps
+        info.calculate_speed(200 << 8, 51_200, false);
+        assert_eq!(0, info.last_error_degrees());
+        // After 1300 revolutions, error is still zero
+        info.calculate_speed(200 << 8, 5_120_000, false);
+        assert_eq!(0, info.last_error_degrees());
+        // Rounded steps per revolution are off by 0.46 steps every revolution
+        info.calculate_speed(200 << 8, 1300 * 3938, false);
+        assert_eq!(-55, info.last_error_degrees());
+    }
+
+    #[test]
+    fn thread_ratio() {
+        let scale = Scale::inch(16 * 200, 16);
+        assert_eq!(Ratio::new(3200, 1), ThreadSize::Tpi(16).to_ratio(scale));
+        assert_eq!(Ratio::new(51_200, 13), ThreadSize::Tpi(13).to_ratio(scale));
+        // 1 mm is 2015.7 steps
+        assert_eq!(
+            Ratio::new(256_000, 127),
+            ThreadSize::Metric(100).to_ratio(scale)
+        );
+        assert_eq!(
+            Ratio::new(179_200, 127),
+            ThreadSize::Metric(70).to_ratio(scale)
+        );
+        assert_eq!(Ratio::new(168_960, 127), ThreadSize::Ba(4).to_ratio(scale));
+        // 11.5 TPI: 4452.17 steps per revolution
+        assert_eq!(
+            Ratio::new(102_400, 23),
+            ThreadSize::Npt(115).to_ratio(scale)
+        );
+        assert_eq!(Ratio::new(25_600, 7), ThreadSize::Bspt(140).to_ratio(scale));
+
+        // 2 mm metric leadscrew, 16 microsteps
+        let scale = Scale::metric(16 * 200, 200);
+        assert_eq!(Ratio::new(1600, 1), ThreadSize::Metric(100).to_ratio(scale));
+        assert_eq!(Ratio::new(2540, 1), ThreadSize::Tpi(16).to_ratio(scale));
+    }
+
+    #[test]
+    fn square_root() {
+        for value in (0..10_000).chain([u64::MAX - 1, u64::MAX, 613_000 * 613_000 * 5 / 6]) {
+            assert_eq!(value.isqrt(), isqrt(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn depth_and_schedule() {
+        // 1 mm pitch metric: 0.613 mm deep, 6 cutting and 2 spring passes
+        let depth = ThreadForm::Iso.depth(ThreadSize::Metric(100).pitch());
+        assert_eq!(613_000, depth);
+        let schedule = InfeedSchedule::new(depth);
+        assert_eq!(8, schedule.passes());
+        // First pass is the deepest
+        assert_eq!(250_256, schedule.infeed(1));
+        assert_eq!(103_659, schedule.infeed(2));
+        assert_eq!(53_411, schedule.infeed(6));
+        assert_eq!(depth, schedule.depth_after(6));
+        // Spring passes
+        assert_eq!(0, schedule.infeed(7));
+        assert_eq!(0, schedule.infeed(8));
+        assert_eq!(depth, schedule.depth_after(8));
+
+        // Each pass removes (about) the same area, which is proportional to the depth squared
+        let area = |pass| schedule.depth_after(pass).pow(2) - schedule.depth_after(pass - 1).pow(2);
+        for pass in 2..=6 {
+            assert!(
+                area(pass).abs_diff(area(1)) < area(1) / 1000,
+                "pass {}",
+                pass
+            );
+        }
+
+        // 16 TPI Whitworth: 0.0400 inch deep, 8 cutting and 2 spring passes
+        let depth = ThreadForm::Whitworth.depth(ThreadSize::Tpi(16).pitch());
+        assert_eq!(1_016_000, depth);
+        assert_eq!(10, InfeedSchedule::new(depth).passes());
+
+        assert_eq!(396_000, ThreadForm::Ba.depth(ThreadSize::Ba(4).pitch()));
+
+        // Compound is set over to 29.5 degrees for 60 degree threads, so it is fed further
+        assert_eq!(295, ThreadForm::Iso.compound_angle());
+        assert_eq!(287_533, ThreadForm::Iso.compound_infeed(250_256));
+        assert_eq!(1_000_000, ThreadForm::Whitworth.compound_infeed(891_007));
+        assert_eq!(0, ThreadForm::Ba.compound_infeed(0));
+
+        // 11.5 TPI NPT: 0.0696 inch deep, tapered 1:16
+        let thread = ThreadSize::Npt(115);
+        assert_eq!(2_208_695, thread.pitch());
+        assert_eq!(1_766_956, ThreadForm::Npt.depth(thread.pitch()));
+        assert_eq!(Some(16), thread.taper());
+        assert_eq!(Some(16), ThreadSize::Bspt(190).taper());
+        assert_eq!(None, ThreadSize::Tpi(16).taper());
+    }
+
+    #[test]
+    fn british_association() {
+        assert_eq!(100, british_association_mm(0));
+        assert_eq!(90, british_association_mm(1));
+        assert_eq!(66, british_association_mm(4));
+        assert_eq!(35, british_association_mm(10));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(" 16 TPI", ThreadSize::Tpi(16).to_string());
+        assert_eq!("1.25mm", ThreadSize::Metric(125).to_string());
+        assert_eq!("0.70mm", ThreadSize::Metric(70).to_string());
+        assert_eq!("6BA", ThreadSize::Ba(6).to_string());
+        assert_eq!(" 18 NPT", ThreadSize::Npt(180).to_string());
+        assert_eq!("11.5 NPT", ThreadSize::Npt(115).to_string());
+        assert_eq!(" 19 BSPT", ThreadSize::Bspt(190).to_string());
+    }
+}
@@ -0,0 +1,208 @@
+//! Units of length and exact conversions between lengths and stepper (micro-)steps.
+//!
+//! Lengths are converted through nanometers: every length we deal with (thou, hundredth of a
+//! millimeter, leadscrew pitch or G-code fixed point number) is a whole amount of nanometers, so
+//! the only rounding happens once, when converting to steps.
+
+use crate::gearing::Ratio;
+
+/// Nanometers per inch
+pub const NM_PER_INCH: i64 = 25_400_000;
+
+/// Nanometers per millimeter
+pub const NM_PER_MM: i64 = 1_000_000;
+
+/// Units positions and feed rates are displayed in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Units {
+    Inch,
+    Metric,
+}
+
+impl Units {
+    /// Smallest displayed length, in nanometers: one thou or a hundredth of a millimeter.
+    pub fn resolution(self) -> i64 {
+        match self {
+            Units::Inch => NM_PER_INCH / 1000,
+            Units::Metric => NM_PER_MM / 100,
+        }
+    }
+}
+
+/// Amount of (micro-)steps per length, kept as an exact ratio so metric leadscrews (or metric
+/// units on an inch leadscrew, or belt reductions) do not lose precision.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Scale {
+    /// (Micro-)steps per `nanometers`
+    steps: u64,
+    nanometers: u64,
+}
+
+impl Scale {
+    pub fn new(steps: u64, nanometers: u64) -> Scale {
+        let gcd = gcd(steps, nanometers).max(1);
+        Scale {
+            steps: steps / gcd,
+            nanometers: nanometers / gcd,
+        }
+    }
+
+    /// Scale for the leadscrew with the given amount of threads per inch.
+    pub fn inch(steps_per_rev: u32, tpi: u32) -> Scale {
+        Scale::new(u64::from(steps_per_rev * tpi), NM_PER_INCH as u64)
+    }
+
+    /// Scale for the leadscrew with the given pitch, in hundredths of a millimeter.
+    pub fn metric(steps_per_rev: u32, pitch: u32) -> Scale {
+        Scale::new(
+            u64::from(steps_per_rev),
+            u64::from(pitch) * (NM_PER_MM / 100) as u64,
+        )
+    }
+
+    /// Scale for the leadscrew driven through the reduction (belt or gears): motor pulley with
+    /// `motor_teeth` drives the leadscrew pulley with `screw_teeth`.
+    pub fn with_reduction(self, motor_teeth: u32, screw_teeth: u32) -> Scale {
+        Scale::new(
+            self.steps * u64::from(screw_teeth),
+            self.nanometers * u64::from(motor_teeth),
+        )
+    }
+
+    /// Convert length, in nanometers, into (micro-)steps, rounding to the nearest step.
+    pub fn to_steps(self, nanometers: i64) -> i64 {
+        div_round(nanometers * self.steps as i64, self.nanometers as i64)
+    }
+
+    /// Convert (micro-)steps into length, in nanometers, rounding to the nearest nanometer.
+    pub fn to_nanometers(self, steps: i64) -> i64 {
+        div_round(steps * self.nanometers as i64, self.steps as i64)
+    }
+
+    /// Convert speed, in nanometers per minute, into the stepper speed, in (micro-)steps per
+    /// second, 24.8 format.
+    pub fn to_speed(self, nanometers_per_minute: u64) -> u32 {
+        let speed = (nanometers_per_minute * self.steps) << 8;
+        (speed / (60 * self.nanometers)).min(u64::from(u32::MAX)) as u32
+    }
+
+    /// Amount of (micro-)steps per spindle revolution for the given feed per revolution, in
+    /// nanometers.
+    pub fn to_ratio(self, nanometers_per_rev: u64) -> Ratio {
+        self.to_ratio_per(nanometers_per_rev, 1)
+    }
+
+    /// Amount of (micro-)steps per spindle revolution for the feed of `nanometers` per the given
+    /// amount of spindle revolutions (like one inch per `tpi` revolutions for inch threads).
+    pub fn to_ratio_per(self, nanometers: u64, revolutions: u32) -> Ratio {
+        self.checked_ratio(nanometers, revolutions)
+            .expect("ratio overflow")
+    }
+
+    /// Same as `to_ratio_per`, but returns `None` if the ratio does not fit.
+    pub fn checked_ratio(self, nanometers: u64, revolutions: u32) -> Option<Ratio> {
+        let numerator = nanometers.checked_mul(self.steps)?;
+        reduced_ratio(numerator, self.nanometers * u64::from(revolutions))
+    }
+
+    /// Amount of (micro-)steps of this axis per (micro-)step of the `leading` axis, for this axis
+    /// to move `1 / reduction` of the distance the leading axis moves (like the cross-slide
+    /// cutting the taper).
+    pub fn follow_ratio(self, leading: Scale, reduction: u32) -> Ratio {
+        let numerator = self.steps * leading.nanometers;
+        let denominator = self.nanometers * leading.steps * u64::from(reduction);
+        reduced_ratio(numerator, denominator).expect("ratio overflow")
+    }
+}
+
+/// Ratio reduced to the lowest terms, `None` if it does not fit.
+fn reduced_ratio(numerator: u64, denominator: u64) -> Option<Ratio> {
+    let gcd = gcd(numerator, denominator).max(1);
+    Some(Ratio::new(
+        u32::try_from(numerator / gcd).ok()?,
+        u32::try_from(denominator / gcd).ok()?,
+    ))
+}
+
+/// Divide, rounding to the nearest integer. `divisor` must be positive.
+pub fn div_round(value: i64, divisor: i64) -> i64 {
+    (value + value.signum() * (divisor / 2)) / divisor
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inch_leadscrew() {
+        // 16 TPI leadscrew, 16 microsteps, 200 steps per rotation
+        let scale = Scale::inch(16 * 200, 16);
+        assert_eq!(51_200, scale.to_steps(NM_PER_INCH));
+        assert_eq!(-512, scale.to_steps(-NM_PER_INCH / 100));
+        // 1 mm is 2015.7 steps
+        assert_eq!(2_016, scale.to_steps(NM_PER_MM));
+        assert_eq!(NM_PER_INCH, scale.to_nanometers(51_200));
+        // 254 mm is exactly 10 inches
+        assert_eq!(512_000, scale.to_steps(254 * NM_PER_MM));
+    }
+
+    #[test]
+    fn metric_leadscrew() {
+        // 2 mm pitch, 8 microsteps, 200 steps per rotation
+        let scale = Scale::metric(8 * 200, 200);
+        assert_eq!(800, scale.to_steps(NM_PER_MM));
+        assert_eq!(-80, scale.to_steps(-Units::Metric.resolution() * 10));
+        // 1 inch is 20320 steps, exactly
+        assert_eq!(20_320, scale.to_steps(NM_PER_INCH));
+        assert_eq!(NM_PER_MM / 800, scale.to_nanometers(1));
+        // 60 mm per minute is 1 mm per second
+        assert_eq!(800 << 8, scale.to_speed(60 * NM_PER_MM as u64));
+    }
+
+    #[test]
+    fn reduction() {
+        // 0.9 degree motor, 8 microsteps, 20:36 belt reduction to 2 mm leadscrew: 5760 steps
+        // per leadscrew revolution
+        let scale = Scale::metric(8 * 400, 200).with_reduction(20, 36);
+        assert_eq!(2_880, scale.to_steps(NM_PER_MM));
+        assert_eq!(Scale::metric(5_760, 200), scale);
+        // 1 inch is 73152 steps, exactly, and 1 step is 347.2 nanometers
+        assert_eq!(73_152, scale.to_steps(NM_PER_INCH));
+        assert_eq!(347, scale.to_nanometers(1));
+        // Long distances do not accumulate the error
+        assert_eq!(1_000 * NM_PER_MM, scale.to_nanometers(2_880_000));
+
+        // 20:30 reduction on 16 TPI: 1 mm is 3023.6 steps
+        let scale = Scale::inch(16 * 200, 16).with_reduction(20, 30);
+        assert_eq!(3_024, scale.to_steps(NM_PER_MM));
+        assert_eq!(76_800, scale.to_steps(NM_PER_INCH));
+    }
+
+    #[test]
+    fn ratio() {
+        let scale = Scale::inch(16 * 200, 16);
+        // 0.004 IPR is 204.8 steps per revolution
+        assert_eq!(Ratio::new(1024, 5), scale.to_ratio(4 * 25_400));
+        // 0.1 mm per revolution on 2 mm leadscrew is 1/20 of its revolution
+        let scale = Scale::metric(8 * 200, 200);
+        assert_eq!(Ratio::new(80, 1), scale.to_ratio(100_000));
+    }
+
+    #[test]
+    fn follow_ratio() {
+        // Cross-slide with 0.9 degree motor cutting the 1:16 taper (1:32 on the radius)
+        let carriage = Scale::inch(16 * 200, 16);
+        let cross = Scale::inch(16 * 400, 16);
+        assert_eq!(Ratio::new(1, 16), cross.follow_ratio(carriage, 32));
+        // 2 mm metric cross-slide screw: 800 steps per millimeter versus 51200 per inch
+        let cross = Scale::metric(8 * 200, 200);
+        assert_eq!(Ratio::new(127, 10_240), cross.follow_ratio(carriage, 32));
+    }
+}
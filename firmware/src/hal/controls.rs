@@ -1,32 +1,8 @@
 use stm32f1xx_hal::gpio::{ErasedPin, Floating, Input};
+use x2_feed_core::hal::{ControlsState, Event, BUTTONS};
 
 type Pin = ErasedPin<Input<Floating>>;
 
-#[derive(Clone, Copy, Debug)]
-pub struct ControlsState {
-    pub left: bool,
-    pub right: bool,
-    pub fast: bool,
-    pub button: bool,
-}
-
-#[derive(Clone, Copy, Debug)]
-pub enum Button {
-    Left,
-    Right,
-    Fast,
-    Encoder,
-}
-
-const BUTTONS: [Button; 4] = [Button::Left, Button::Right, Button::Fast, Button::Encoder];
-
-#[derive(Clone, Copy, Debug)]
-pub enum Event {
-    Pressed(Button),
-    Unpressed(Button),
-    None,
-}
-
 pub struct Controls {
     pins: [Pin; 4],
     last: [bool; 4],
@@ -39,8 +15,10 @@ impl Controls {
             last: [false; 4],
         }
     }
+}
 
-    pub fn state(&self) -> ControlsState {
+impl x2_feed_core::hal::Controls for Controls {
+    fn state(&self) -> ControlsState {
         let mut pressed: [bool; 4] = [false; 4];
         for (idx, pin) in self.pins.iter().enumerate() {
             pressed[idx] = pin.is_high();
@@ -58,7 +36,7 @@ impl Controls {
     /// state was changed.
     /// # Note
     /// Only handles one pin at a time.
-    pub fn read_event(&mut self) -> Event {
+    fn read_event(&mut self) -> Event {
         for (idx, pin) in self.pins.iter().enumerate() {
             let state = pin.is_high();
             if state && !self.last[idx] {
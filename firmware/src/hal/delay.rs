@@ -50,3 +50,13 @@ impl Duration {
         self.duration
     }
 }
+
+impl x2_feed_core::hal::Stopwatch for Duration {
+    fn start() -> Self {
+        Duration::new()
+    }
+
+    fn elapsed_us(&mut self) -> u32 {
+        self.duration()
+    }
+}
@@ -0,0 +1,235 @@
+use stm32f1::stm32f103::{TIM1, TIM4};
+use stm32f1xx_hal::gpio::{Alternate, ErasedPin, OpenDrain, Output, Pin as PinT, CRH};
+use x2_feed_core::StepperDriver;
+
+type Pin = ErasedPin<Output<OpenDrain>>;
+type StepPin = PinT<Alternate<OpenDrain>, CRH, 'A', 8>;
+type CrossStepPin = PinT<Alternate<OpenDrain>, CRH, 'B', 8>;
+
+pub const DRIVER_TICK_FREQUENCY: u32 = 1_000_000; // 1us timer resolution
+
+const fn ns2ticks(ns: u32) -> u16 {
+    const NANOS_IN_SECOND: u32 = 1_000_000_000 / DRIVER_TICK_FREQUENCY;
+    ns.div_ceil(NANOS_IN_SECOND) as u16
+}
+
+/// Width of the step pulse we send.
+const STEP_PULSE_WIDTH_TICKS: u16 = ns2ticks(75);
+
+/// Timer generating the step pulses. Both axes use the same driver type, so the stepper code is
+/// only compiled once.
+enum Timer {
+    /// Main axis, pulses on channel 1 (PA8)
+    Tim1(TIM1),
+    /// Cross-slide axis, pulses on channel 3 (PB8)
+    Tim4(TIM4),
+}
+
+/// Run the code with either of the timers, for the registers which are the same on both.
+macro_rules! with_timer {
+    ($timer:expr, $tim:ident => $body:expr) => {
+        match $timer {
+            Timer::Tim1($tim) => $body,
+            Timer::Tim4($tim) => $body,
+        }
+    };
+}
+
+pub struct StepperDriverImpl {
+    timer: Timer,
+    dir: Pin,
+    enable: Pin,
+    /// Cross-slide driver has its RESET wired to the one of the main driver
+    reset: Option<Pin>,
+}
+
+impl StepperDriverImpl {
+    pub fn new(tim1: TIM1, _step: StepPin, dir: Pin, enable: Pin, reset: Pin) -> StepperDriverImpl {
+        let mut driver = StepperDriverImpl {
+            timer: Timer::Tim1(tim1),
+            dir,
+            enable,
+            reset: Some(reset),
+        };
+        driver.init();
+        driver
+    }
+
+    /// Driver of the cross-slide axis. Should be created after the main one, which takes both
+    /// drivers out of reset.
+    pub fn cross(tim4: TIM4, _step: CrossStepPin, dir: Pin, enable: Pin) -> StepperDriverImpl {
+        let mut driver = StepperDriverImpl {
+            timer: Timer::Tim4(tim4),
+            dir,
+            enable,
+            reset: None,
+        };
+        driver.init();
+        driver
+    }
+
+    // Note that we require an explicit ownership of I/O port peripheral to guard against
+    // concurrent access when we modify shared register of the peripheral (CRH)
+    fn init(&mut self) {
+        self.dir.set_high();
+        self.enable.set_low();
+        // Start in reset mode
+        if let Some(ref mut reset) = self.reset {
+            reset.set_low();
+        }
+
+        with_timer!(&self.timer, tim => {
+            // Prescaler
+            tim.psc.write(|w| {
+                w.psc()
+                    .bits(((crate::hal::FREQUENCY / DRIVER_TICK_FREQUENCY) - 1) as u16)
+            });
+
+            // Initialize timer
+            tim.cr1.write(|w| {
+                w.dir()
+                    .up()
+                    .ckd()
+                    .div1()
+                    // Preload ARR (gets loaded once timer update event triggers)
+                    .arpe()
+                    .set_bit()
+                    // Only counter overflow/underflow generates an update interrupt
+                    // reloading timer in start() should not generate an event.
+                    .urs()
+                    .set_bit()
+            });
+        });
+
+        match &self.timer {
+            Timer::Tim1(tim1) => {
+                tim1.cr2.write(|w| {
+                    w
+                        // Output '1' when idle
+                        .ois1()
+                        .set_bit()
+                });
+
+                tim1.ccmr1_output().write(|w| {
+                    w
+                        // Preload CCR1 (gets loaded once timer update event triggers)
+                        .oc1pe()
+                        .set_bit()
+                        // Inactive till CCR1, then active
+                        .oc1m()
+                        .pwm_mode2()
+                });
+
+                // Configure PWM channel 1
+                tim1.ccer.write(|w| {
+                    w
+                        // Active low
+                        .cc1p()
+                        .set_bit()
+                        // Enable channel 1
+                        .cc1e()
+                        .set_bit()
+                });
+
+                tim1.bdtr.write(|w| {
+                    w
+                        // Enable PWM outputs
+                        .moe()
+                        .set_bit()
+                });
+            }
+            Timer::Tim4(tim4) => {
+                // Same as channel 1 of TIM1. Output is '1' when idle as the counter stops below
+                // CCR3, general purpose timer has no outputs to enable.
+                tim4.ccmr2_output()
+                    .write(|w| w.oc3pe().set_bit().oc3m().pwm_mode2());
+                tim4.ccer.write(|w| w.cc3p().set_bit().cc3e().set_bit());
+            }
+        }
+
+        // Enable interrupts
+        with_timer!(&self.timer, tim => {
+            tim.sr.modify(|_, w| w.uif().clear());
+            tim.dier.write(|w| w.uie().set_bit());
+        });
+
+        // Enable the driver
+        if let Some(ref mut reset) = self.reset {
+            reset.set_high();
+        }
+    }
+}
+
+impl StepperDriver for StepperDriverImpl {
+    // Controls
+    fn set_enable(&mut self, enable: bool) {
+        if enable {
+            self.enable.set_high();
+        } else {
+            self.enable.set_low();
+        }
+    }
+
+    // Low-level timer output control: enable/disable PWM channel
+    fn set_timer_output(&mut self, enable: bool) {
+        match &self.timer {
+            Timer::Tim1(tim1) => tim1.ccer.write(|w| w.cc1e().bit(enable)),
+            Timer::Tim4(tim4) => tim4.ccer.write(|w| w.cc3p().set_bit().cc3e().bit(enable)),
+        }
+    }
+
+    fn set_direction(&mut self, dir: bool) {
+        if dir {
+            self.dir.set_high();
+        } else {
+            self.dir.set_low();
+        }
+    }
+
+    // Pulse generation
+
+    fn start(&mut self, first_delay: u16) {
+        self.preload_delay(first_delay);
+
+        // FIXME: does this cause second preload?...
+        // Generate event to reload timer values from the preload registers.
+        with_timer!(&self.timer, tim => {
+            tim.egr.write(|w| w.ug().set_bit());
+            tim.cr1.modify(|_, w| w.opm().disabled().cen().enabled());
+        });
+    }
+
+    fn preload_delay(&mut self, delay: u16) {
+        // FIXME: delay could be 0?
+        with_timer!(&self.timer, tim => tim.arr.write(|w| w.arr().bits(delay - 1)));
+
+        // FIXME: reject too short delays?
+        let compare = delay.saturating_sub(STEP_PULSE_WIDTH_TICKS);
+        match &self.timer {
+            Timer::Tim1(tim1) => tim1.ccr1.write(|w| w.ccr().bits(compare)),
+            Timer::Tim4(tim4) => tim4.ccr3.write(|w| w.ccr().bits(compare)),
+        }
+    }
+
+    fn set_last(&mut self) {
+        // Switch to one-pulse mode (current pulse is the last one)
+        // FIXME: verify: was opm().one_pulse()
+        with_timer!(&self.timer, tim => tim.cr1.modify(|_, w| w.opm().enabled()));
+    }
+
+    fn is_running(&self) -> bool {
+        // Check if timer is still running
+        with_timer!(&self.timer, tim => tim.cr1.read().cen().bit_is_set())
+    }
+
+    fn interrupt(&mut self) -> bool {
+        with_timer!(&self.timer, tim => {
+            if tim.sr.read().uif().is_update_pending() {
+                tim.sr.modify(|_, w| w.uif().clear());
+                true
+            } else {
+                false
+            }
+        })
+    }
+}
@@ -0,0 +1,61 @@
+use stm32f1::stm32f103::TIM3;
+use stm32f1xx_hal::gpio::{Floating, Input, Pin, CRL};
+use x2_feed_core::hal;
+
+type DtPin = Pin<Input<Floating>, CRL, 'A', 6>;
+type ClkPin = Pin<Input<Floating>, CRL, 'A', 7>;
+
+pub struct QuadEncoder {
+    tim3: TIM3,
+}
+
+impl QuadEncoder {
+    pub fn new(tim3: TIM3, _dt: DtPin, _clk: ClkPin) -> QuadEncoder {
+        let encoder = QuadEncoder { tim3 };
+        encoder.init();
+        encoder
+    }
+
+    // Note that we require an explicit ownership of I/O port peripheral to guard against
+    // concurrent access when we modify shared register of the peripheral (CRL)
+    fn init(&self) {
+        //Timer::tim3().qei()
+        // Configure timer
+        // Configure timer as rotary encoder
+        // FIXME: was sms().encoder_ti2()
+        self.tim3.smcr.write(|w| w.sms().encoder_mode_3());
+
+        // Count on rising edges
+        self.tim3
+            .ccer
+            .write(|w| w.cc1p().clear_bit().cc2p().clear_bit());
+
+        // FIXME: verify unsafe, maybe can do safe?
+        self.tim3.ccmr1_output().write(|w| unsafe {
+            w.bits(
+                (0b1111 << 4/* Input capture 1 filter */)
+                    | (0b1111 << 12/* Input capture 2 filter */),
+            )
+        });
+
+        self.tim3.cr1.write(|w| w.cen().enabled());
+    }
+}
+
+impl hal::QuadEncoder for QuadEncoder {
+    fn get_limit(&self) -> u16 {
+        self.tim3.arr.read().arr().bits().div_ceil(2)
+    }
+
+    fn set_limit_unsafe(&mut self, limit: u16) {
+        self.tim3.arr.write(|w| w.arr().bits((limit * 4) - 1));
+    }
+
+    fn current(&self) -> u16 {
+        self.tim3.cnt.read().cnt().bits() / 4
+    }
+
+    fn set_current(&mut self, pos: u16) {
+        self.tim3.cnt.write(|w| w.cnt().bits(pos * 4 + 1));
+    }
+}
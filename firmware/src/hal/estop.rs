@@ -8,9 +8,10 @@ impl EStop {
     pub fn new(estop: ErasedPin<Input<PullUp>>) -> EStop {
         EStop { estop }
     }
+}
 
-    /// Check if we are in the emergency stop condition.
-    pub fn is_emergency_stop(&self) -> bool {
+impl x2_feed_core::hal::EStop for EStop {
+    fn is_emergency_stop(&self) -> bool {
         self.estop.is_low()
     }
 }
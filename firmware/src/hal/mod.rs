@@ -6,17 +6,25 @@ mod estop;
 mod led;
 mod rpm;
 mod screen;
+mod spindle;
+mod spindle_encoder;
+mod storage;
+mod switch;
 
 pub const FREQUENCY: u32 = 72_000_000;
 
-pub use self::controls::{Button, Controls, ControlsState, Event};
+pub use self::controls::Controls;
+pub use self::driver::StepperDriverImpl;
 pub use self::driver::DRIVER_TICK_FREQUENCY;
-pub use self::driver::{StepperDriver, StepperDriverImpl};
 pub use self::encoder::QuadEncoder;
 pub use self::estop::EStop;
 pub use self::led::Led;
 pub use self::rpm::RpmSensor;
 pub use self::screen::Screen;
+pub use self::spindle::Spindle;
+pub use self::spindle_encoder::EncoderSensor;
+pub use self::storage::Storage;
+pub use self::switch::{HomeSwitch, LimitSwitch};
 use eeprom::Params;
 use stm32f1xx_hal::flash::{FlashSize, SectorSize};
 
@@ -1,5 +1,6 @@
 use stm32f1::stm32f103::TIM2;
 use stm32f1xx_hal::gpio::{Input, Pin, PullUp, CRL};
+use x2_feed_core::hal::SpindleSensor;
 
 const HALL_TICK_FREQUENCY: u32 = 100_000; // 0.01 ms
 const HALL_MAX_RPM: u32 = 6000;
@@ -86,10 +87,16 @@ impl RpmSensor {
                 .enabled()
         });
     }
+}
+
+impl SpindleSensor for RpmSensor {
+    fn rpm(&self) -> u32 {
+        ((60 * HALL_TICK_FREQUENCY) << 8)
+            .checked_div(self.captured)
+            .unwrap_or(0)
+    }
 
-    /// Check for pending interrupt and handle it (reset pending flag). Returns `true` if captured
-    /// signal from hall sensor.
-    pub fn interrupt(&mut self) -> bool {
+    fn interrupt(&mut self) -> bool {
         if self.tim2.sr.read().cc1if().bit_is_set() {
             // FIXME: check if we can get away with write...
             self.tim2.sr.modify(|_, w| w.cc1if().clear_bit());
@@ -134,13 +141,4 @@ impl RpmSensor {
             false
         }
     }
-
-    /// Get latest captured RPM, in 24.8 format
-    pub fn rpm(&self) -> u32 {
-        if self.captured != 0 {
-            ((60 * HALL_TICK_FREQUENCY) << 8) / self.captured
-        } else {
-            0
-        }
-    }
 }
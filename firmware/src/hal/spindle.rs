@@ -0,0 +1,31 @@
+use crate::hal::{EncoderSensor, RpmSensor};
+use x2_feed_core::hal::SpindleSensor;
+
+/// Spindle sensor selected in the settings.
+pub enum Spindle {
+    Hall(RpmSensor),
+    Encoder(EncoderSensor),
+}
+
+impl SpindleSensor for Spindle {
+    fn rpm(&self) -> u32 {
+        match self {
+            Spindle::Hall(sensor) => sensor.rpm(),
+            Spindle::Encoder(sensor) => sensor.rpm(),
+        }
+    }
+
+    fn angle(&self) -> u16 {
+        match self {
+            Spindle::Hall(sensor) => sensor.angle(),
+            Spindle::Encoder(sensor) => sensor.angle(),
+        }
+    }
+
+    fn interrupt(&mut self) -> bool {
+        match self {
+            Spindle::Hall(sensor) => sensor.interrupt(),
+            Spindle::Encoder(sensor) => sensor.interrupt(),
+        }
+    }
+}
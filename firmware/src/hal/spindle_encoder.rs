@@ -0,0 +1,136 @@
+use cortex_m::peripheral::DWT;
+use stm32f1::stm32f103::{EXTI, TIM2};
+use stm32f1xx_hal::afio;
+use stm32f1xx_hal::gpio::{Edge, ExtiPin, Floating, Input, Pin, PullUp, CRH, CRL};
+use x2_feed_core::hal::SpindleSensor;
+use x2_feed_core::spindle::EncoderSpindle;
+
+type APin = Pin<Input<Floating>, CRH, 'A', 15>;
+type BPin = Pin<Input<Floating>, CRL, 'B', 3>;
+type IndexPin = Pin<Input<PullUp>, CRL, 'A', 0>;
+
+/// Quadrature encoder on the spindle. Channels A and B are connected to the TIM2 (partial remap,
+/// PA15 and PB3), optional index pulse is connected to PA0 (same pin as the hall sensor).
+///
+/// Timestamps are taken from the DWT cycle counter, which needs to be enabled.
+pub struct EncoderSensor {
+    tim2: TIM2,
+    index: IndexPin,
+    spindle: EncoderSpindle,
+    /// Counter was reset on the index pulse
+    is_aligned: bool,
+}
+
+impl EncoderSensor {
+    pub fn new(
+        tim2: TIM2,
+        _a: APin,
+        _b: BPin,
+        mut index: IndexPin,
+        cpr: u16,
+        afio: &mut afio::Parts,
+        exti: &EXTI,
+    ) -> EncoderSensor {
+        // Partial remap: CH1 on PA15, CH2 on PB3
+        afio.mapr
+            .modify_mapr(|_, w| unsafe { w.tim2_remap().bits(0b01) });
+
+        index.make_interrupt_source(afio);
+        index.trigger_on_edge(exti, Edge::Falling);
+        index.enable_interrupt(exti);
+
+        let sensor = EncoderSensor {
+            tim2,
+            index,
+            spindle: EncoderSpindle::new(cpr, crate::hal::FREQUENCY),
+            is_aligned: false,
+        };
+        sensor.init();
+        sensor
+    }
+
+    fn init(&self) {
+        // Counter wraps around once per revolution
+        self.tim2
+            .arr
+            .write(|w| w.arr().bits(self.spindle.counts() - 1));
+        self.tim2.smcr.write(|w| w.sms().encoder_mode_3());
+
+        // Count on rising edges
+        self.tim2
+            .ccer
+            .write(|w| w.cc1p().clear_bit().cc2p().clear_bit());
+
+        // FIXME: verify unsafe, maybe can do safe?
+        self.tim2.ccmr1_output().write(|w| unsafe {
+            w.bits(
+                // CC1 channel is configured as input, IC1 is mapped on TI1
+                0b01
+                    // Filter fSAMPLING=fCK_INT,N=8
+                    | (0b0011 << 4)
+                    // CC2 channel is configured as input, IC2 is mapped on TI2
+                    | (0b01 << 8)
+                    // Filter fSAMPLING=fCK_INT,N=8
+                    | (0b0011 << 12),
+            )
+        });
+
+        // CC3 and CC4 are "frozen" output compares, used to get an interrupt at the next segment
+        // boundary and when the spindle reverses back over the last one
+        self.set_compares();
+        self.tim2
+            .dier
+            .write(|w| w.cc3ie().set_bit().cc4ie().set_bit());
+
+        self.tim2.cr1.write(|w| w.cen().enabled());
+    }
+
+    fn set_compares(&self) {
+        self.tim2
+            .ccr3
+            .write(|w| w.ccr().bits(self.spindle.next_compare()));
+        self.tim2
+            .ccr4
+            .write(|w| w.ccr().bits(self.spindle.back_compare()));
+    }
+}
+
+impl SpindleSensor for EncoderSensor {
+    fn rpm(&self) -> u32 {
+        self.spindle.rpm(DWT::cycle_count())
+    }
+
+    fn angle(&self) -> u16 {
+        self.spindle.angle(self.tim2.cnt.read().cnt().bits())
+    }
+
+    fn interrupt(&mut self) -> bool {
+        if self.index.check_interrupt() {
+            self.index.clear_interrupt_pending_bit();
+            // Only align the counter once, it keeps track of the position afterwards
+            if self.is_aligned {
+                return false;
+            }
+            self.is_aligned = true;
+            self.tim2.cnt.write(|w| w.cnt().bits(0));
+            self.spindle.index(DWT::cycle_count());
+            self.set_compares();
+            true
+        } else if self.tim2.sr.read().cc3if().bit_is_set() {
+            // FIXME: check if we can get away with write...
+            self.tim2.sr.modify(|_, w| w.cc3if().clear_bit());
+
+            let is_start = self.spindle.crossed(DWT::cycle_count());
+            self.set_compares();
+            is_start
+        } else if self.tim2.sr.read().cc4if().bit_is_set() {
+            self.tim2.sr.modify(|_, w| w.cc4if().clear_bit());
+
+            let is_start = self.spindle.reversed(DWT::cycle_count());
+            self.set_compares();
+            is_start
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::hal::EEPROM_PARAMS;
+use eeprom::EEPROMExt;
+use stm32f1xx_hal::flash;
+use x2_feed_core::settings::SettingsStorage;
+
+/// Settings storage backed by the EEPROM emulation at the end of the flash memory.
+pub struct Storage {
+    flash: flash::Parts,
+}
+
+impl Storage {
+    pub fn new(flash: flash::Parts) -> Storage {
+        Storage { flash }
+    }
+}
+
+impl SettingsStorage for Storage {
+    type Error = flash::Error;
+
+    fn read(&mut self, tag: u16) -> Option<u16> {
+        self.flash.eeprom(EEPROM_PARAMS).read(tag)
+    }
+
+    fn write(&mut self, tag: u16, value: u16) -> flash::Result<()> {
+        self.flash.eeprom(EEPROM_PARAMS).write(tag, value)
+    }
+
+    fn erase(&mut self) -> flash::Result<()> {
+        self.flash.eeprom(EEPROM_PARAMS).erase()
+    }
+}
@@ -0,0 +1,43 @@
+use stm32f1::stm32f103::EXTI;
+use stm32f1xx_hal::afio;
+use stm32f1xx_hal::gpio::{Edge, ExtiPin, Input, Pin, PullDown, CRH, CRL};
+
+/// Home switch on PB5.
+pub type HomeSwitch = Switch<CRL, 'B', 5>;
+
+/// Hard limit switches on PA12. Switches at both ends of the travel share the same input.
+pub type LimitSwitch = Switch<CRH, 'A', 12>;
+
+/// Normally open switch, closing the input to 3.3V. Input is pulled down, so nothing happens if
+/// the switch is not connected. Interrupt is triggered both when the switch is pressed and
+/// released.
+pub struct Switch<CR, const P: char, const N: u8> {
+    pin: Pin<Input<PullDown>, CR, P, N>,
+}
+
+impl<CR, const P: char, const N: u8> Switch<CR, P, N> {
+    pub fn new(
+        mut pin: Pin<Input<PullDown>, CR, P, N>,
+        afio: &mut afio::Parts,
+        exti: &EXTI,
+    ) -> Self {
+        pin.make_interrupt_source(afio);
+        pin.trigger_on_edge(exti, Edge::RisingFalling);
+        pin.enable_interrupt(exti);
+        Switch { pin }
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.pin.is_high()
+    }
+
+    /// Check for pending interrupt and handle it (reset pending flag). Returns `true` if interrupt
+    /// was pending.
+    pub fn interrupt(&mut self) -> bool {
+        if !self.pin.check_interrupt() {
+            return false;
+        }
+        self.pin.clear_interrupt_pending_bit();
+        true
+    }
+}
@@ -0,0 +1,430 @@
+#![no_std]
+#![no_main]
+//#![deny(warnings)]
+
+//! Stepper-motor based power feed for X2 mill.
+//!
+//! The following features are supported:
+//!
+//! 1. Power feeding in both directions.
+//! 1. Two feed modes: "slow" and "fast".
+//! 1. Setting feed speed via rotary encoder (both "slow" and "fast").
+//! 1. Spindle tachometer via hall sensor.
+//! 1. Emergency stop mode: halts stepper motor driver when emergency stop is pressed.
+//! 1. Screen screen displays current spindle speed and feed speed.
+//! 1. Serial console on USART1 (PB6 is TX, PB7 is RX, 115200 8N1).
+//! 1. Homing with the home switch on PB5, optional hard limit switches on PA12.
+//! 1. Second stepper driver for the cross-slide: STEP on PB8 (TIM4), DIR on PB9, ENABLE on PB2,
+//!    RESET shared with the main driver (PA11).
+//!
+//! # PCB
+//! See PCB (Eagle CAD) in the [pcb/](pcb/) directory.
+
+use crate::hal::{Display, Screen};
+use core::panic::PanicInfo;
+use stm32f1::stm32f103::Peripherals;
+use stm32f1xx_hal::prelude::*;
+use x2_feed_core::menu::init_display;
+
+mod hal;
+
+#[rtic::app(device = stm32f1::stm32f103, peripherals = true)]
+mod app {
+    use crate::hal::{
+        delay, Controls, Display, EStop, EncoderSensor, HomeSwitch, Led, LimitSwitch, QuadEncoder,
+        RpmSensor, Screen, Spindle, StepperDriverImpl, Storage, DRIVER_TICK_FREQUENCY,
+        EEPROM_PARAMS,
+    };
+    use core::marker::PhantomData;
+    use eeprom::EEPROMExt;
+    use stm32f1::stm32f103::{Peripherals, USART1};
+    use stm32f1xx_hal::prelude::*;
+    use stm32f1xx_hal::serial::{self, Rx, Serial, Tx};
+    use x2_feed_core::console::{self, Console};
+    use x2_feed_core::gcode::Program;
+    use x2_feed_core::hal::{Board, SpindleSensor};
+    use x2_feed_core::menu::{
+        init_display, startup, LatheMenu, MenuItem, MenuResources, MillMenu, SharedResources,
+    };
+    use x2_feed_core::settings;
+    use x2_feed_core::stepper::Stepper;
+
+    /// Hardware the menus run on; shared resources are locked through the RTIC resource proxies.
+    pub struct FirmwareBoard<'a>(PhantomData<&'a ()>);
+
+    impl<'a> Board for FirmwareBoard<'a> {
+        type Screen = Screen;
+        type Encoder = QuadEncoder;
+        type Controls = Controls;
+        type EStop = EStop;
+        type Storage = Storage;
+        type Stopwatch = delay::Duration;
+        type Driver = StepperDriverImpl;
+        type Spindle = Spindle;
+        type Stepper = shared_resources::stepper_that_needs_to_be_locked<'a>;
+        type Cross = shared_resources::cross_that_needs_to_be_locked<'a>;
+        type Hall = shared_resources::hall_that_needs_to_be_locked<'a>;
+        type Flash = shared_resources::flash_that_needs_to_be_locked<'a>;
+        type Program = shared_resources::program_that_needs_to_be_locked<'a>;
+
+        fn wait_for_interrupt() {
+            cortex_m::asm::wfi();
+        }
+    }
+
+    #[shared]
+    struct Shared {
+        stepper: Stepper<StepperDriverImpl>,
+        /// Cross-slide axis
+        cross: Stepper<StepperDriverImpl>,
+        hall: Spindle,
+        flash: Storage,
+        program: Program,
+    }
+
+    #[local]
+    struct Local {
+        display: Display,
+        led: Led,
+        encoder: QuadEncoder,
+        controls: Controls,
+        estop: EStop,
+        console: Console,
+        tx: Tx<USART1>,
+        rx: Rx<USART1>,
+        home_switch: HomeSwitch,
+        limit_switch: Option<LimitSwitch>,
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let mut core: cortex_m::Peripherals = cx.core;
+        let peripherals: stm32f1::stm32f103::Peripherals = cx.device;
+
+        // Enable peripherals
+        peripherals.RCC.apb1enr.modify(|_, w| w.tim2en().enabled());
+        peripherals.RCC.apb1enr.modify(|_, w| w.tim3en().enabled());
+        peripherals.RCC.apb1enr.modify(|_, w| w.tim4en().enabled());
+        peripherals.RCC.apb2enr.modify(|_, w| w.tim1en().enabled());
+        peripherals.RCC.apb2enr.modify(|_, w| w.iopaen().enabled());
+        peripherals.RCC.apb2enr.modify(|_, w| w.iopben().enabled());
+        peripherals.RCC.apb2enr.modify(|_, w| w.afioen().enabled());
+
+        let flash = unsafe { Peripherals::steal().FLASH };
+        let mut flash = flash.constrain();
+        let rcc = peripherals.RCC.constrain();
+        let clocks = rcc
+            .cfgr
+            .use_hse(8.MHz())
+            .sysclk(72.MHz())
+            .pclk1(36.MHz())
+            .pclk2(72.MHz())
+            .hclk(72.MHz())
+            .freeze(&mut flash.acr);
+
+        // We use our own timer which keeps syst running (since we also use it for measuring delays)
+        // Note that SYST is running at the frequency of AHB/8, which is 9Mhz (72Mhz SYSCLK)
+        core.SYST.enable_counter();
+        core.SYST.set_reload(0x00ff_ffff);
+
+        // Cycle counter is used for timestamps of the spindle encoder
+        core.DCB.enable_trace();
+        core.DWT.enable_cycle_counter();
+
+        let mut gpioa = peripherals.GPIOA.split();
+        let hall_pin = gpioa.pa0.into_pull_up_input(&mut gpioa.crl);
+        let left_btn = gpioa.pa1.erase();
+        let right_btn = gpioa.pa2.erase();
+        let fast_btn = gpioa.pa3.erase();
+        let led_pin = gpioa.pa4.into_push_pull_output(&mut gpioa.crl).erase();
+        let encoder_btn = gpioa.pa5.erase();
+        let encoder_dt_pin = gpioa.pa6;
+        let encoder_clk_pin = gpioa.pa7;
+        let step_pin = gpioa.pa8.into_alternate_open_drain(&mut gpioa.crh);
+        let dir_pin = gpioa.pa9.into_open_drain_output(&mut gpioa.crh).erase();
+        let enable_pin = gpioa.pa10.into_open_drain_output(&mut gpioa.crh).erase();
+        let reset_pin = gpioa.pa11.into_open_drain_output(&mut gpioa.crh).erase();
+
+        let limit_pin = gpioa.pa12.into_pull_down_input(&mut gpioa.crh);
+        // Used by debugger, no need to "passivate"
+        //gpioa.pa13.into_pull_down_input(&mut gpioa.crh);
+        //gpioa.pa14.into_pull_down_input(&mut gpioa.crh);
+        //gpioa.pa15.into_pull_down_input(&mut gpioa.crh);
+
+        let mut gpiob = peripherals.GPIOB.split();
+        let mut afio = peripherals.AFIO.constrain();
+        let rs_pin = gpiob.pb1.into_push_pull_output(&mut gpiob.crl).erase();
+        let rw_pin = gpiob.pb10.into_push_pull_output(&mut gpiob.crh).erase();
+        let e_pin = gpiob.pb11.into_push_pull_output(&mut gpiob.crh).erase();
+        let db4 = gpiob.pb12.into_push_pull_output(&mut gpiob.crh).erase();
+        let db5 = gpiob.pb13.into_push_pull_output(&mut gpiob.crh).erase();
+        let db6 = gpiob.pb14.into_push_pull_output(&mut gpiob.crh).erase();
+        let db7 = gpiob.pb15.into_push_pull_output(&mut gpiob.crh).erase();
+
+        let cross_enable_pin = gpiob.pb2.into_open_drain_output(&mut gpiob.crl).erase();
+        // Used by debugger, no need to "passivate"
+        //gpiob.pb3.into_pull_down_input(&mut gpiob.crl);
+        //gpiob.pb4.into_pull_down_input(&mut gpiob.crl);
+        let home_pin = gpiob.pb5.into_pull_down_input(&mut gpiob.crl);
+        let cross_step_pin = gpiob.pb8.into_alternate_open_drain(&mut gpiob.crh);
+        let cross_dir_pin = gpiob.pb9.into_open_drain_output(&mut gpiob.crh).erase();
+
+        // Initialize EEPROM emulation
+        // FIXME: constants?..
+        let mut flash = peripherals.FLASH.constrain();
+        flash.eeprom(EEPROM_PARAMS).init().unwrap();
+        let mut flash = Storage::new(flash);
+        settings::check(&mut flash).unwrap();
+
+        // Initialize peripherals
+        let driver =
+            StepperDriverImpl::new(peripherals.TIM1, step_pin, dir_pin, enable_pin, reset_pin);
+        let cross_driver = StepperDriverImpl::cross(
+            peripherals.TIM4,
+            cross_step_pin,
+            cross_dir_pin,
+            cross_enable_pin,
+        );
+        let led = Led::new(led_pin);
+        let screen = Screen::new(rs_pin, rw_pin, e_pin, [db4, db5, db6, db7]);
+        let encoder = QuadEncoder::new(peripherals.TIM3, encoder_dt_pin, encoder_clk_pin);
+        let tx_pin = gpiob.pb6.into_alternate_push_pull(&mut gpiob.crl);
+        let rx_pin = gpiob.pb7;
+        let serial = Serial::usart1(
+            peripherals.USART1,
+            (tx_pin, rx_pin),
+            &mut afio.mapr,
+            serial::Config::default().baudrate(115200.bps()),
+            clocks,
+        );
+        let (tx, mut rx) = serial.split();
+        rx.listen();
+        let hall = if settings::SPINDLE_ENCODER.read(&mut flash) {
+            // Encoder is connected to the JTAG pins, SWD keeps working
+            let (encoder_a_pin, encoder_b_pin, _) =
+                afio.mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
+            let cpr = settings::SPINDLE_CPR.read(&mut flash);
+            Spindle::Encoder(EncoderSensor::new(
+                peripherals.TIM2,
+                encoder_a_pin,
+                encoder_b_pin,
+                hall_pin,
+                cpr,
+                &mut afio,
+                &peripherals.EXTI,
+            ))
+        } else {
+            Spindle::Hall(RpmSensor::new(peripherals.TIM2, hall_pin))
+        };
+        let home_switch = HomeSwitch::new(home_pin, &mut afio, &peripherals.EXTI);
+        // Without hard limit switches, pin stays "passivated"
+        let limit_switch = if settings::HARD_LIMITS.read(&mut flash) {
+            Some(LimitSwitch::new(limit_pin, &mut afio, &peripherals.EXTI))
+        } else {
+            None
+        };
+        let is_lathe = settings::IS_LATHE.read(&mut flash);
+        let mut stepper = Stepper::new(DRIVER_TICK_FREQUENCY, driver, !is_lathe);
+        stepper.set_home_switch(home_switch.is_pressed());
+        if let Some(ref limit_switch) = limit_switch {
+            stepper.set_limit_switch(limit_switch.is_pressed());
+        }
+        // Settings are loaded by the operations moving the cross-slide
+        let cross = Stepper::new(DRIVER_TICK_FREQUENCY, cross_driver, !is_lathe);
+        let mut display = Display::new(screen);
+        let controls = Controls::new(left_btn, right_btn, fast_btn, encoder_btn);
+
+        // Pull-up e-stop (it's `1` when not active).
+        let estop = EStop::new(gpiob.pb0.into_pull_up_input(&mut gpiob.crl).erase());
+
+        // LCD device init
+        // Need to wait at least 40ms after Vcc rises to 2.7V
+        // STM32 could start much earlier than that
+        delay::ms(50);
+
+        init_display(&mut display);
+        (
+            Shared {
+                stepper,
+                cross,
+                hall,
+                flash,
+                program: Program::new(),
+            },
+            Local {
+                display,
+                led,
+                encoder,
+                controls,
+                estop,
+                console: Console::new(),
+                tx,
+                rx,
+                home_switch,
+                limit_switch,
+            },
+            init::Monotonics(),
+        )
+    }
+
+    #[idle(local = [led, encoder, controls, display, estop], shared = [stepper, cross, hall, flash, program])]
+    fn idle(context: idle::Context) -> ! {
+        let mut r = MenuResources::<FirmwareBoard> {
+            encoder: context.local.encoder,
+            display: context.local.display,
+            controls: context.local.controls,
+            shared: SharedResources {
+                stepper: context.shared.stepper,
+                cross: context.shared.cross,
+                hall: context.shared.hall,
+                flash: context.shared.flash,
+                program: context.shared.program,
+            },
+            estop: context.local.estop,
+            session: None,
+        };
+
+        // Profile selected at startup decides the machine type
+        startup(&mut r);
+        let is_lathe = r.shared.flash.lock(|f| settings::IS_LATHE.read(f));
+        r.shared.stepper.lock(|s| s.set_disable_at_stop(!is_lathe));
+        r.shared.cross.lock(|s| s.set_disable_at_stop(!is_lathe));
+        if is_lathe {
+            let mut menu = LatheMenu::new();
+            loop {
+                menu.run(&mut r);
+            }
+        } else {
+            let mut menu = MillMenu::new();
+            loop {
+                menu.run(&mut r);
+            }
+        }
+    }
+
+    #[task(binds = TIM1_UP, priority = 16, shared = [stepper])]
+    fn step_completed(mut ctx: step_completed::Context) {
+        ctx.shared.stepper.lock(|s| s.interrupt())
+    }
+
+    #[task(binds = TIM4, priority = 16, shared = [cross])]
+    fn cross_step_completed(mut ctx: cross_step_completed::Context) {
+        ctx.shared.cross.lock(|s| s.interrupt())
+    }
+
+    #[task(binds = TIM2, priority = 2, shared = [hall, stepper, cross])]
+    fn hall_interrupt(ctx: hall_interrupt::Context) {
+        spindle_interrupt(ctx.shared.hall, ctx.shared.stepper, ctx.shared.cross);
+    }
+
+    /// Spindle encoder index pulse
+    #[task(binds = EXTI0, priority = 2, shared = [hall, stepper, cross])]
+    fn index_interrupt(ctx: index_interrupt::Context) {
+        spindle_interrupt(ctx.shared.hall, ctx.shared.stepper, ctx.shared.cross);
+    }
+
+    #[task(binds = EXTI9_5, priority = 2, local = [home_switch], shared = [stepper])]
+    fn home_interrupt(mut ctx: home_interrupt::Context) {
+        let switch = ctx.local.home_switch;
+        if switch.interrupt() {
+            ctx.shared
+                .stepper
+                .lock(|s| s.set_home_switch(switch.is_pressed()));
+        }
+    }
+
+    #[task(binds = EXTI15_10, priority = 2, local = [limit_switch], shared = [stepper])]
+    fn limit_interrupt(mut ctx: limit_interrupt::Context) {
+        if let Some(switch) = ctx.local.limit_switch {
+            if switch.interrupt() {
+                ctx.shared
+                    .stepper
+                    .lock(|s| s.set_limit_switch(switch.is_pressed()));
+            }
+        }
+    }
+
+    /// Serial console. Runs at the lowest priority as responses are written while blocking.
+    #[task(binds = USART1, priority = 1, local = [console, tx, rx], shared = [stepper, hall, flash, program])]
+    fn serial_interrupt(mut ctx: serial_interrupt::Context) {
+        while let Ok(byte) = ctx.local.rx.read() {
+            if let Some(command) = ctx.local.console.receive(byte) {
+                // Nothing we can do if the response cannot be written
+                let _ = console::respond(
+                    command,
+                    &mut ctx.shared.stepper,
+                    &mut ctx.shared.hall,
+                    &mut ctx.shared.flash,
+                    &mut ctx.shared.program,
+                    ctx.local.tx,
+                );
+            }
+        }
+    }
+
+    fn spindle_interrupt(
+        mut hall: impl rtic::Mutex<T = Spindle>,
+        mut stepper: impl rtic::Mutex<T = Stepper<StepperDriverImpl>>,
+        mut cross: impl rtic::Mutex<T = Stepper<StepperDriverImpl>>,
+    ) {
+        let (captured, rpm) = hall.lock(|h| (h.interrupt(), h.rpm()));
+        if captured {
+            // Start of the spindle revolution, update thread cutting logic
+            let position = stepper.lock(|s| {
+                s.spindle_sync(rpm);
+                s.position()
+            });
+            // Cross-slide follows the carriage (cutting the taper)
+            cross.lock(|s| s.follow_sync(position, rpm));
+        }
+    }
+}
+
+#[inline(never)]
+#[panic_handler]
+pub fn begin_panic_handler(info: &PanicInfo<'_>) -> ! {
+    // Immediately disable driver outputs, just in case
+    let mut gpioa = unsafe { Peripherals::steal().GPIOA }.split();
+    gpioa.pa10.into_push_pull_output(&mut gpioa.crh).set_low();
+
+    // Steal GPIOB and create another screen in an attempt to print some info
+    let mut gpiob = unsafe { Peripherals::steal().GPIOB }.split();
+    gpiob.pb2.into_push_pull_output(&mut gpiob.crl).set_low();
+    let rs_pin = gpiob.pb1.into_push_pull_output(&mut gpiob.crl).erase();
+    let rw_pin = gpiob.pb10.into_push_pull_output(&mut gpiob.crh).erase();
+    let e_pin = gpiob.pb11.into_push_pull_output(&mut gpiob.crh).erase();
+    let db4 = gpiob.pb12.into_push_pull_output(&mut gpiob.crh).erase();
+    let db5 = gpiob.pb13.into_push_pull_output(&mut gpiob.crh).erase();
+    let db6 = gpiob.pb14.into_push_pull_output(&mut gpiob.crh).erase();
+    let db7 = gpiob.pb15.into_push_pull_output(&mut gpiob.crh).erase();
+
+    let screen = Screen::new(rs_pin, rw_pin, e_pin, [db4, db5, db6, db7]);
+    let mut display = Display::new(screen);
+
+    // Print reason on the display
+    use core::fmt::Write;
+    init_display(&mut display);
+    display.position(0, 0);
+    // Location goes on the second line, so only the message is printed here
+    let _ = write!(display, "{}", info.message());
+    display.position(0, 1);
+    if let Some(loc) = info.location() {
+        let file = loc.file();
+        // Not indexing the string directly keeps the string slicing panic message out of flash
+        let file = match file.rfind('/') {
+            Some(pos) => file.get(pos + 1..).unwrap_or(file),
+            None => file,
+        };
+        let _ = write!(
+            display,
+            "{}:{} {}            ",
+            loc.line(),
+            loc.column(),
+            file
+        );
+    }
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -0,0 +1,624 @@
+//! Simulated board: virtual LCD, front panel (buttons, encoder and e-stop), spindle and the
+//! stepper motors of both axes driven by `SimDriver`.
+use crate::clock;
+use crate::input::{self, Command, Input, Next};
+use crate::lcd::{Hd44780, ROWS};
+use rtic_core::{Exclusive, Mutex};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use x2_feed_core::console::{self, Console};
+use x2_feed_core::gcode::Program;
+use x2_feed_core::hal::{self, Board, Button, ControlsState, Event, SpindleSensor, BUTTONS};
+use x2_feed_core::settings::SettingsStorage;
+use x2_feed_core::sim::SimDriver;
+use x2_feed_core::stepper::Stepper;
+
+/// Stepper driver timer frequency, same as on the device (1us resolution).
+pub const DRIVER_TICK_FREQUENCY: u32 = 1_000_000;
+
+/// Time it takes to poll the buttons, in microseconds.
+const POLL_US: u64 = 100;
+/// Time it takes to lock the shared resource, in microseconds.
+const LOCK_US: u64 = 10;
+/// Pause after each input command, so menus observe every change of the buttons state.
+const COMMAND_GAP_US: u64 = 50_000;
+/// How long the encoder button is held for the short press.
+const CLICK_US: u64 = 100_000;
+/// How long the encoder button is held for the long press ("exit").
+const LONG_PRESS_US: u64 = 2_000_000;
+
+/// How often screen is checked for changes while resources are being locked.
+const FRAME_CHECK_US: u64 = 50_000;
+
+/// Encoder limit after reset (timer auto-reload register is `0xffff`).
+const ENCODER_RESET_LIMIT: u16 = 0x4000;
+
+fn index(button: Button) -> usize {
+    BUTTONS.iter().position(|b| *b == button).unwrap()
+}
+
+pub struct Panel {
+    buttons: [bool; 4],
+    estop: bool,
+    encoder_limit: u16,
+    encoder_current: u16,
+    /// Encoder button is released at this time
+    release_at: Option<u64>,
+    /// Next input command is processed at this time
+    resume_at: u64,
+}
+
+/// Spindle rotating at the given speed, with the hall sensor emitting one pulse per revolution.
+pub struct Spindle {
+    /// Actual spindle speed, in RPM
+    speed: u32,
+    next_pulse: u64,
+    last_pulse: Option<u64>,
+    /// Measured speed, in 24.8 format
+    measured: u32,
+    /// Pulse is not handled by the "interrupt handler" yet
+    pending: bool,
+}
+
+impl Spindle {
+    fn set_rpm(&mut self, rpm: u32) {
+        if self.speed == 0 {
+            self.next_pulse = clock::now() + 60_000_000 / u64::from(rpm.max(1));
+        }
+        self.speed = rpm;
+        if rpm == 0 {
+            self.last_pulse = None;
+            self.measured = 0;
+        }
+    }
+
+    fn next_pulse(&self) -> Option<u64> {
+        if self.speed == 0 {
+            None
+        } else {
+            Some(self.next_pulse)
+        }
+    }
+
+    /// Hall sensor pulse.
+    fn pulse(&mut self) {
+        let now = self.next_pulse;
+        if let Some(last) = self.last_pulse {
+            self.measured = ((60_000_000 << 8) / (now - last)) as u32;
+        }
+        self.last_pulse = Some(now);
+        self.next_pulse = now + 60_000_000 / u64::from(self.speed);
+        self.pending = true;
+    }
+}
+
+impl SpindleSensor for Spindle {
+    fn rpm(&self) -> u32 {
+        self.measured
+    }
+
+    fn angle(&self) -> u16 {
+        match self.last_pulse {
+            Some(last) if self.speed != 0 => {
+                let period = self.next_pulse - last;
+                (((clock::now() - last) << 16) / period).min(0xffff) as u16
+            }
+            _ => 0,
+        }
+    }
+
+    fn interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Home and hard limit switches, placed along the carriage travel.
+#[derive(Default)]
+pub struct Switches {
+    /// Carriage position, in steps the stepper motor made since the start of the simulation (so
+    /// it doesn't change when stepper position is zeroed by homing)
+    carriage: i64,
+    /// Home switch is pressed at this position and beyond it (away from the start)
+    home: Option<i64>,
+    /// Hard limit switches, each is pressed at its position and beyond it (away from the start).
+    /// All switches share the same input.
+    limits: Vec<i64>,
+    home_pressed: bool,
+    limit_pressed: bool,
+}
+
+impl Switches {
+    /// Update carriage position with the steps stepper made and report switches which changed
+    /// their state to the stepper.
+    fn update(&mut self, stepper: &mut Stepper<SimDriver>) {
+        for step in stepper.driver_mut().take_steps() {
+            self.carriage += if step.direction { 1 } else { -1 };
+        }
+        let carriage = self.carriage;
+        let is_pressed = |switch: i64| {
+            if switch < 0 {
+                carriage <= switch
+            } else {
+                carriage >= switch
+            }
+        };
+        let home_pressed = self.home.is_some_and(is_pressed);
+        if home_pressed != self.home_pressed {
+            self.home_pressed = home_pressed;
+            stepper.set_home_switch(home_pressed);
+        }
+        let limit_pressed = self.limits.iter().copied().any(is_pressed);
+        if limit_pressed != self.limit_pressed {
+            self.limit_pressed = limit_pressed;
+            stepper.set_limit_switch(limit_pressed);
+        }
+    }
+}
+
+pub struct Machine {
+    stepper: RefCell<Stepper<SimDriver>>,
+    /// Cross-slide axis
+    cross: RefCell<Stepper<SimDriver>>,
+    spindle: RefCell<Spindle>,
+    switches: RefCell<Switches>,
+    flash: RefCell<MemoryStorage>,
+    program: RefCell<Program>,
+    panel: RefCell<Panel>,
+    lcd: RefCell<Hd44780>,
+    input: RefCell<Input>,
+    /// Nesting level of the shared resources locks
+    locks: Cell<u32>,
+    /// Real time at the start of the simulation, if simulation is paced to the real time
+    started: Option<Instant>,
+    last_frame: RefCell<[String; ROWS]>,
+    /// Time screen was last checked for changes
+    frame_checked: Cell<u64>,
+}
+
+impl Machine {
+    pub fn new(input: Input, flash: MemoryStorage, disable_at_stop: bool) -> Rc<Machine> {
+        let started = if input.is_interactive() {
+            Some(Instant::now())
+        } else {
+            None
+        };
+        Rc::new(Machine {
+            stepper: RefCell::new(Stepper::new(
+                DRIVER_TICK_FREQUENCY,
+                SimDriver::new(),
+                disable_at_stop,
+            )),
+            cross: RefCell::new(Stepper::new(
+                DRIVER_TICK_FREQUENCY,
+                SimDriver::new(),
+                disable_at_stop,
+            )),
+            spindle: RefCell::new(Spindle {
+                speed: 0,
+                next_pulse: 0,
+                last_pulse: None,
+                measured: 0,
+                pending: false,
+            }),
+            switches: RefCell::new(Switches::default()),
+            flash: RefCell::new(flash),
+            program: RefCell::new(Program::new()),
+            panel: RefCell::new(Panel {
+                buttons: [false; 4],
+                estop: false,
+                encoder_limit: ENCODER_RESET_LIMIT,
+                encoder_current: 0,
+                release_at: None,
+                resume_at: 0,
+            }),
+            lcd: RefCell::new(Hd44780::default()),
+            input: RefCell::new(input),
+            locks: Cell::new(0),
+            started,
+            last_frame: RefCell::new(Default::default()),
+            frame_checked: Cell::new(0),
+        })
+    }
+
+    /// Run interrupt handlers for all the events that happened up to the current time: stepper
+    /// driver timer updates (of both axes) and hall sensor pulses.
+    fn catch_up(&self) {
+        let now = clock::now();
+        let mut stepper = self.stepper.borrow_mut();
+        let mut cross = self.cross.borrow_mut();
+        let mut spindle = self.spindle.borrow_mut();
+        let mut switches = self.switches.borrow_mut();
+        loop {
+            let update = stepper.driver().next_update().filter(|&t| t <= now);
+            let cross_update = cross.driver().next_update().filter(|&t| t <= now);
+            let pulse = spindle.next_pulse().filter(|&t| t <= now);
+            let earliest = [update, cross_update, pulse].into_iter().flatten().min();
+            match earliest {
+                // Stepper interrupts have higher priority
+                Some(time) if update == Some(time) => {
+                    cross.driver_mut().advance(time);
+                    if stepper.driver_mut().advance(time) {
+                        stepper.interrupt();
+                    }
+                    switches.update(&mut stepper);
+                }
+                Some(time) if cross_update == Some(time) => {
+                    stepper.driver_mut().advance(time);
+                    if cross.driver_mut().advance(time) {
+                        cross.interrupt();
+                    }
+                }
+                Some(time) => {
+                    stepper.driver_mut().advance(time);
+                    cross.driver_mut().advance(time);
+                    spindle.pulse();
+                    if spindle.interrupt() {
+                        stepper.spindle_sync(spindle.rpm());
+                        cross.follow_sync(stepper.position(), spindle.rpm());
+                    }
+                }
+                None => {
+                    stepper.driver_mut().advance(now);
+                    cross.driver_mut().advance(now);
+                    break;
+                }
+            }
+        }
+        switches.update(&mut stepper);
+        drop((stepper, cross, spindle, switches));
+
+        if let Some(started) = self.started {
+            let simulated = Duration::from_micros(now);
+            let real = started.elapsed();
+            if simulated > real + Duration::from_millis(1) {
+                std::thread::sleep(simulated - real);
+            }
+        }
+    }
+
+    /// Lock shared resources, preventing "interrupts" from being handled.
+    fn lock<R>(&self, f: impl FnOnce(&Machine) -> R) -> R {
+        if self.locks.get() == 0 {
+            clock::advance(LOCK_US);
+            self.catch_up();
+            // Some screens are updated without polling the buttons (like "Cutting...")
+            if clock::now() >= self.frame_checked.get() + FRAME_CHECK_US {
+                self.print_frame();
+            }
+        }
+        self.locks.set(self.locks.get() + 1);
+        let result = f(self);
+        self.locks.set(self.locks.get() - 1);
+        result
+    }
+
+    /// Called every time buttons are polled: handles interrupts, prints the screen if it was
+    /// changed and processes operator input.
+    fn poll(&self) {
+        clock::advance(POLL_US);
+        self.catch_up();
+        self.print_frame();
+        self.process_input();
+    }
+
+    fn print_frame(&self) {
+        self.frame_checked.set(clock::now());
+        let rows = self.lcd.borrow().rows();
+        if *self.last_frame.borrow() == rows {
+            return;
+        }
+        let position = self.stepper.borrow().position();
+        let cross = self.cross.borrow().position();
+        let rpm = (self.spindle.borrow().measured + 128) >> 8;
+        println!(
+            "[{:>9.3} s] position: {}, cross: {}, spindle: {} RPM",
+            clock::now() as f64 / 1e6,
+            position,
+            cross,
+            rpm
+        );
+        for row in &rows {
+            println!("    |{}|", row);
+        }
+        *self.last_frame.borrow_mut() = rows;
+    }
+
+    /// Send the line to the serial console and print the response.
+    fn serial(&self, line: &str) {
+        self.catch_up();
+        let mut console = Console::new();
+        let mut response = String::new();
+        for &byte in line.as_bytes().iter().chain(b"\n") {
+            if let Some(command) = console.receive(byte) {
+                console::respond(
+                    command,
+                    &mut Exclusive(&mut *self.stepper.borrow_mut()),
+                    &mut Exclusive(&mut *self.spindle.borrow_mut()),
+                    &mut Exclusive(&mut *self.flash.borrow_mut()),
+                    &mut Exclusive(&mut *self.program.borrow_mut()),
+                    &mut response,
+                )
+                .unwrap();
+            }
+        }
+        for line in response.lines() {
+            println!("[{:>9.3} s] serial: {}", clock::now() as f64 / 1e6, line);
+        }
+    }
+
+    fn process_input(&self) {
+        let now = clock::now();
+        let mut panel = self.panel.borrow_mut();
+        if let Some(release_at) = panel.release_at {
+            if now < release_at {
+                return;
+            }
+            panel.buttons[index(Button::Encoder)] = false;
+            panel.release_at = None;
+        }
+        if now < panel.resume_at {
+            return;
+        }
+
+        let mut input = self.input.borrow_mut();
+        let command = loop {
+            match input.next_line() {
+                Next::Pending => return,
+                Next::End => {
+                    drop((panel, input));
+                    self.print_frame();
+                    std::process::exit(0);
+                }
+                Next::Line(line) => match input::parse(&line) {
+                    Ok(Some(command)) => break command,
+                    Ok(None) => {}
+                    Err(err) if input.is_interactive() => eprintln!("error: {}", err),
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
+                },
+            }
+        };
+
+        panel.resume_at = now + COMMAND_GAP_US;
+        match command {
+            Command::Toggle(button) => {
+                panel.buttons[index(button)] = !panel.buttons[index(button)];
+            }
+            Command::Click | Command::LongPress => {
+                let duration = if command == Command::Click {
+                    CLICK_US
+                } else {
+                    LONG_PRESS_US
+                };
+                panel.buttons[index(Button::Encoder)] = true;
+                panel.release_at = Some(now + duration);
+                panel.resume_at = now + duration + COMMAND_GAP_US;
+            }
+            Command::Encoder(delta) => {
+                let limit = i32::from(panel.encoder_limit);
+                let current = (i32::from(panel.encoder_current) + delta).rem_euclid(limit);
+                panel.encoder_current = current as u16;
+            }
+            Command::Rpm(rpm) => self.spindle.borrow_mut().set_rpm(rpm),
+            Command::EStop => panel.estop = true,
+            Command::Serial(line) => self.serial(&line),
+            Command::Home(position) => {
+                self.switches.borrow_mut().home = Some(position.into());
+                self.catch_up();
+            }
+            Command::Limit(position) => {
+                self.switches.borrow_mut().limits.push(position.into());
+                self.catch_up();
+            }
+            Command::Wait(ms) => panel.resume_at = now + u64::from(ms) * 1000,
+            Command::Quit => std::process::exit(0),
+        }
+    }
+}
+
+pub struct Screen(pub Rc<Machine>);
+
+impl lcd::Hardware for Screen {
+    fn rs(&mut self, bit: bool) {
+        self.0.lcd.borrow_mut().rs(bit);
+    }
+
+    fn enable(&mut self, bit: bool) {
+        self.0.lcd.borrow_mut().enable(bit);
+    }
+
+    fn data(&mut self, data: u8) {
+        self.0.lcd.borrow_mut().data(data);
+    }
+}
+
+impl lcd::Delay for Screen {
+    fn delay_us(&mut self, delay_usec: u32) {
+        clock::advance(u64::from(delay_usec));
+    }
+}
+
+pub struct Encoder(pub Rc<Machine>);
+
+impl hal::QuadEncoder for Encoder {
+    fn get_limit(&self) -> u16 {
+        self.0.panel.borrow().encoder_limit
+    }
+
+    fn set_limit_unsafe(&mut self, limit: u16) {
+        self.0.panel.borrow_mut().encoder_limit = limit;
+    }
+
+    fn current(&self) -> u16 {
+        self.0.panel.borrow().encoder_current
+    }
+
+    fn set_current(&mut self, pos: u16) {
+        self.0.panel.borrow_mut().encoder_current = pos;
+    }
+}
+
+pub struct Controls {
+    machine: Rc<Machine>,
+    last: [bool; 4],
+}
+
+impl Controls {
+    pub fn new(machine: Rc<Machine>) -> Controls {
+        Controls {
+            machine,
+            last: [false; 4],
+        }
+    }
+}
+
+impl hal::Controls for Controls {
+    fn state(&self) -> ControlsState {
+        let pressed = self.machine.panel.borrow().buttons;
+        ControlsState {
+            left: pressed[0],
+            right: pressed[1],
+            fast: pressed[2],
+            button: pressed[3],
+        }
+    }
+
+    fn read_event(&mut self) -> Event {
+        self.machine.poll();
+        let pressed = self.machine.panel.borrow().buttons;
+        for (idx, &state) in pressed.iter().enumerate() {
+            if state != self.last[idx] {
+                self.last[idx] = state;
+                return if state {
+                    Event::Pressed(BUTTONS[idx])
+                } else {
+                    Event::Unpressed(BUTTONS[idx])
+                };
+            }
+        }
+        Event::None
+    }
+}
+
+pub struct EStop(pub Rc<Machine>);
+
+impl hal::EStop for EStop {
+    fn is_emergency_stop(&self) -> bool {
+        self.0.panel.borrow().estop
+    }
+}
+
+pub struct Stopwatch {
+    started: u64,
+}
+
+impl hal::Stopwatch for Stopwatch {
+    fn start() -> Self {
+        Stopwatch {
+            started: clock::now(),
+        }
+    }
+
+    fn elapsed_us(&mut self) -> u32 {
+        (clock::now() - self.started) as u32
+    }
+}
+
+/// Settings kept in memory, starting with defaults on every run.
+#[derive(Default)]
+pub struct MemoryStorage(HashMap<u16, u16>);
+
+impl SettingsStorage for MemoryStorage {
+    type Error = Infallible;
+
+    fn read(&mut self, tag: u16) -> Option<u16> {
+        self.0.get(&tag).copied()
+    }
+
+    fn write(&mut self, tag: u16, value: u16) -> Result<(), Infallible> {
+        self.0.insert(tag, value);
+        Ok(())
+    }
+
+    fn erase(&mut self) -> Result<(), Infallible> {
+        self.0.clear();
+        Ok(())
+    }
+}
+
+pub struct StepperLock(pub Rc<Machine>);
+
+impl Mutex for StepperLock {
+    type T = Stepper<SimDriver>;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut Self::T) -> R) -> R {
+        self.0.lock(|m| f(&mut m.stepper.borrow_mut()))
+    }
+}
+
+pub struct CrossLock(pub Rc<Machine>);
+
+impl Mutex for CrossLock {
+    type T = Stepper<SimDriver>;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut Self::T) -> R) -> R {
+        self.0.lock(|m| f(&mut m.cross.borrow_mut()))
+    }
+}
+
+pub struct HallLock(pub Rc<Machine>);
+
+impl Mutex for HallLock {
+    type T = Spindle;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut Self::T) -> R) -> R {
+        self.0.lock(|m| f(&mut m.spindle.borrow_mut()))
+    }
+}
+
+pub struct FlashLock(pub Rc<Machine>);
+
+impl Mutex for FlashLock {
+    type T = MemoryStorage;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut Self::T) -> R) -> R {
+        self.0.lock(|m| f(&mut m.flash.borrow_mut()))
+    }
+}
+
+pub struct ProgramLock(pub Rc<Machine>);
+
+impl Mutex for ProgramLock {
+    type T = Program;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut Self::T) -> R) -> R {
+        self.0.lock(|m| f(&mut m.program.borrow_mut()))
+    }
+}
+
+pub struct SimBoard;
+
+impl Board for SimBoard {
+    type Screen = Screen;
+    type Encoder = Encoder;
+    type Controls = Controls;
+    type EStop = EStop;
+    type Storage = MemoryStorage;
+    type Stopwatch = Stopwatch;
+    type Driver = SimDriver;
+    type Spindle = Spindle;
+    type Stepper = StepperLock;
+    type Cross = CrossLock;
+    type Hall = HallLock;
+    type Flash = FlashLock;
+    type Program = ProgramLock;
+
+    fn wait_for_interrupt() {
+        // Time moves forward every time stepper is locked, so nothing to wait for
+    }
+}
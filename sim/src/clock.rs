@@ -0,0 +1,17 @@
+//! Virtual time of the simulation, in microseconds. Time only moves forward when the simulated
+//! hardware is used: LCD delays, button polling and locking of the shared resources.
+use std::cell::Cell;
+
+thread_local! {
+    static NOW: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Current virtual time, in microseconds.
+pub fn now() -> u64 {
+    NOW.with(|now| now.get())
+}
+
+/// Move virtual time forward by given amount of microseconds.
+pub fn advance(us: u64) {
+    NOW.with(|now| now.set(now.get() + us));
+}
@@ -0,0 +1,158 @@
+//! Operator input: commands read from the script file or typed in the terminal.
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use x2_feed_core::hal::Button;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    /// Press the button if it is released, release it otherwise (`l`, `r`, `f`)
+    Toggle(Button),
+    /// Short press of the encoder button (`e`)
+    Click,
+    /// Long press of the encoder button, "exit" (`E`)
+    LongPress,
+    /// Turn the encoder by the given amount of clicks (`+N`, `-N`)
+    Encoder(i32),
+    /// Set spindle speed (`rpm N`)
+    Rpm(u32),
+    /// Press the emergency stop (`estop`)
+    EStop,
+    /// Send the line to the serial console (`serial LINE`)
+    Serial(String),
+    /// Place the home switch at the given carriage position, in steps (`home N`)
+    Home(i32),
+    /// Add hard limit switch at the given carriage position, in steps (`limit N`)
+    Limit(i32),
+    /// Do nothing for the given amount of milliseconds (`wait MS`)
+    Wait(u32),
+    /// Stop the simulation (`q`)
+    Quit,
+}
+
+/// Parse a single line of input. Returns `Ok(None)` for empty lines and `#` comments.
+pub fn parse(line: &str) -> Result<Option<Command>, String> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        None => return Ok(None),
+        Some(command) => command,
+    };
+    let mut arg = || -> Result<&str, String> {
+        words
+            .next()
+            .ok_or_else(|| format!("'{}' needs an argument", command))
+    };
+
+    let result = match command {
+        "l" => Command::Toggle(Button::Left),
+        "r" => Command::Toggle(Button::Right),
+        "f" => Command::Toggle(Button::Fast),
+        "e" => Command::Click,
+        "E" => Command::LongPress,
+        "rpm" => Command::Rpm(number(arg()?)?),
+        "estop" => Command::EStop,
+        "serial" => Command::Serial(words.collect::<Vec<_>>().join(" ")),
+        "wait" => Command::Wait(number(arg()?)?),
+        "home" => Command::Home(number(arg()?)?),
+        "limit" => Command::Limit(number(arg()?)?),
+        "q" => Command::Quit,
+        _ if command.starts_with(['+', '-']) => Command::Encoder(
+            command
+                .parse()
+                .map_err(|_| format!("invalid encoder delta '{}'", command))?,
+        ),
+        _ => return Err(format!("unknown command '{}'", command)),
+    };
+    Ok(Some(result))
+}
+
+fn number<T: std::str::FromStr>(arg: &str) -> Result<T, String> {
+    arg.parse().map_err(|_| format!("invalid number '{}'", arg))
+}
+
+pub enum Next {
+    Line(String),
+    /// Nothing to do yet (interactive input only)
+    Pending,
+    End,
+}
+
+pub enum Input {
+    Script(VecDeque<String>),
+    Interactive(Receiver<String>),
+}
+
+impl Input {
+    pub fn script(text: &str) -> Input {
+        Input::Script(text.lines().map(String::from).collect())
+    }
+
+    /// Read commands from the standard input. Lines are read on a separate thread, so simulation
+    /// keeps running while waiting for the operator.
+    pub fn interactive() -> Input {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Input::Interactive(rx)
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        matches!(self, Input::Interactive(_))
+    }
+
+    pub fn next_line(&mut self) -> Next {
+        match self {
+            Input::Script(lines) => lines.pop_front().map_or(Next::End, Next::Line),
+            Input::Interactive(rx) => match rx.try_recv() {
+                Ok(line) => Next::Line(line),
+                Err(TryRecvError::Empty) => Next::Pending,
+                Err(TryRecvError::Disconnected) => Next::End,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(Ok(Some(Command::Toggle(Button::Left))), parse("l"));
+        assert_eq!(Ok(Some(Command::LongPress)), parse("  E  "));
+        assert_eq!(Ok(Some(Command::Encoder(5))), parse("+5"));
+        assert_eq!(Ok(Some(Command::Encoder(-12))), parse("-12 # back"));
+        assert_eq!(Ok(Some(Command::Rpm(600))), parse("rpm 600"));
+        assert_eq!(Ok(Some(Command::Wait(1500))), parse("wait 1500"));
+        assert_eq!(Ok(Some(Command::Home(-2000))), parse("home -2000"));
+        assert_eq!(Ok(Some(Command::Limit(30000))), parse("limit 30000"));
+        assert_eq!(
+            Ok(Some(Command::Serial("set pitch 20".into()))),
+            parse("serial set  pitch 20")
+        );
+        assert_eq!(Ok(None), parse(""));
+        assert_eq!(Ok(None), parse("# Select threading"));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse("rpm").is_err());
+        assert!(parse("wait x").is_err());
+        assert!(parse("+x").is_err());
+        assert!(parse("jump").is_err());
+    }
+
+    #[test]
+    fn script() {
+        let mut input = Input::script("e\n+1\n");
+        assert!(matches!(input.next_line(), Next::Line(line) if line == "e"));
+        assert!(matches!(input.next_line(), Next::Line(line) if line == "+1"));
+        assert!(matches!(input.next_line(), Next::End));
+    }
+}
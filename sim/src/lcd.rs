@@ -0,0 +1,224 @@
+//! Virtual HD44780 LCD controller, decoding the 4-bit protocol driven through `lcd::Hardware`.
+use x2_feed_core::font;
+
+pub const COLUMNS: usize = 16;
+pub const ROWS: usize = 2;
+
+/// DDRAM address of the first character of each row.
+const ROW_OFFSETS: [usize; ROWS] = [0x00, 0x40];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Ddram,
+    Cgram,
+}
+
+pub struct Hd44780 {
+    // Pins
+    rs: bool,
+    enable: bool,
+    data: u8,
+
+    /// Interface is 8-bit wide (only the upper 4 bits are connected, though)
+    bit8: bool,
+    /// First (upper) nibble of the byte transferred in 4-bit mode
+    pending: Option<u8>,
+
+    ddram: [u8; 0x80],
+    cgram: [u8; 0x40],
+    address: usize,
+    target: Target,
+    increment: bool,
+    display_on: bool,
+}
+
+impl Default for Hd44780 {
+    fn default() -> Self {
+        Hd44780 {
+            rs: false,
+            enable: false,
+            data: 0,
+            // Controller always starts in 8-bit mode
+            bit8: true,
+            pending: None,
+            ddram: [b' '; 0x80],
+            cgram: [0; 0x40],
+            address: 0,
+            target: Target::Ddram,
+            increment: true,
+            display_on: false,
+        }
+    }
+}
+
+impl Hd44780 {
+    pub fn rs(&mut self, bit: bool) {
+        self.rs = bit;
+    }
+
+    pub fn data(&mut self, data: u8) {
+        self.data = data & 0xf;
+    }
+
+    /// Data is latched on the falling edge of the "enable" signal.
+    pub fn enable(&mut self, bit: bool) {
+        if self.enable && !bit {
+            self.latch();
+        }
+        self.enable = bit;
+    }
+
+    fn latch(&mut self) {
+        let nibble = self.data;
+        let byte = if self.bit8 {
+            nibble << 4
+        } else if let Some(high) = self.pending.take() {
+            (high << 4) | nibble
+        } else {
+            self.pending = Some(nibble);
+            return;
+        };
+
+        if self.rs {
+            self.write_data(byte);
+        } else {
+            self.command(byte);
+        }
+    }
+
+    fn command(&mut self, cmd: u8) {
+        if cmd & 0x80 != 0 {
+            self.target = Target::Ddram;
+            self.address = usize::from(cmd & 0x7f);
+        } else if cmd & 0x40 != 0 {
+            self.target = Target::Cgram;
+            self.address = usize::from(cmd & 0x3f);
+        } else if cmd & 0x20 != 0 {
+            self.bit8 = cmd & 0x10 != 0;
+            self.pending = None;
+        } else if cmd & 0x10 != 0 {
+            // Cursor / display shift are not supported
+        } else if cmd & 0x08 != 0 {
+            self.display_on = cmd & 0x04 != 0;
+        } else if cmd & 0x04 != 0 {
+            self.increment = cmd & 0x02 != 0;
+        } else if cmd & 0x02 != 0 {
+            self.target = Target::Ddram;
+            self.address = 0;
+        } else if cmd & 0x01 != 0 {
+            self.ddram = [b' '; 0x80];
+            self.target = Target::Ddram;
+            self.address = 0;
+            self.increment = true;
+        }
+    }
+
+    fn write_data(&mut self, data: u8) {
+        let (memory, mask): (&mut [u8], usize) = match self.target {
+            Target::Ddram => (&mut self.ddram, 0x7f),
+            Target::Cgram => (&mut self.cgram, 0x3f),
+        };
+        memory[self.address] = data;
+        self.address = if self.increment {
+            self.address.wrapping_add(1)
+        } else {
+            self.address.wrapping_sub(1)
+        } & mask;
+    }
+
+    /// Text currently shown on the screen, one string per row. Custom characters are rendered as
+    /// arrows if they match the glyphs from the `font` module.
+    pub fn rows(&self) -> [String; ROWS] {
+        ROW_OFFSETS.map(|offset| {
+            if !self.display_on {
+                return " ".repeat(COLUMNS);
+            }
+            self.ddram[offset..offset + COLUMNS]
+                .iter()
+                .map(|&c| self.render(c))
+                .collect()
+        })
+    }
+
+    fn render(&self, c: u8) -> char {
+        match c {
+            0x20..=0x7e => char::from(c),
+            0x00..=0x0f => {
+                let loc = usize::from(c & 0x7) * 8;
+                let glyph = &self.cgram[loc..loc + 8];
+                if glyph == font::LEFT_CHAR {
+                    '◂'
+                } else if glyph == font::RIGHT_CHAR {
+                    '▸'
+                } else if glyph == font::FAST_LEFT_CHAR {
+                    '◀'
+                } else if glyph == font::FAST_RIGHT_CHAR {
+                    '▶'
+                } else {
+                    '▒'
+                }
+            }
+            _ => '?',
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Screen(Rc<RefCell<Hd44780>>);
+
+    impl lcd::Hardware for Screen {
+        fn rs(&mut self, bit: bool) {
+            self.0.borrow_mut().rs(bit);
+        }
+
+        fn enable(&mut self, bit: bool) {
+            self.0.borrow_mut().enable(bit);
+        }
+
+        fn data(&mut self, data: u8) {
+            self.0.borrow_mut().data(data);
+        }
+    }
+
+    impl lcd::Delay for Screen {
+        fn delay_us(&mut self, _delay_usec: u32) {}
+    }
+
+    #[test]
+    fn print() {
+        let controller = Rc::new(RefCell::new(Hd44780::default()));
+        let mut display = lcd::Display::new(Screen(controller.clone()));
+        x2_feed_core::menu::init_display(&mut display);
+
+        display.position(0, 0);
+        write!(display, "Hello").unwrap();
+        display.position(2, 1);
+        write!(display, "{}{}World", font::LEFT, font::FAST_RIGHT).unwrap();
+        assert_eq!(
+            ["Hello           ", "  ◂▶World       "],
+            controller.borrow().rows()
+        );
+
+        display.clear();
+        write!(display, "Bye").unwrap();
+        assert_eq!(
+            ["Bye             ", "                "],
+            controller.borrow().rows()
+        );
+    }
+
+    #[test]
+    fn display_off() {
+        let controller = Rc::new(RefCell::new(Hd44780::default()));
+        let mut display = lcd::Display::new(Screen(controller.clone()));
+        display.init(lcd::FunctionLine::Line2, lcd::FunctionDots::Dots5x8);
+        write!(display, "Hidden").unwrap();
+        assert_eq!(" ".repeat(COLUMNS), controller.borrow().rows()[0]);
+    }
+}
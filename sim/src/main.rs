@@ -0,0 +1,113 @@
+//! Desktop simulator of the power feed: runs the firmware menus against a virtual LCD, buttons,
+//! rotary encoder, spindle and stepper motor.
+//!
+//! Operator input is read line by line, either from the script given as an argument or from the
+//! terminal:
+//!
+//! * `l`, `r`, `f` -- press (or release, if pressed) "Left", "Right" or "Fast" button;
+//! * `e` -- click the encoder button ("Select");
+//! * `E` -- hold the encoder button for two seconds ("Exit");
+//! * `+N`, `-N` -- turn the encoder by `N` clicks;
+//! * `rpm N` -- set spindle speed;
+//! * `estop` -- press emergency stop;
+//! * `serial LINE` -- send the command line to the serial console and print the response;
+//! * `home N` -- place the home switch at `N` steps from the start position;
+//! * `limit N` -- add a hard limit switch at `N` steps from the start position;
+//! * `wait MS` -- let the simulation run for the given amount of milliseconds;
+//! * `q` -- quit.
+//!
+//! Everything after `#` is a comment. Every time the screen changes, it is printed along with the
+//! current (simulated) time, positions of both axes and spindle speed.
+use crate::board::{
+    Controls, CrossLock, EStop, Encoder, FlashLock, HallLock, Machine, MemoryStorage, ProgramLock,
+    Screen, SimBoard, StepperLock,
+};
+use crate::input::Input;
+use x2_feed_core::menu::{
+    init_display, startup, LatheMenu, MenuItem, MenuResources, MillMenu, SharedResources,
+};
+use x2_feed_core::settings;
+
+mod board;
+mod clock;
+mod input;
+mod lcd;
+
+const USAGE: &str = "Usage: x2-feed-sim [--lathe] [SCRIPT]";
+
+fn main() {
+    let mut is_lathe = false;
+    let mut script = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--lathe" => is_lathe = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if script.is_none() && !arg.starts_with('-') => script = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let input = match script {
+        Some(path) => match std::fs::read_to_string(&path) {
+            Ok(text) => Input::script(&text),
+            Err(err) => {
+                eprintln!("cannot read '{}': {}", path, err);
+                std::process::exit(2);
+            }
+        },
+        None => Input::interactive(),
+    };
+
+    // Panics are how firmware reports fatal errors (like emergency stop), just report and exit.
+    std::panic::set_hook(Box::new(|info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("panic");
+        println!("[{:>9.3} s] {}", clock::now() as f64 / 1e6, message);
+    }));
+
+    let mut flash = MemoryStorage::default();
+    settings::check(&mut flash).unwrap();
+    settings::IS_LATHE.write(&mut flash, is_lathe).unwrap();
+
+    let machine = Machine::new(input, flash, !is_lathe);
+    let mut display = ::lcd::Display::new(Screen(machine.clone()));
+    init_display(&mut display);
+
+    let mut r = MenuResources::<SimBoard> {
+        encoder: &mut Encoder(machine.clone()),
+        display: &mut display,
+        controls: &mut Controls::new(machine.clone()),
+        estop: &mut EStop(machine.clone()),
+        shared: SharedResources {
+            stepper: StepperLock(machine.clone()),
+            cross: CrossLock(machine.clone()),
+            hall: HallLock(machine.clone()),
+            flash: FlashLock(machine.clone()),
+            program: ProgramLock(machine),
+        },
+        session: None,
+    };
+
+    startup(&mut r);
+    if is_lathe {
+        let mut menu = LatheMenu::new();
+        loop {
+            menu.run(&mut r);
+        }
+    } else {
+        let mut menu = MillMenu::new();
+        loop {
+            menu.run(&mut r);
+        }
+    }
+}